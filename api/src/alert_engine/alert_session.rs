@@ -1,4 +1,5 @@
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
 use crate::covin::centers::{Center, Session};
 
@@ -6,11 +7,52 @@ use crate::covin::centers::{Center, Session};
 pub struct AlertSession<'a, 'b> {
     pub session: &'a Session,
     pub center: &'b Center,
+    /// Distance in kilometers from the alert location to the center, for radius based alerts.
+    pub distance_km: Option<f64>,
 }
 
 impl<'a, 'b> From<(&'a Session, &'b Center)> for AlertSession<'a, 'b> {
     fn from((session, center): (&'a Session, &'b Center)) -> Self {
-        Self { session, center }
+        Self {
+            session,
+            center,
+            distance_km: None,
+        }
+    }
+}
+
+/// Session of an alert held back to be sent later on, kept along with its center since
+/// CoWIN may no longer list the session by then.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeldSession {
+    pub session: Session,
+    /// Center of the session, without its sessions.
+    pub center: Center,
+    #[serde(default)]
+    pub distance_km: Option<f64>,
+    /// Unix timestamp of when the session was last held.
+    pub held_at: i64,
+}
+
+impl HeldSession {
+    pub fn new(alert_session: &AlertSession, held_at: DateTime<Utc>) -> Self {
+        let mut center = alert_session.center.clone();
+        center.sessions.clear();
+        Self {
+            session: alert_session.session.clone(),
+            center,
+            distance_km: alert_session.distance_km,
+            held_at: held_at.timestamp(),
+        }
+    }
+
+    pub fn alert_session(&self) -> AlertSession<'_, '_> {
+        AlertSession {
+            session: &self.session,
+            center: &self.center,
+            distance_km: self.distance_km,
+        }
     }
 }
 
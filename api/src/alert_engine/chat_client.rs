@@ -0,0 +1,320 @@
+use std::{env, error::Error as _, fmt::Write, time::Duration};
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::alert_session::AlertSession;
+
+static CONFIG: Lazy<ChatConfig> = Lazy::new(ChatConfig::init);
+
+/// Sessions beyond this are summarised, to keep the message within the Telegram limit of 4096 chars.
+const MAX_SESSIONS_PER_MESSAGE: usize = 20;
+
+const CHAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum ChatError {
+    /// Failure of the request, with the bot token in its URL redacted.
+    #[error("Request failed: {0}")]
+    RequestFail(String),
+    #[error("Telegram Bot API error: {0}")]
+    BotApiError(String),
+}
+
+#[async_trait]
+pub trait ChatClient {
+    type Error: std::error::Error + Sync + Send + 'static;
+
+    async fn send_alert_message(&mut self, chat_id: &str, text: &str) -> Result<(), Self::Error>;
+}
+
+/// Escapes the reserved characters of Telegram MarkdownV2 in a plain `text`.
+pub fn escape_markdown(text: &str) -> String {
+    const RESERVED: &[char] = &[
+        '_', '*', '[', ']', '(', ')', '~', '`', '>', '#', '+', '-', '=', '|', '{', '}', '.', '!',
+        '\\',
+    ];
+    text.chars().fold(String::new(), |mut escaped, c| {
+        if RESERVED.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+/// Renders the `sessions_to_alert` under the `title` as a Telegram MarkdownV2 message.
+pub fn render_alert_markdown(title: &str, sessions_to_alert: &[AlertSession]) -> String {
+    let mut message = format!("*{}*\n", escape_markdown(title));
+    for alert_session in sessions_to_alert.iter().take(MAX_SESSIONS_PER_MESSAGE) {
+        let AlertSession {
+            center, session, ..
+        } = alert_session;
+        let _ = write!(
+            message,
+            "\n*{}*, {}, {}\n{} · {} · {}\\+ · dose 1: {} · dose 2: {}\n",
+            escape_markdown(&center.name),
+            escape_markdown(&center.district_name),
+            center.pincode,
+            escape_markdown(&session.date),
+            escape_markdown(&session.vaccine),
+            session.min_age_limit,
+            session.available_capacity_dose1,
+            session.available_capacity_dose2,
+        );
+    }
+    if sessions_to_alert.len() > MAX_SESSIONS_PER_MESSAGE {
+        let _ = write!(
+            message,
+            "\n_and {} more sessions_\n",
+            sessions_to_alert.len() - MAX_SESSIONS_PER_MESSAGE
+        );
+    }
+    message
+}
+
+#[derive(Debug, Serialize)]
+struct SendMessageRequest<'a> {
+    chat_id: &'a str,
+    text: &'a str,
+    parse_mode: &'static str,
+    disable_web_page_preview: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct BotApiResponse {
+    ok: bool,
+    description: Option<String>,
+}
+
+/// Sends the alerts to a Telegram chat through the Bot API.
+pub struct TelegramChatClient {
+    client: reqwest::Client,
+    base_url: String,
+    bot_token: String,
+}
+
+impl TelegramChatClient {
+    pub fn new() -> Self {
+        Self::with_base_url(&CONFIG.base_url, &CONFIG.bot_token)
+    }
+
+    pub fn with_base_url(base_url: &str, bot_token: &str) -> Self {
+        let client = reqwest::ClientBuilder::new()
+            .timeout(CHAT_TIMEOUT)
+            .build()
+            .expect("Unable to build the Telegram client");
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            bot_token: bot_token.to_string(),
+        }
+    }
+}
+
+impl TelegramChatClient {
+    /// The URL of the Bot API holds the bot token, which errors mustn't pass on to the
+    /// run report and the logs.
+    fn request_fail(&self, err: &reqwest::Error) -> ChatError {
+        let mut message = err.to_string();
+        let mut source = err.source();
+        while let Some(err) = source {
+            let _ = write!(message, ": {}", err);
+            source = err.source();
+        }
+        if !self.bot_token.is_empty() {
+            message = message.replace(&self.bot_token, "<redacted>");
+        }
+        ChatError::RequestFail(message)
+    }
+}
+
+impl Default for TelegramChatClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ChatClient for TelegramChatClient {
+    type Error = ChatError;
+
+    #[tracing::instrument(level = "debug", skip(self, text))]
+    async fn send_alert_message(&mut self, chat_id: &str, text: &str) -> Result<(), Self::Error> {
+        let resp = self
+            .client
+            .post(format!(
+                "{}/bot{}/sendMessage",
+                self.base_url, self.bot_token
+            ))
+            .json(&SendMessageRequest {
+                chat_id,
+                text,
+                parse_mode: "MarkdownV2",
+                disable_web_page_preview: true,
+            })
+            .send()
+            .await
+            .map_err(|err| self.request_fail(&err))?
+            .json::<BotApiResponse>()
+            .await
+            .map_err(|err| self.request_fail(&err))?;
+        if resp.ok {
+            Ok(())
+        } else {
+            Err(ChatError::BotApiError(resp.description.unwrap_or_default()))
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ChatConfig {
+    base_url: String,
+    bot_token: String,
+}
+
+impl ChatConfig {
+    fn init() -> Self {
+        let base_url = env::var("TELEGRAM_API_BASE_URL")
+            .unwrap_or_else(|_| "https://api.telegram.org".to_string());
+        let bot_token = env::var("TELEGRAM_BOT_TOKEN").unwrap_or_default();
+        Self {
+            base_url,
+            bot_token,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use serde_json::{json, Value};
+    use warp::Filter;
+
+    use super::{
+        escape_markdown, render_alert_markdown, ChatClient, ChatError, TelegramChatClient,
+    };
+    use crate::{
+        alert_engine::alert_session::AlertSession,
+        covin::centers::{Center, Session},
+    };
+
+    type Requests = Arc<Mutex<Vec<(String, Value)>>>;
+
+    /// Stand-in Telegram Bot API, which rejects messages to the chat id `0`.
+    fn serve() -> (SocketAddr, Requests) {
+        let requests = Requests::default();
+        let route = {
+            let requests = requests.clone();
+            warp::post()
+                .and(warp::path!(String / "sendMessage"))
+                .and(warp::body::json())
+                .map(move |bot: String, body: Value| {
+                    let ok = body["chat_id"] != "0";
+                    requests.lock().unwrap().push((bot, body));
+                    if ok {
+                        warp::reply::json(&json!({ "ok": true, "result": {} }))
+                    } else {
+                        warp::reply::json(
+                            &json!({ "ok": false, "description": "Bad Request: chat not found" }),
+                        )
+                    }
+                })
+        };
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (addr, requests)
+    }
+
+    #[test]
+    fn test_escape_markdown() {
+        assert_eq!(
+            escape_markdown("PHC Kochi (Block-1). 18+"),
+            "PHC Kochi \\(Block\\-1\\)\\. 18\\+"
+        );
+    }
+
+    #[test]
+    fn test_render_alert_markdown() {
+        let center = Center {
+            name: "PHC Kochi (Block-1)".to_string(),
+            district_name: "Ernakulam".to_string(),
+            pincode: 682001,
+            ..Default::default()
+        };
+        let session = Session {
+            date: "14-05-2021".to_string(),
+            vaccine: "COVISHIELD".to_string(),
+            min_age_limit: 18,
+            available_capacity_dose1: 10_f32,
+            available_capacity_dose2: 2_f32,
+            ..Default::default()
+        };
+        let message = render_alert_markdown(
+            "Vaccine slots available",
+            &[AlertSession::from((&session, &center))],
+        );
+        assert_eq!(
+            message,
+            "*Vaccine slots available*\n\n*PHC Kochi \\(Block\\-1\\)*, Ernakulam, 682001\n14\\-05\\-2021 · COVISHIELD · 18\\+ · dose 1: 10 · dose 2: 2\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_telegram_send_message() {
+        let (addr, requests) = serve();
+        let mut chat_client =
+            TelegramChatClient::with_base_url(&format!("http://{}/", addr), "dummy-token");
+        chat_client
+            .send_alert_message("12345", "*Vaccine slots available*")
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let (bot, body) = &requests[0];
+        assert_eq!(bot, "botdummy-token");
+        assert_eq!(body["chat_id"], "12345");
+        assert_eq!(body["text"], "*Vaccine slots available*");
+        assert_eq!(body["parse_mode"], "MarkdownV2");
+    }
+
+    #[tokio::test]
+    async fn test_telegram_send_message_error() {
+        let (addr, _requests) = serve();
+        let mut chat_client =
+            TelegramChatClient::with_base_url(&format!("http://{}", addr), "dummy-token");
+        let res = chat_client.send_alert_message("0", "hello").await;
+        assert!(
+            matches!(res, Err(ChatError::BotApiError(description)) if description == "Bad Request: chat not found")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_telegram_request_fail_redacts_token() {
+        // Nothing listens on the port once the listener is dropped
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut chat_client =
+            TelegramChatClient::with_base_url(&format!("http://{}", addr), "dummy-token");
+        let err = chat_client
+            .send_alert_message("12345", "hello")
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", anyhow::Error::from(err));
+        assert!(
+            message.contains("/bot<redacted>/sendMessage"),
+            "{}",
+            message
+        );
+        assert!(!message.contains("dummy-token"));
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
+
+const HOUR: i32 = 3600;
+
+/// Indian Standard Time, which CoWIN uses for the session dates.
+pub fn ist_offset() -> FixedOffset {
+    FixedOffset::east(5 * HOUR + HOUR / 2)
+}
+
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Current date in IST.
+    fn today(&self) -> NaiveDate {
+        self.now().with_timezone(&ist_offset()).date().naive_local()
+    }
+}
+
+/// Clock backed by the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock pinned to a given time, which moves only when it is set or advanced.
+#[derive(Debug)]
+pub struct FixedClock(Mutex<DateTime<Utc>>);
+
+impl FixedClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self(Mutex::new(now))
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() = self.now() + duration;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, NaiveDate, TimeZone, Utc};
+
+    use super::{Clock, FixedClock, SystemClock};
+
+    #[test]
+    fn test_today_ist_midnight_rollover() {
+        // 23:59:59 IST on 13-05-2021
+        let clock = FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(18, 29, 59));
+        assert_eq!(clock.today(), NaiveDate::from_ymd(2021, 5, 13));
+
+        // 00:00:00 IST on 14-05-2021, while it is still 13-05-2021 in UTC
+        clock.advance(Duration::seconds(1));
+        assert_eq!(clock.now(), Utc.ymd(2021, 5, 13).and_hms(18, 30, 0));
+        assert_eq!(clock.today(), NaiveDate::from_ymd(2021, 5, 14));
+
+        // 05:29:59 IST on 14-05-2021, UTC is yet to roll over to 14-05-2021
+        clock.set(Utc.ymd(2021, 5, 13).and_hms(23, 59, 59));
+        assert_eq!(clock.today(), NaiveDate::from_ymd(2021, 5, 14));
+    }
+
+    #[test]
+    fn test_system_clock() {
+        let before = Utc::now();
+        let now = SystemClock.now();
+        assert!(before <= now && now <= Utc::now());
+    }
+}
@@ -0,0 +1,1098 @@
+use std::{collections::HashMap, convert::TryFrom, env};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use dynomite::{
+    attr_map,
+    dynamodb::{
+        BatchGetItemError, BatchGetItemInput, DeleteItemError, DeleteItemInput, DynamoDb,
+        DynamoDbClient, GetItemError, GetItemInput, KeysAndAttributes, PutItemError, PutItemInput,
+        QueryError, QueryInput, UpdateItemError, UpdateItemInput,
+    },
+    retry::{Policy, RetryingDynamoDb},
+    AttributeError, Attributes, Item, Retries,
+};
+use once_cell::sync::Lazy;
+use rusoto_core::RusotoError;
+use rusoto_s3::S3Client;
+use thiserror::Error;
+
+use super::{
+    alert_session::{AlertSession, HeldSession},
+    clock::ist_offset,
+    exclusion_map::{sessions_to_hold, ExclusionMap, S3ExclusionMap, StoredExclusionMap},
+    renotify::LastNotified,
+    DATE_FORMAT,
+};
+
+static CONFIG: Lazy<DynamoExclusionMapConfig> = Lazy::new(DynamoExclusionMapConfig::init);
+
+/// Maximum number of keys DynamoDB allows in a single `BatchGetItem` request.
+const BATCH_GET_MAX_KEYS: usize = 100;
+const BATCH_GET_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_TABLE_NAME: &str = "CovinExclusions";
+const DEFAULT_TTL_HOURS: i64 = 48;
+/// Sort key of the item logging the notifications of an alert, next to its sessions.
+const NOTIFICATION_LOG_SESSION_ID: &str = "#notifications";
+/// Notifications are logged for the longest window of the rate limits of an alert.
+const NOTIFICATION_LOG_RETENTION_HOURS: i64 = 24;
+/// Attempts at writing a notification log changed by other engines in the meantime.
+const NOTIFICATION_LOG_MAX_ATTEMPTS: u32 = 5;
+/// Prefix of the sort keys of the sessions held for an alert, followed by the session id.
+const HELD_SESSION_ID_PREFIX: &str = "#held#";
+
+#[derive(Debug, Error)]
+pub enum DynamoExclusionMapError {
+    #[error("Unable to read the exclusions")]
+    BatchGetFail(#[from] RusotoError<BatchGetItemError>),
+    #[error("Unable to read all the exclusions, unprocessed keys are left")]
+    UnprocessedKeys,
+    #[error("Unable to reserve the session")]
+    PutFail(#[from] RusotoError<PutItemError>),
+    #[error("Unable to release the session")]
+    DeleteFail(#[from] RusotoError<DeleteItemError>),
+    #[error("Unable to mark the session depleted")]
+    UpdateFail(#[from] RusotoError<UpdateItemError>),
+    #[error("Unable to read the notification log")]
+    GetFail(#[from] RusotoError<GetItemError>),
+    #[error("Unable to log the notifications, the log kept changing")]
+    LogConflict,
+    #[error("Unable to read the held sessions")]
+    QueryFail(#[from] RusotoError<QueryError>),
+    #[error("Invalid held session")]
+    HeldSessionFail(#[from] serde_json::Error),
+    #[error("Invalid exclusion item")]
+    AttributeFail(#[from] AttributeError),
+}
+
+/// Session alerted for an alert, keyed by `alert_key` (partition key) and `session_id`
+/// (sort key). The table has DynamoDB TTL enabled on `expires_at`.
+#[derive(Item, Debug, Clone, PartialEq)]
+pub struct ExclusionItem {
+    #[dynomite(partition_key)]
+    pub alert_key: String,
+    #[dynomite(sort_key)]
+    pub session_id: String,
+    pub capacity: f32,
+    /// Date of the session as given by CoWIN, in `%d-%m-%Y`.
+    pub session_date: String,
+    /// Unix timestamp of the latest notification of the session.
+    pub notified_at: i64,
+    /// Unix timestamp after which DynamoDB deletes the item.
+    pub expires_at: i64,
+    /// Number of notifications of the session, items written before it was counted have 0.
+    #[dynomite(default)]
+    pub notify_count: u32,
+    /// Whether the session was seen without capacity since the latest notification.
+    #[dynomite(default)]
+    pub depleted: bool,
+}
+
+/// Notifications sent for an alert in the last day, stored along with the sessions of
+/// the alert under the `NOTIFICATION_LOG_SESSION_ID` sort key.
+#[derive(Item, Debug, Clone, PartialEq)]
+pub struct NotificationLogItem {
+    #[dynomite(partition_key)]
+    pub alert_key: String,
+    #[dynomite(sort_key)]
+    pub session_id: String,
+    /// Unix timestamps of the notifications.
+    pub notified_at: Vec<i64>,
+    pub expires_at: i64,
+    /// Bumped on every write, so that a log written by another engine since it was read
+    /// isn't overwritten. Logs written before it was kept have 0.
+    #[dynomite(default)]
+    pub version: u64,
+}
+
+impl NotificationLogItem {
+    /// Log with the notifications `added` to this `log`, dropping the ones older than a day.
+    fn merge(alert_key: &str, log: Option<&NotificationLogItem>, added: &[i64]) -> Self {
+        let retention = Duration::hours(NOTIFICATION_LOG_RETENTION_HOURS).num_seconds();
+        let mut notified_at = log
+            .iter()
+            .flat_map(|log| log.notified_at.iter())
+            .chain(added)
+            .copied()
+            .collect::<Vec<_>>();
+        let latest = notified_at.iter().copied().max().unwrap_or_default();
+        notified_at.retain(|notified_at| *notified_at > latest - retention);
+        notified_at.sort_unstable();
+        notified_at.dedup();
+        Self {
+            alert_key: alert_key.to_string(),
+            session_id: NOTIFICATION_LOG_SESSION_ID.to_string(),
+            notified_at,
+            expires_at: latest + retention,
+            version: log.map(|log| log.version + 1).unwrap_or(1),
+        }
+    }
+}
+
+/// Session held for an alert, stored along with the sessions of the alert under the
+/// `HELD_SESSION_ID_PREFIX` sort key of the session.
+#[derive(Item, Debug, Clone, PartialEq)]
+pub struct HeldSessionItem {
+    #[dynomite(partition_key)]
+    pub alert_key: String,
+    #[dynomite(sort_key)]
+    pub session_id: String,
+    /// Held session along with its center, as JSON.
+    pub held: String,
+    /// Unix timestamp of when the session was last held.
+    pub held_at: i64,
+    pub expires_at: i64,
+}
+
+/// Items expire once the session date is over in IST, or after the `ttl`, whichever is earlier.
+pub fn expires_at(session_date: &str, notified_at: DateTime<Utc>, ttl: Duration) -> i64 {
+    let ttl_expires_at = (notified_at + ttl).timestamp();
+    NaiveDate::parse_from_str(session_date, DATE_FORMAT)
+        .ok()
+        .and_then(|session_date| {
+            ist_offset()
+                .from_local_datetime(&session_date.succ().and_hms(0, 0, 0))
+                .single()
+        })
+        .map(|session_over_at| session_over_at.timestamp().min(ttl_expires_at))
+        .unwrap_or(ttl_expires_at)
+}
+
+/// Exclusion map with an item per session alerted for an alert, read only for the sessions
+/// matched in a run. Sessions are reserved with conditional writes before they are alerted,
+/// so engines running at the same time don't alert the same session twice.
+pub struct DynamoExclusionMap {
+    dynamodb_client: RetryingDynamoDb<DynamoDbClient>,
+    table_name: String,
+    ttl: Duration,
+    /// Items loaded or reserved in this run, keyed by `(alert_key, session_id)`.
+    items: HashMap<(String, String), ExclusionItem>,
+    /// Items as loaded before they were reserved again in this run, restored on release.
+    replaced: HashMap<(String, String), ExclusionItem>,
+    /// Notifications of the alerts loaded, keyed by `alert_key`.
+    notifications: HashMap<String, Vec<i64>>,
+    /// Notification logs as loaded, keyed by `alert_key`.
+    logs: HashMap<String, NotificationLogItem>,
+    /// Notifications sent in this run, which are to be added to the logs of the alerts.
+    notified: HashMap<String, Vec<i64>>,
+    /// Sessions held for the alerts loaded, keyed by `alert_key`.
+    held: HashMap<String, Vec<HeldSession>>,
+    /// Maps stored in S3 before the exclusions moved to the table, their entries are
+    /// imported as the sessions are loaded.
+    fallback: Option<StoredExclusionMap>,
+}
+
+impl DynamoExclusionMap {
+    pub fn new() -> Self {
+        Self::with_client(
+            DynamoDbClient::new(Default::default()),
+            &CONFIG.table_name,
+            CONFIG.ttl,
+        )
+    }
+
+    pub fn with_client(dynamodb_client: DynamoDbClient, table_name: &str, ttl: Duration) -> Self {
+        let retry_policy = Policy::Pause(3, std::time::Duration::from_millis(10));
+        Self {
+            dynamodb_client: dynamodb_client.with_retries(retry_policy),
+            table_name: table_name.to_string(),
+            ttl,
+            items: HashMap::new(),
+            replaced: HashMap::new(),
+            notifications: HashMap::new(),
+            logs: HashMap::new(),
+            notified: HashMap::new(),
+            held: HashMap::new(),
+            fallback: None,
+        }
+    }
+
+    /// Imports the entries of the `fallback` map missing from the table as they are loaded.
+    pub fn with_fallback(self, fallback: StoredExclusionMap) -> Self {
+        Self {
+            fallback: Some(fallback),
+            ..self
+        }
+    }
+
+    /// Falls back on the maps stored in S3 by the engines before the exclusions moved to
+    /// the table, so that the sessions they alerted aren't alerted again. Only the maps
+    /// stored within the TTL can have entries left, so none is loaded once it has passed.
+    pub async fn with_s3_fallback(self) -> Self {
+        let s3_client = S3Client::new(rusoto_core::Region::ApSouth1);
+        let modified_since = Utc::now() - self.ttl;
+        let fallback =
+            S3ExclusionMap::load_exclusion_maps(&s3_client, None, Some(modified_since)).await;
+        self.with_fallback(fallback)
+    }
+
+    /// Writes the entries of the fallback map for the `keys` missing from the table, unless
+    /// they have expired or another run wrote them since.
+    async fn import_fallback(
+        &mut self,
+        keys: &[(String, String)],
+        now: DateTime<Utc>,
+    ) -> Result<(), DynamoExclusionMapError> {
+        let fallback = match self.fallback.take() {
+            Some(fallback) => fallback,
+            None => return Ok(()),
+        };
+        for (alert_key, session_id) in keys {
+            let item_key = (alert_key.clone(), session_id.clone());
+            if self.items.contains_key(&item_key) {
+                continue;
+            }
+            let entry =
+                match fallback.alerts.get(alert_key).and_then(|entries| {
+                    entries.iter().find(|entry| &entry.session_id == session_id)
+                }) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+            let item = ExclusionItem {
+                alert_key: alert_key.clone(),
+                session_id: session_id.clone(),
+                capacity: entry.capacity,
+                session_date: entry.session_date.clone(),
+                notified_at: entry.notified_at,
+                expires_at: expires_at(
+                    &entry.session_date,
+                    Utc.timestamp(entry.notified_at, 0),
+                    self.ttl,
+                ),
+                notify_count: entry.notify_count,
+                depleted: entry.depleted,
+            };
+            if item.expires_at <= now.timestamp() {
+                continue;
+            }
+            let res = self
+                .dynamodb_client
+                .put_item(PutItemInput {
+                    table_name: self.table_name.clone(),
+                    item: item.clone().into(),
+                    condition_expression: Some("attribute_not_exists(session_id)".to_string()),
+                    ..Default::default()
+                })
+                .await;
+            match res {
+                Ok(_) => {
+                    self.items.insert(item_key, item);
+                }
+                // Reserved by another run since, which the reservation of this run will see
+                Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {}
+                Err(err) => {
+                    self.fallback = Some(fallback);
+                    return Err(err.into());
+                }
+            }
+        }
+        // Notifications of the alerts without a log yet count towards their rate limits
+        for (alert_key, _) in keys {
+            if self.logs.contains_key(alert_key) || self.notified.contains_key(alert_key) {
+                continue;
+            }
+            let notified_at = fallback
+                .notifications
+                .get(alert_key)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|notified_at| {
+                    *notified_at
+                        > (now - Duration::hours(NOTIFICATION_LOG_RETENTION_HOURS)).timestamp()
+                })
+                .collect::<Vec<_>>();
+            if !notified_at.is_empty() {
+                self.notifications
+                    .entry(alert_key.clone())
+                    .or_default()
+                    .extend(&notified_at);
+                self.notified.insert(alert_key.clone(), notified_at);
+            }
+        }
+        self.fallback = Some(fallback);
+        Ok(())
+    }
+
+    fn key(alert_key: &str, session_id: &str) -> Attributes {
+        attr_map! {
+            "alert_key" => alert_key.to_string(),
+            "session_id" => session_id.to_string()
+        }
+    }
+
+    async fn get_log(
+        &self,
+        alert_key: &str,
+    ) -> Result<Option<NotificationLogItem>, DynamoExclusionMapError> {
+        let resp = self
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: self.table_name.clone(),
+                key: Self::key(alert_key, NOTIFICATION_LOG_SESSION_ID),
+                consistent_read: Some(true),
+                ..Default::default()
+            })
+            .await?;
+        Ok(resp.item.map(NotificationLogItem::try_from).transpose()?)
+    }
+
+    /// Adds the notifications sent in this run to the log of the alert. The log is written
+    /// only if it is still as it was read, and is read again to add to otherwise.
+    async fn store_log(
+        &self,
+        alert_key: &str,
+        added: &[i64],
+    ) -> Result<(), DynamoExclusionMapError> {
+        let mut log = self.logs.get(alert_key).cloned();
+        for _ in 0..NOTIFICATION_LOG_MAX_ATTEMPTS {
+            let (condition_expression, expression_attribute_values) = match &log {
+                Some(log) if log.version > 0 => (
+                    "version = :version",
+                    Some(attr_map! { ":version" => log.version }),
+                ),
+                Some(_) => ("attribute_not_exists(version)", None),
+                None => ("attribute_not_exists(session_id)", None),
+            };
+            let res = self
+                .dynamodb_client
+                .put_item(PutItemInput {
+                    table_name: self.table_name.clone(),
+                    item: NotificationLogItem::merge(alert_key, log.as_ref(), added).into(),
+                    condition_expression: Some(condition_expression.to_string()),
+                    expression_attribute_values,
+                    ..Default::default()
+                })
+                .await;
+            match res {
+                Ok(_) => return Ok(()),
+                Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {
+                    tracing::debug!(message = "Notification log changed by another run", %alert_key);
+                    log = self.get_log(alert_key).await?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Err(DynamoExclusionMapError::LogConflict)
+    }
+
+    /// Sessions held for the alert which haven't expired by `now`.
+    async fn query_held(
+        &self,
+        alert_key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<HeldSession>, DynamoExclusionMapError> {
+        let mut held = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            let resp = self
+                .dynamodb_client
+                .query(QueryInput {
+                    table_name: self.table_name.clone(),
+                    key_condition_expression: Some(
+                        "alert_key = :alert_key AND begins_with(session_id, :prefix)".to_string(),
+                    ),
+                    expression_attribute_values: Some(attr_map! {
+                        ":alert_key" => alert_key.to_string(),
+                        ":prefix" => HELD_SESSION_ID_PREFIX.to_string()
+                    }),
+                    consistent_read: Some(true),
+                    exclusive_start_key,
+                    ..Default::default()
+                })
+                .await?;
+            for attrs in resp.items.unwrap_or_default() {
+                let item = HeldSessionItem::try_from(attrs)?;
+                // DynamoDB deletes the expired items only eventually
+                if item.expires_at > now.timestamp() {
+                    held.push(serde_json::from_str(&item.held)?);
+                }
+            }
+            exclusive_start_key = resp.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                return Ok(held);
+            }
+        }
+    }
+}
+
+impl Default for DynamoExclusionMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ExclusionMap for DynamoExclusionMap {
+    type Error = DynamoExclusionMapError;
+
+    fn last_notified(&self, alert_key: &str, session_id: &str) -> Option<LastNotified> {
+        self.items
+            .get(&(alert_key.to_string(), session_id.to_string()))
+            .map(|item| LastNotified {
+                capacity: item.capacity,
+                notified_at: Utc.timestamp(item.notified_at, 0),
+                count: item.notify_count.max(1),
+                depleted: item.depleted,
+            })
+    }
+
+    /// Sessions are written to the table when they are reserved, so this only keeps the
+    /// items in sync for the rest of the run.
+    fn add(&mut self, alert_key: &str, sessions: &[AlertSession], notified_at: DateTime<Utc>) {
+        if sessions.is_empty() {
+            return;
+        }
+        self.notifications
+            .entry(alert_key.to_string())
+            .or_default()
+            .push(notified_at.timestamp());
+        self.notified
+            .entry(alert_key.to_string())
+            .or_default()
+            .push(notified_at.timestamp());
+        for AlertSession { session, .. } in sessions {
+            let item_key = (alert_key.to_string(), session.session_id.clone());
+            let notify_count = match self.items.get(&item_key) {
+                // Already counted when it was reserved
+                Some(item) if item.notified_at == notified_at.timestamp() => item.notify_count,
+                Some(item) => item.notify_count.max(1) + 1,
+                None => 1,
+            };
+            self.items.insert(
+                item_key,
+                ExclusionItem {
+                    alert_key: alert_key.to_string(),
+                    session_id: session.session_id.clone(),
+                    capacity: session.available_capacity,
+                    session_date: session.date.clone(),
+                    notified_at: notified_at.timestamp(),
+                    expires_at: expires_at(&session.date, notified_at, self.ttl),
+                    notify_count,
+                    depleted: false,
+                },
+            );
+        }
+    }
+
+    /// Sessions are already written as they are reserved, only the notifications sent in
+    /// this run are left to add to the logs of the alerts.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn store(&self) -> Result<(), Self::Error> {
+        for (alert_key, added) in self.notified.iter() {
+            self.store_log(alert_key, added).await?;
+        }
+        Ok(())
+    }
+
+    fn notifications_since(&self, alert_key: &str, since: DateTime<Utc>) -> usize {
+        self.notifications
+            .get(alert_key)
+            .map(|notified_at| {
+                notified_at
+                    .iter()
+                    .filter(|notified_at| **notified_at > since.timestamp())
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, keys))]
+    async fn load(
+        &mut self,
+        keys: &[(String, String)],
+        now: DateTime<Utc>,
+    ) -> Result<(), Self::Error> {
+        // Notification logs of the alerts are read along with their sessions
+        let mut keys = keys
+            .iter()
+            .map(|(alert_key, _)| (alert_key.clone(), NOTIFICATION_LOG_SESSION_ID.to_string()))
+            .chain(keys.iter().cloned())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        for chunk in keys.chunks(BATCH_GET_MAX_KEYS) {
+            let mut request_keys = chunk
+                .iter()
+                .map(|(alert_key, session_id)| Self::key(alert_key, session_id))
+                .collect::<Vec<_>>();
+            let mut attempt = 0;
+            while !request_keys.is_empty() {
+                if attempt == BATCH_GET_MAX_ATTEMPTS {
+                    return Err(DynamoExclusionMapError::UnprocessedKeys);
+                }
+                let mut request_items = HashMap::new();
+                request_items.insert(
+                    self.table_name.clone(),
+                    KeysAndAttributes {
+                        keys: request_keys,
+                        consistent_read: Some(true),
+                        ..Default::default()
+                    },
+                );
+                let resp = self
+                    .dynamodb_client
+                    .batch_get_item(BatchGetItemInput {
+                        request_items,
+                        ..Default::default()
+                    })
+                    .await?;
+                for attrs in resp
+                    .responses
+                    .and_then(|mut responses| responses.remove(&self.table_name))
+                    .unwrap_or_default()
+                {
+                    let is_log = attrs
+                        .get("session_id")
+                        .and_then(|session_id| session_id.s.as_deref())
+                        == Some(NOTIFICATION_LOG_SESSION_ID);
+                    if is_log {
+                        let log = NotificationLogItem::try_from(attrs)?;
+                        self.notifications
+                            .insert(log.alert_key.clone(), log.notified_at.clone());
+                        self.logs.insert(log.alert_key.clone(), log);
+                        continue;
+                    }
+                    let item = ExclusionItem::try_from(attrs)?;
+                    // DynamoDB deletes the expired items only eventually
+                    if item.expires_at > now.timestamp() {
+                        self.items
+                            .insert((item.alert_key.clone(), item.session_id.clone()), item);
+                    }
+                }
+                request_keys = resp
+                    .unprocessed_keys
+                    .and_then(|mut unprocessed_keys| unprocessed_keys.remove(&self.table_name))
+                    .map(|unprocessed| unprocessed.keys)
+                    .unwrap_or_default();
+                if !request_keys.is_empty() {
+                    tokio::time::sleep(std::time::Duration::from_millis(50 * 2_u64.pow(attempt)))
+                        .await;
+                }
+                attempt += 1;
+            }
+        }
+        self.import_fallback(&keys, now).await
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, sessions))]
+    async fn reserve<'s>(
+        &mut self,
+        alert_key: &str,
+        sessions: Vec<AlertSession<'s, 's>>,
+        notified_at: DateTime<Utc>,
+    ) -> Result<Vec<AlertSession<'s, 's>>, Self::Error> {
+        let mut reserved = vec![];
+        for alert_session in sessions {
+            let session = alert_session.session;
+            let item_key = (alert_key.to_string(), session.session_id.clone());
+            let loaded = self.items.get(&item_key).cloned();
+            let item = ExclusionItem {
+                alert_key: alert_key.to_string(),
+                session_id: session.session_id.clone(),
+                capacity: session.available_capacity,
+                session_date: session.date.clone(),
+                notified_at: notified_at.timestamp(),
+                expires_at: expires_at(&session.date, notified_at, self.ttl),
+                notify_count: loaded
+                    .as_ref()
+                    .map(|loaded| loaded.notify_count.max(1) + 1)
+                    .unwrap_or(1),
+                depleted: false,
+            };
+            // The item must be as it was loaded, otherwise another engine has reserved it since
+            let (condition_expression, expression_attribute_values) = match &loaded {
+                Some(loaded) => (
+                    "notified_at = :loaded_notified_at",
+                    attr_map! { ":loaded_notified_at" => loaded.notified_at },
+                ),
+                None => (
+                    "attribute_not_exists(session_id) OR expires_at <= :now",
+                    attr_map! { ":now" => notified_at.timestamp() },
+                ),
+            };
+            let res = self
+                .dynamodb_client
+                .put_item(PutItemInput {
+                    table_name: self.table_name.clone(),
+                    item: item.clone().into(),
+                    condition_expression: Some(condition_expression.to_string()),
+                    expression_attribute_values: Some(expression_attribute_values),
+                    ..Default::default()
+                })
+                .await;
+            match res {
+                Ok(_) => {
+                    if let Some(loaded) = loaded {
+                        self.replaced.insert(item_key.clone(), loaded);
+                    }
+                    self.items.insert(item_key, item);
+                    reserved.push(alert_session);
+                }
+                Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {
+                    tracing::debug!(message = "Session reserved by another run", %alert_key, session_id = %session.session_id);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(reserved)
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, sessions))]
+    async fn release(
+        &mut self,
+        alert_key: &str,
+        sessions: &[AlertSession],
+    ) -> Result<(), Self::Error> {
+        for AlertSession { session, .. } in sessions {
+            let item_key = (alert_key.to_string(), session.session_id.clone());
+            let item = match self.items.remove(&item_key) {
+                Some(item) => item,
+                None => continue,
+            };
+            // Only the reservation made by this run is released, restoring the item it replaced
+            let condition_expression = Some("notified_at = :notified_at".to_string());
+            let expression_attribute_values = Some(attr_map! {
+                ":notified_at" => item.notified_at
+            });
+            match self.replaced.remove(&item_key) {
+                Some(replaced) => {
+                    let res = self
+                        .dynamodb_client
+                        .put_item(PutItemInput {
+                            table_name: self.table_name.clone(),
+                            item: replaced.clone().into(),
+                            condition_expression,
+                            expression_attribute_values,
+                            ..Default::default()
+                        })
+                        .await;
+                    match res {
+                        Ok(_) => {
+                            self.items.insert(item_key, replaced);
+                        }
+                        Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {}
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+                None => {
+                    let res = self
+                        .dynamodb_client
+                        .delete_item(DeleteItemInput {
+                            table_name: self.table_name.clone(),
+                            key: Self::key(alert_key, &session.session_id),
+                            condition_expression,
+                            expression_attribute_values,
+                            ..Default::default()
+                        })
+                        .await;
+                    match res {
+                        Ok(_)
+                        | Err(RusotoError::Service(DeleteItemError::ConditionalCheckFailed(_))) => {
+                        }
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every session is held in an item of its own, so holding a session never overwrites
+    /// another one held by an engine running at the same time.
+    #[tracing::instrument(level = "debug", skip(self, sessions))]
+    async fn hold(
+        &mut self,
+        alert_key: &str,
+        sessions: &[AlertSession],
+        held_at: DateTime<Utc>,
+    ) -> Result<(), Self::Error> {
+        let held = self.held.get(alert_key).map(Vec::as_slice).unwrap_or(&[]);
+        for held_session in sessions_to_hold(held, sessions, held_at) {
+            let session = &held_session.session;
+            let item = HeldSessionItem {
+                alert_key: alert_key.to_string(),
+                session_id: format!("{}{}", HELD_SESSION_ID_PREFIX, session.session_id),
+                held: serde_json::to_string(&held_session)?,
+                held_at: held_session.held_at,
+                expires_at: expires_at(&session.date, held_at, self.ttl),
+            };
+            self.dynamodb_client
+                .put_item(PutItemInput {
+                    table_name: self.table_name.clone(),
+                    item: item.into(),
+                    ..Default::default()
+                })
+                .await?;
+            let held = self.held.entry(alert_key.to_string()).or_default();
+            match held
+                .iter_mut()
+                .find(|held| held.session.session_id == held_session.session.session_id)
+            {
+                Some(held) => *held = held_session,
+                None => held.push(held_session),
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, alert_keys))]
+    async fn load_held(
+        &mut self,
+        alert_keys: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<(), Self::Error> {
+        for alert_key in alert_keys {
+            let held = self.query_held(alert_key, now).await?;
+            if !held.is_empty() {
+                self.held.insert(alert_key.clone(), held);
+            }
+        }
+        Ok(())
+    }
+
+    fn held(&self, alert_key: &str) -> Vec<HeldSession> {
+        self.held.get(alert_key).cloned().unwrap_or_default()
+    }
+
+    /// Sessions held again by another engine since they were loaded are left to be sent
+    /// with the next notification.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn clear_held(&mut self, alert_key: &str) -> Result<(), Self::Error> {
+        for held_session in self.held.remove(alert_key).unwrap_or_default() {
+            let session_id = format!(
+                "{}{}",
+                HELD_SESSION_ID_PREFIX, held_session.session.session_id
+            );
+            let res = self
+                .dynamodb_client
+                .delete_item(DeleteItemInput {
+                    table_name: self.table_name.clone(),
+                    key: Self::key(alert_key, &session_id),
+                    condition_expression: Some("held_at = :held_at".to_string()),
+                    expression_attribute_values: Some(attr_map! {
+                        ":held_at" => held_session.held_at
+                    }),
+                    ..Default::default()
+                })
+                .await;
+            match res {
+                Ok(_) | Err(RusotoError::Service(DeleteItemError::ConditionalCheckFailed(_))) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, session_ids))]
+    async fn deplete(
+        &mut self,
+        alert_key: &str,
+        session_ids: &[String],
+    ) -> Result<(), Self::Error> {
+        for session_id in session_ids {
+            let item_key = (alert_key.to_string(), session_id.clone());
+            let item = match self.items.get_mut(&item_key) {
+                Some(item) if !item.depleted => item,
+                _ => continue,
+            };
+            // Left as is when another engine alerted the session again since it was loaded
+            let res = self
+                .dynamodb_client
+                .update_item(UpdateItemInput {
+                    table_name: self.table_name.clone(),
+                    key: Self::key(alert_key, session_id),
+                    update_expression: Some("SET depleted = :depleted".to_string()),
+                    condition_expression: Some("notified_at = :notified_at".to_string()),
+                    expression_attribute_values: Some(attr_map! {
+                        ":depleted" => true,
+                        ":notified_at" => item.notified_at
+                    }),
+                    ..Default::default()
+                })
+                .await;
+            match res {
+                Ok(_) => item.depleted = true,
+                Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct DynamoExclusionMapConfig {
+    table_name: String,
+    ttl: Duration,
+}
+
+impl DynamoExclusionMapConfig {
+    fn init() -> Self {
+        let table_name =
+            env::var("EXCLUSION_MAP_TABLE").unwrap_or_else(|_| DEFAULT_TABLE_NAME.to_string());
+        let ttl_hours = env::var("EXCLUSION_MAP_TTL_HOURS")
+            .ok()
+            .and_then(|ttl_hours| ttl_hours.parse().ok())
+            .unwrap_or(DEFAULT_TTL_HOURS);
+        Self {
+            table_name,
+            ttl: Duration::hours(ttl_hours),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use dynomite::dynamodb::{
+        AttributeDefinition, CreateTableInput, DeleteTableInput, DynamoDb, DynamoDbClient,
+        KeySchemaElement,
+    };
+    use rusoto_core::{credential::StaticProvider, HttpClient, Region};
+
+    use super::{expires_at, DynamoExclusionMap, NotificationLogItem};
+    use crate::{
+        alert_engine::{
+            alert_session::AlertSession,
+            exclusion_map::{
+                test::AnyVariance, ExclusionMap, InMemoryExclusionMap, StoredExclusionMap,
+            },
+        },
+        covin::centers::{Center, Session},
+    };
+
+    #[test]
+    fn test_expires_at() {
+        let ttl = Duration::hours(48);
+        // 14-05-2021 01:30 IST
+        let notified_at = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
+
+        // Session is over at 15-05-2021 00:00 IST, before the TTL
+        assert_eq!(
+            expires_at("14-05-2021", notified_at, ttl),
+            Utc.ymd(2021, 5, 14).and_hms(18, 30, 0).timestamp()
+        );
+        // Session later than the TTL, or without a valid date
+        assert_eq!(
+            expires_at("20-05-2021", notified_at, ttl),
+            (notified_at + ttl).timestamp()
+        );
+        assert_eq!(
+            expires_at("", notified_at, ttl),
+            (notified_at + ttl).timestamp()
+        );
+    }
+
+    #[test]
+    fn test_merge_notification_log() {
+        let notified_at = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0).timestamp();
+        let hour = Duration::hours(1).num_seconds();
+
+        // First notifications of the alert
+        let log = NotificationLogItem::merge("alert-1", None, &[notified_at]);
+        assert_eq!(log.notified_at, vec![notified_at]);
+        assert_eq!(log.expires_at, notified_at + 24 * hour);
+        assert_eq!(log.version, 1);
+
+        // Notifications of another run are kept, the ones older than a day are dropped
+        let log = NotificationLogItem {
+            notified_at: vec![notified_at - 25 * hour, notified_at, notified_at + hour],
+            ..log
+        };
+        let merged = NotificationLogItem::merge(
+            "alert-1",
+            Some(&log),
+            &[notified_at + 2 * hour, notified_at],
+        );
+        assert_eq!(
+            merged.notified_at,
+            vec![notified_at, notified_at + hour, notified_at + 2 * hour]
+        );
+        assert_eq!(merged.version, 2);
+    }
+
+    /// Client of DynamoDB Local, at `DYNAMODB_LOCAL_ENDPOINT` or `http://localhost:8000`.
+    fn local_client() -> DynamoDbClient {
+        let endpoint = env::var("DYNAMODB_LOCAL_ENDPOINT")
+            .unwrap_or_else(|_| "http://localhost:8000".to_string());
+        DynamoDbClient::new_with(
+            HttpClient::new().unwrap(),
+            StaticProvider::new_minimal("local".to_string(), "local".to_string()),
+            Region::Custom {
+                name: "local".to_string(),
+                endpoint,
+            },
+        )
+    }
+
+    async fn create_table(client: &DynamoDbClient) -> String {
+        let table_name = format!("CovinExclusions-{}", Utc::now().timestamp_nanos());
+        let attribute = |name: &str, attribute_type: &str| AttributeDefinition {
+            attribute_name: name.to_string(),
+            attribute_type: attribute_type.to_string(),
+        };
+        let key = |name: &str, key_type: &str| KeySchemaElement {
+            attribute_name: name.to_string(),
+            key_type: key_type.to_string(),
+        };
+        client
+            .create_table(CreateTableInput {
+                table_name: table_name.clone(),
+                attribute_definitions: vec![
+                    attribute("alert_key", "S"),
+                    attribute("session_id", "S"),
+                ],
+                key_schema: vec![key("alert_key", "HASH"), key("session_id", "RANGE")],
+                billing_mode: Some("PAY_PER_REQUEST".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        table_name
+    }
+
+    fn session(session_id: &str, capacity: f32) -> Session {
+        Session {
+            session_id: session_id.to_string(),
+            date: "14-05-2021".to_string(),
+            available_capacity: capacity,
+            ..Default::default()
+        }
+    }
+
+    fn keys(session_ids: &[&str]) -> Vec<(String, String)> {
+        session_ids
+            .iter()
+            .map(|session_id| ("alert-1".to_string(), session_id.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DynamoDB Local"]
+    async fn test_dynamo_exclusion_map() {
+        let client = local_client();
+        let table_name = create_table(&client).await;
+        let ttl = Duration::hours(48);
+        let now = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
+        let center = Center::default();
+        let (session_1, session_2) = (
+            session("session-id-1", 5_f32),
+            session("session-id-2", 3_f32),
+        );
+
+        // Two engines load the same sessions before either of them alerts
+        let mut engine_1 = DynamoExclusionMap::with_client(local_client(), &table_name, ttl);
+        let mut engine_2 = DynamoExclusionMap::with_client(local_client(), &table_name, ttl);
+        let keys = keys(&["session-id-1", "session-id-2"]);
+        engine_1.load(&keys, now).await.unwrap();
+        engine_2.load(&keys, now).await.unwrap();
+        assert!(engine_1.any_variance("alert-1", "session-id-1", 5_f32, now));
+        assert!(engine_2.any_variance("alert-1", "session-id-1", 5_f32, now));
+
+        // Only the first to reserve the session alerts it
+        let reserved = engine_1
+            .reserve(
+                "alert-1",
+                vec![AlertSession::from((&session_1, &center))],
+                now,
+            )
+            .await
+            .unwrap();
+        assert_eq!(reserved.len(), 1);
+        let reserved = engine_2
+            .reserve(
+                "alert-1",
+                vec![
+                    AlertSession::from((&session_1, &center)),
+                    AlertSession::from((&session_2, &center)),
+                ],
+                now,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            reserved
+                .iter()
+                .map(|alert_session| alert_session.session.session_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["session-id-2"]
+        );
+
+        // Engine 2 couldn't alert session 2, which is released for the next run
+        engine_2.release("alert-1", &reserved).await.unwrap();
+
+        // Next run sees session 1 alerted with the same capacity and session 2 not alerted
+        let mut next_run = DynamoExclusionMap::with_client(local_client(), &table_name, ttl);
+        let now = now + Duration::minutes(5);
+        next_run.load(&keys, now).await.unwrap();
+        assert!(!next_run.any_variance("alert-1", "session-id-1", 5_f32, now));
+        assert!(next_run.any_variance("alert-1", "session-id-1", 1_f32, now));
+        assert!(next_run.any_variance("alert-1", "session-id-2", 3_f32, now));
+
+        // Items past their expiry are ignored, even before DynamoDB deletes them
+        let mut after_session = DynamoExclusionMap::with_client(local_client(), &table_name, ttl);
+        let now = Utc.ymd(2021, 5, 14).and_hms(18, 30, 0);
+        after_session.load(&keys, now).await.unwrap();
+        assert!(after_session.any_variance("alert-1", "session-id-1", 5_f32, now));
+
+        // Engines storing their notifications at the same time don't drop each other's
+        let notified_at = Utc.ymd(2021, 5, 13).and_hms(20, 10, 0);
+        let mut engine_1 = DynamoExclusionMap::with_client(local_client(), &table_name, ttl);
+        let mut engine_2 = DynamoExclusionMap::with_client(local_client(), &table_name, ttl);
+        engine_1.load(&keys, notified_at).await.unwrap();
+        engine_2.load(&keys, notified_at).await.unwrap();
+        engine_1.add(
+            "alert-1",
+            &[AlertSession::from((&session_1, &center))],
+            notified_at,
+        );
+        engine_2.add(
+            "alert-1",
+            &[AlertSession::from((&session_2, &center))],
+            notified_at + Duration::seconds(1),
+        );
+        engine_1.store().await.unwrap();
+        engine_2.store().await.unwrap();
+        let mut next_run = DynamoExclusionMap::with_client(local_client(), &table_name, ttl);
+        next_run.load(&keys, notified_at).await.unwrap();
+        assert_eq!(
+            next_run.notifications_since("alert-1", notified_at - Duration::minutes(1)),
+            2
+        );
+
+        // Sessions alerted while the map was kept in S3 are imported, and not alerted again
+        let mut fallback = InMemoryExclusionMap::new().with_ttl(ttl);
+        fallback.add(
+            "alert-2",
+            &[AlertSession::from((&session_1, &center))],
+            notified_at,
+        );
+        let fallback = StoredExclusionMap {
+            alerts: fallback.entries().clone(),
+            notifications: fallback.notifications().clone(),
+            held: Default::default(),
+        };
+        let keys = vec![("alert-2".to_string(), "session-id-1".to_string())];
+        let mut first_run = DynamoExclusionMap::with_client(local_client(), &table_name, ttl)
+            .with_fallback(fallback);
+        first_run.load(&keys, notified_at).await.unwrap();
+        assert!(!first_run.any_variance("alert-2", "session-id-1", 5_f32, notified_at));
+        first_run.store().await.unwrap();
+        let mut next_run = DynamoExclusionMap::with_client(local_client(), &table_name, ttl);
+        next_run.load(&keys, notified_at).await.unwrap();
+        assert!(!next_run.any_variance("alert-2", "session-id-1", 5_f32, notified_at));
+        assert_eq!(
+            next_run.notifications_since("alert-2", notified_at - Duration::minutes(1)),
+            1
+        );
+
+        client
+            .delete_table(DeleteTableInput { table_name })
+            .await
+            .unwrap();
+    }
+}
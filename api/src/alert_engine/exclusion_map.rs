@@ -1,69 +1,752 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, convert::Infallible, env, path::PathBuf};
 
 use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 use futures::TryStreamExt;
+use once_cell::sync::Lazy;
 use rusoto_core::RusotoError;
-use rusoto_s3::{GetObjectRequest, PutObjectError, PutObjectRequest, S3Client, S3};
+use rusoto_s3::{
+    GetObjectError, GetObjectRequest, ListObjectsV2Request, PutObjectError, PutObjectRequest,
+    S3Client, S3,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use super::alert_session::AlertSession;
+use crate::api::alerts::{RenotifyPolicy, LEGACY_ALERT_ID};
+
+use super::{
+    alert_session::{AlertSession, HeldSession},
+    renotify::{should_renotify, LastNotified},
+    shard::Shard,
+    DATE_FORMAT,
+};
+
+static CONFIG: Lazy<ExclusionMapConfig> = Lazy::new(ExclusionMapConfig::init);
 
 const EXCLUSION_MAP_S3_BUCKET: &str = "covin-transactions";
 const EXCLUSION_MAP_S3_KEY: &str = "exclusion_map.json";
-
-const VARIANCE_THRESHOLD: &f32 = &1_f32;
+const EXCLUSION_MAP_UPDATED_AT_METADATA: &str = "updated-at";
+const EXCLUSION_MAP_DEFAULT_TTL_HOURS: i64 = 48;
+/// Notifications are logged for the longest window of the rate limits of an alert.
+const NOTIFICATION_LOG_RETENTION_HOURS: i64 = 24;
 
 #[async_trait]
-pub trait ExclusionMap {
+pub trait ExclusionMap: Send {
     type Error: std::error::Error + Sync + Send + 'static;
 
-    fn any_variance(&self, user_id: &str, session_id: &str, capacity: f32) -> bool;
-    fn add(&mut self, user_id: &str, sessions: &[AlertSession]);
+    /// Latest alert of the session for the alert, `None` when it was never alerted.
+    fn last_notified(&self, alert_key: &str, session_id: &str) -> Option<LastNotified>;
+    fn add(&mut self, alert_key: &str, sessions: &[AlertSession], notified_at: DateTime<Utc>);
+    /// Drops the sessions dated before `today`, and the ones last notified longer than
+    /// the TTL before `now`. Maps expiring the entries on their own need not implement it.
+    fn expire(&mut self, _today: NaiveDate, _now: DateTime<Utc>) {}
     async fn store(&self) -> Result<(), Self::Error>;
+
+    /// Loads the entries of the sessions about to be checked, keyed by `(alert_key, session_id)`.
+    /// Maps holding every entry in memory need not implement it.
+    async fn load(
+        &mut self,
+        _keys: &[(String, String)],
+        _now: DateTime<Utc>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Reserves the `sessions` of an alert right before it is sent, returning the ones
+    /// reserved. Maps shared by engines running at the same time leave out the sessions
+    /// reserved by another engine since they were loaded, so they aren't alerted twice.
+    async fn reserve<'s>(
+        &mut self,
+        _alert_key: &str,
+        sessions: Vec<AlertSession<'s, 's>>,
+        _notified_at: DateTime<Utc>,
+    ) -> Result<Vec<AlertSession<'s, 's>>, Self::Error> {
+        Ok(sessions)
+    }
+
+    /// Releases the reserved `sessions` of an alert which couldn't be sent, so that
+    /// they are alerted again in the next run.
+    async fn release(
+        &mut self,
+        _alert_key: &str,
+        _sessions: &[AlertSession],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Records that the sessions of an alert alerted before were seen without capacity,
+    /// for the policies alerting a session again once it is restocked.
+    async fn deplete(
+        &mut self,
+        _alert_key: &str,
+        _session_ids: &[String],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Whether the session, now having the `capacity`, is to be alerted as per the
+    /// renotify `policy` of the alert. Sessions never alerted always are.
+    fn should_notify(
+        &self,
+        alert_key: &str,
+        session_id: &str,
+        capacity: f32,
+        policy: &RenotifyPolicy,
+        now: DateTime<Utc>,
+    ) -> bool {
+        self.last_notified(alert_key, session_id)
+            .map(|last| should_renotify(policy, &last, capacity, now))
+            .unwrap_or(true)
+    }
+
+    /// Holds the `sessions` of an alert held back, to be sent along with its next
+    /// notification. A session held already is kept at its latest capacity. Maps not
+    /// holding any session only send the sessions open when the alert is let through.
+    async fn hold(
+        &mut self,
+        _alert_key: &str,
+        _sessions: &[AlertSession],
+        _held_at: DateTime<Utc>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Loads the sessions held for the alerts about to be checked. Maps holding every
+    /// entry in memory need not implement it.
+    async fn load_held(
+        &mut self,
+        _alert_keys: &[String],
+        _now: DateTime<Utc>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Sessions held for the alert, in the order they were first held.
+    fn held(&self, _alert_key: &str) -> Vec<HeldSession> {
+        vec![]
+    }
+
+    /// Drops the sessions held for the alert, once they are sent.
+    async fn clear_held(&mut self, _alert_key: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Number of notifications sent for the alert after `since`, for the rate limits of the
+    /// alert. Maps not logging the notifications don't limit them.
+    fn notifications_since(&self, _alert_key: &str, _since: DateTime<Utc>) -> usize {
+        0
+    }
+}
+
+/// Session alerted for an alert, along with the capacity it was alerted with.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExclusionEntry {
+    pub session_id: String,
+    pub capacity: f32,
+    /// Date of the session as given by CoWIN, in `%d-%m-%Y`.
+    pub session_date: String,
+    /// Unix timestamp of the latest notification of the session.
+    pub notified_at: i64,
+    /// Number of notifications of the session, entries stored before it was counted
+    /// count as one.
+    #[serde(default = "default_notify_count")]
+    pub notify_count: u32,
+    /// Whether the session was seen without capacity since the latest notification.
+    #[serde(default)]
+    pub depleted: bool,
+}
+
+fn default_notify_count() -> u32 {
+    1
+}
+
+impl ExclusionEntry {
+    fn last_notified(&self) -> LastNotified {
+        LastNotified {
+            capacity: self.capacity,
+            notified_at: Utc.timestamp(self.notified_at, 0),
+            count: self.notify_count,
+            depleted: self.depleted,
+        }
+    }
+
+    fn is_expired(&self, today: NaiveDate, now: DateTime<Utc>, ttl: Duration) -> bool {
+        let session_passed = NaiveDate::parse_from_str(&self.session_date, DATE_FORMAT)
+            .map(|session_date| session_date < today)
+            .unwrap_or(false);
+        session_passed || now.timestamp() - self.notified_at > ttl.num_seconds()
+    }
+}
+
+/// Entry as stored in S3, maps stored before the session date and the notification
+/// time were recorded have `[session_id, capacity]` pairs instead.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StoredEntry {
+    Entry(ExclusionEntry),
+    Legacy(String, f32),
+}
+
+pub type AlertEntries = HashMap<String, Vec<ExclusionEntry>>;
+
+/// Unix timestamps of the notifications sent for each alert.
+pub type NotificationLog = HashMap<String, Vec<i64>>;
+
+/// Sessions held for each alert, until the alert is let through.
+pub type HeldSessions = HashMap<String, Vec<HeldSession>>;
+
+/// Sessions to hold for an alert, leaving out the ones already `held` with the same capacity.
+pub(crate) fn sessions_to_hold(
+    held: &[HeldSession],
+    sessions: &[AlertSession],
+    held_at: DateTime<Utc>,
+) -> Vec<HeldSession> {
+    sessions
+        .iter()
+        .filter(|alert_session| {
+            let session = alert_session.session;
+            !held.iter().any(|held| {
+                held.session.session_id == session.session_id
+                    && held.session.available_capacity == session.available_capacity
+            })
+        })
+        .map(|alert_session| HeldSession::new(alert_session, held_at))
+        .collect()
+}
+
+/// Held sessions are dropped once the session date is over, or after the `ttl`.
+fn is_held_expired(
+    held: &HeldSession,
+    today: NaiveDate,
+    now: DateTime<Utc>,
+    ttl: Duration,
+) -> bool {
+    let session_passed = NaiveDate::parse_from_str(&held.session.date, DATE_FORMAT)
+        .map(|session_date| session_date < today)
+        .unwrap_or(false);
+    session_passed || now.timestamp() - held.held_at > ttl.num_seconds()
+}
+
+/// Exclusion map as stored, along with the log of the notifications and the sessions held
+/// of each alert.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct StoredExclusionMap {
+    pub alerts: AlertEntries,
+    pub notifications: NotificationLog,
+    pub held: HeldSessions,
+}
+
+/// Maps stored before the notifications were logged have only the entries of each alert.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StoredFormat {
+    Current {
+        alerts: HashMap<String, Vec<StoredEntry>>,
+        #[serde(default)]
+        notifications: NotificationLog,
+        #[serde(default)]
+        held: HeldSessions,
+    },
+    Legacy(HashMap<String, Vec<StoredEntry>>),
+}
+
+/// Parses a stored exclusion map, legacy entries are taken as notified at `loaded_at`
+/// so that they expire after the TTL.
+pub(crate) fn parse_exclusion_map(body: &[u8], loaded_at: DateTime<Utc>) -> StoredExclusionMap {
+    let (alerts, notifications, held) = match serde_json::from_slice(body) {
+        Ok(StoredFormat::Current {
+            alerts,
+            notifications,
+            held,
+        }) => (alerts, notifications, held),
+        Ok(StoredFormat::Legacy(alerts)) => {
+            let alerts = alerts
+                .into_iter()
+                .map(|(alert_key, entries)| (migrate_alert_key(alert_key), entries))
+                .collect();
+            (alerts, NotificationLog::new(), HeldSessions::new())
+        }
+        Err(_) => Default::default(),
+    };
+    let alerts = alerts
+        .into_iter()
+        .map(|(alert_key, entries)| {
+            let entries = entries
+                .into_iter()
+                .map(|entry| match entry {
+                    StoredEntry::Entry(entry) => entry,
+                    StoredEntry::Legacy(session_id, capacity) => ExclusionEntry {
+                        session_id,
+                        capacity,
+                        session_date: String::new(),
+                        notified_at: loaded_at.timestamp(),
+                        notify_count: default_notify_count(),
+                        depleted: false,
+                    },
+                })
+                .collect();
+            (alert_key, entries)
+        })
+        .collect();
+    StoredExclusionMap {
+        alerts,
+        notifications,
+        held,
+    }
+}
+
+/// Maps stored while users had a single alert are keyed by `user_id` alone, their entries
+/// belong to the alert migrated from the legacy table.
+fn migrate_alert_key(alert_key: String) -> String {
+    if alert_key.contains('#') {
+        alert_key
+    } else {
+        format!("{}#{}", alert_key, LEGACY_ALERT_ID)
+    }
+}
+
+/// S3 key of the exclusion map of a shard, every shard has its own map so that the shards
+/// running in parallel don't overwrite each other. A single shard keeps the original key.
+/// Maps of a new shard count start from the maps stored for the previous one.
+pub fn exclusion_map_key(shard: &Shard) -> String {
+    if shard.is_single() {
+        EXCLUSION_MAP_S3_KEY.to_string()
+    } else {
+        format!(
+            "{}/shard-{}-of-{}.json",
+            exclusion_map_prefix(),
+            shard.index,
+            shard.count
+        )
+    }
+}
+
+/// Prefix of the S3 keys of the maps of the shards, the S3 key of the single map included.
+pub(crate) fn exclusion_map_prefix() -> &'static str {
+    EXCLUSION_MAP_S3_KEY.trim_end_matches(".json")
+}
+
+/// Whether the key is the S3 key of an exclusion map, of the single shard or of a shard of
+/// any shard count.
+pub(crate) fn is_exclusion_map_key(key: &str) -> bool {
+    let shard_prefix = format!("{}/shard-", exclusion_map_prefix());
+    key == EXCLUSION_MAP_S3_KEY || (key.starts_with(&shard_prefix) && key.ends_with(".json"))
+}
+
+/// Merges the maps stored for other shard counts, keeping the latest entry of each session.
+/// A shard without a map of its own starts from them, so that changing the shard count
+/// doesn't alert every session again.
+pub(crate) fn merge_exclusion_maps(
+    maps: impl IntoIterator<Item = StoredExclusionMap>,
+) -> StoredExclusionMap {
+    let mut merged = StoredExclusionMap::default();
+    for map in maps {
+        for (alert_key, entries) in map.alerts {
+            let merged_entries = merged.alerts.entry(alert_key).or_default();
+            for entry in entries {
+                match merged_entries
+                    .iter_mut()
+                    .find(|merged| merged.session_id == entry.session_id)
+                {
+                    Some(merged) if merged.notified_at >= entry.notified_at => {}
+                    Some(merged) => *merged = entry,
+                    None => merged_entries.push(entry),
+                }
+            }
+        }
+        for (alert_key, notified_at) in map.notifications {
+            let merged_log = merged.notifications.entry(alert_key).or_default();
+            merged_log.extend(notified_at);
+            merged_log.sort_unstable();
+            merged_log.dedup();
+        }
+        for (alert_key, held) in map.held {
+            let merged_held = merged.held.entry(alert_key).or_default();
+            for held in held {
+                if !merged_held
+                    .iter()
+                    .any(|merged| merged.session.session_id == held.session.session_id)
+                {
+                    merged_held.push(held);
+                }
+            }
+        }
+    }
+    merged
+}
+
+/// Exclusion map held in memory for a single process, nothing is persisted across runs.
+/// Also the state the S3 and file backed maps load and store as a whole.
+#[derive(Debug, Clone)]
+pub struct InMemoryExclusionMap {
+    exclusion_map: AlertEntries,
+    notifications: NotificationLog,
+    held: HeldSessions,
+    ttl: Duration,
+    /// Whether the map changed since it was loaded, and needs to be stored.
+    dirty: bool,
+    /// Time of the latest notification added to the map in this run.
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl Default for InMemoryExclusionMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryExclusionMap {
+    /// Empty map, expiring the entries after the TTL of `EXCLUSION_MAP_TTL_HOURS`.
+    pub fn new() -> Self {
+        Self {
+            exclusion_map: AlertEntries::new(),
+            notifications: NotificationLog::new(),
+            held: HeldSessions::new(),
+            ttl: CONFIG.ttl,
+            dirty: false,
+            updated_at: None,
+        }
+    }
+
+    /// Starts from the map loaded from a store.
+    pub fn with_stored(mut self, stored: StoredExclusionMap) -> Self {
+        self.exclusion_map = stored.alerts;
+        self.notifications = stored.notifications;
+        self.held = stored.held;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn entries(&self) -> &AlertEntries {
+        &self.exclusion_map
+    }
+
+    pub fn notifications(&self) -> &NotificationLog {
+        &self.notifications
+    }
+
+    pub fn held_sessions(&self) -> &HeldSessions {
+        &self.held
+    }
+
+    /// Map in the stored format, as read by `parse_exclusion_map`.
+    pub fn to_json(&self) -> serde_json::Result<Vec<u8>> {
+        #[derive(Serialize)]
+        struct StoredRef<'a> {
+            alerts: &'a AlertEntries,
+            notifications: &'a NotificationLog,
+            held: &'a HeldSessions,
+        }
+        serde_json::to_vec(&StoredRef {
+            alerts: &self.exclusion_map,
+            notifications: &self.notifications,
+            held: &self.held,
+        })
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.updated_at
+    }
+
+    /// Marks the entries of the sessions of an alert as seen without capacity.
+    pub(crate) fn mark_depleted(&mut self, alert_key: &str, session_ids: &[String]) {
+        if let Some(entries) = self.exclusion_map.get_mut(alert_key) {
+            for entry in entries.iter_mut() {
+                if !entry.depleted && session_ids.contains(&entry.session_id) {
+                    entry.depleted = true;
+                    self.dirty = true;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn hold_sessions(
+        &mut self,
+        alert_key: &str,
+        sessions: &[AlertSession],
+        held_at: DateTime<Utc>,
+    ) {
+        let held = self.held.entry(alert_key.to_owned()).or_default();
+        for held_session in sessions_to_hold(held, sessions, held_at) {
+            match held
+                .iter_mut()
+                .find(|held| held.session.session_id == held_session.session.session_id)
+            {
+                Some(held) => *held = held_session,
+                None => held.push(held_session),
+            }
+            self.dirty = true;
+        }
+        if held.is_empty() {
+            self.held.remove(alert_key);
+        }
+    }
+
+    pub(crate) fn held_sessions_of(&self, alert_key: &str) -> Vec<HeldSession> {
+        self.held.get(alert_key).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn clear_held_sessions(&mut self, alert_key: &str) {
+        if self.held.remove(alert_key).is_some() {
+            self.dirty = true;
+        }
+    }
+}
+
+#[async_trait]
+impl ExclusionMap for InMemoryExclusionMap {
+    type Error = Infallible;
+
+    fn add(&mut self, alert_key: &str, sessions: &[AlertSession], notified_at: DateTime<Utc>) {
+        if sessions.is_empty() {
+            return;
+        }
+        self.dirty = true;
+        self.updated_at = Some(notified_at);
+        self.notifications
+            .entry(alert_key.to_owned())
+            .or_default()
+            .push(notified_at.timestamp());
+        let existing_entries = self.exclusion_map.entry(alert_key.to_owned()).or_default();
+        sessions.iter().for_each(|alert_session| {
+            let session = alert_session.session;
+            let mut entry = ExclusionEntry {
+                session_id: session.session_id.to_owned(),
+                capacity: session.available_capacity,
+                session_date: session.date.to_owned(),
+                notified_at: notified_at.timestamp(),
+                notify_count: 1,
+                depleted: false,
+            };
+            if let Some(existing_entry) = existing_entries
+                .iter_mut()
+                .find(|existing_entry| existing_entry.session_id == entry.session_id)
+            {
+                entry.notify_count = existing_entry.notify_count + 1;
+                *existing_entry = entry;
+            } else {
+                existing_entries.push(entry);
+            }
+        });
+    }
+
+    fn last_notified(&self, alert_key: &str, session_id: &str) -> Option<LastNotified> {
+        self.exclusion_map
+            .get(alert_key)?
+            .iter()
+            .find(|entry| entry.session_id == session_id)
+            .map(ExclusionEntry::last_notified)
+    }
+
+    async fn deplete(
+        &mut self,
+        alert_key: &str,
+        session_ids: &[String],
+    ) -> Result<(), Self::Error> {
+        self.mark_depleted(alert_key, session_ids);
+        Ok(())
+    }
+
+    async fn hold(
+        &mut self,
+        alert_key: &str,
+        sessions: &[AlertSession],
+        held_at: DateTime<Utc>,
+    ) -> Result<(), Self::Error> {
+        self.hold_sessions(alert_key, sessions, held_at);
+        Ok(())
+    }
+
+    fn held(&self, alert_key: &str) -> Vec<HeldSession> {
+        self.held_sessions_of(alert_key)
+    }
+
+    async fn clear_held(&mut self, alert_key: &str) -> Result<(), Self::Error> {
+        self.clear_held_sessions(alert_key);
+        Ok(())
+    }
+
+    fn notifications_since(&self, alert_key: &str, since: DateTime<Utc>) -> usize {
+        self.notifications
+            .get(alert_key)
+            .map(|notified_at| {
+                notified_at
+                    .iter()
+                    .filter(|notified_at| **notified_at > since.timestamp())
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    fn expire(&mut self, today: NaiveDate, now: DateTime<Utc>) {
+        let ttl = self.ttl;
+        let mut expired = 0;
+        self.exclusion_map.retain(|_alert_key, entries| {
+            let len = entries.len();
+            entries.retain(|entry| !entry.is_expired(today, now, ttl));
+            expired += len - entries.len();
+            !entries.is_empty()
+        });
+        let logged_since = (now - Duration::hours(NOTIFICATION_LOG_RETENTION_HOURS)).timestamp();
+        self.notifications.retain(|_alert_key, notified_at| {
+            let len = notified_at.len();
+            notified_at.retain(|notified_at| *notified_at > logged_since);
+            expired += len - notified_at.len();
+            !notified_at.is_empty()
+        });
+        self.held.retain(|_alert_key, held| {
+            let len = held.len();
+            held.retain(|held| !is_held_expired(held, today, now, ttl));
+            expired += len - held.len();
+            !held.is_empty()
+        });
+        if expired > 0 {
+            tracing::debug!(message = "Expired exclusion map entries", expired);
+            self.dirty = true;
+        }
+    }
+
+    async fn store(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 pub struct S3ExclusionMap {
     s3_client: S3Client,
-    initial_content_length: usize,
-    exclusion_map: HashMap<String, Vec<(String, f32)>>,
+    key: String,
+    exclusion_map: InMemoryExclusionMap,
 }
 
 impl S3ExclusionMap {
     pub async fn init() -> Self {
+        Self::init_for_shard(&Shard::default()).await
+    }
+
+    pub async fn init_for_shard(shard: &Shard) -> Self {
         let s3_client = S3Client::new(rusoto_core::Region::ApSouth1);
-        let (exclusion_map, content_length) = Self::init_exclusion_map(&s3_client).await;
+        let key = exclusion_map_key(shard);
+        let exclusion_map = Self::init_exclusion_map(&s3_client, &key).await;
         Self {
             s3_client,
-            exclusion_map,
-            initial_content_length: content_length,
+            key,
+            exclusion_map: InMemoryExclusionMap::new().with_stored(exclusion_map),
+        }
+    }
+
+    /// Loads the map stored at the `key`. A shard without a map of its own yet starts from
+    /// the maps of the other shard counts.
+    #[tracing::instrument(level = "debug", skip(s3_client))]
+    pub async fn init_exclusion_map(s3_client: &S3Client, key: &str) -> StoredExclusionMap {
+        match Self::get_exclusion_map(s3_client, key).await {
+            Ok(Some(value)) => value,
+            Ok(None) => Self::load_exclusion_maps(s3_client, Some(key), None).await,
+            Err(err) => {
+                tracing::warn!(message = "Unable to load the exclusion map", %key, error = ?err);
+                StoredExclusionMap::default()
+            }
         }
     }
 
+    /// Every map stored, but the one at the `except_key`, merged into one. Only the maps
+    /// stored after `modified_since` are loaded, when given.
     #[tracing::instrument(level = "debug", skip(s3_client))]
-    pub async fn init_exclusion_map(
+    pub async fn load_exclusion_maps(
         s3_client: &S3Client,
-    ) -> (HashMap<String, Vec<(String, f32)>>, usize) {
-        if let Ok(resp) = s3_client
+        except_key: Option<&str>,
+        modified_since: Option<DateTime<Utc>>,
+    ) -> StoredExclusionMap {
+        let is_modified = |last_modified: Option<&str>| match (modified_since, last_modified) {
+            (Some(since), Some(last_modified)) => DateTime::parse_from_rfc3339(last_modified)
+                .map(|last_modified| last_modified > since)
+                .unwrap_or(true),
+            _ => true,
+        };
+        let mut keys = vec![];
+        let mut continuation_token = None;
+        loop {
+            let resp = match s3_client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: EXCLUSION_MAP_S3_BUCKET.to_string(),
+                    prefix: Some(exclusion_map_prefix().to_string()),
+                    continuation_token,
+                    ..Default::default()
+                })
+                .await
+            {
+                Ok(resp) => resp,
+                Err(err) => {
+                    tracing::warn!(message = "Unable to list the exclusion maps", error = ?err);
+                    break;
+                }
+            };
+            keys.extend(
+                resp.contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|object| is_modified(object.last_modified.as_deref()))
+                    .filter_map(|object| object.key)
+                    .filter(|key| is_exclusion_map_key(key) && Some(key.as_str()) != except_key),
+            );
+            continuation_token = resp.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        let mut maps = vec![];
+        for key in keys {
+            match Self::get_exclusion_map(s3_client, &key).await {
+                Ok(Some(value)) => maps.push(value),
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::warn!(message = "Unable to load the exclusion map", %key, error = ?err)
+                }
+            }
+        }
+        merge_exclusion_maps(maps)
+    }
+
+    /// Map stored at the `key`, `None` when there is none.
+    async fn get_exclusion_map(
+        s3_client: &S3Client,
+        key: &str,
+    ) -> Result<Option<StoredExclusionMap>, RusotoError<GetObjectError>> {
+        let resp = match s3_client
             .get_object(GetObjectRequest {
                 bucket: EXCLUSION_MAP_S3_BUCKET.to_string(),
-                key: EXCLUSION_MAP_S3_KEY.to_string(),
+                key: key.to_string(),
                 ..Default::default()
             })
             .await
         {
-            if let Some(body) = resp.body {
-                let body = body
-                    .map_ok(|b| b.to_vec())
-                    .try_concat()
-                    .await
-                    .unwrap_or_default();
-                let content_length = body.len();
-                let value: HashMap<String, Vec<(String, f32)>> =
-                    serde_json::from_slice(&body).unwrap_or_default();
-                tracing::debug!(message = "exclusion map", ?value);
-                return (value, content_length);
-            }
-        }
-        (HashMap::<String, Vec<(String, f32)>>::new(), 0)
+            Ok(resp) => resp,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let body = match resp.body {
+            Some(body) => body
+                .map_ok(|b| b.to_vec())
+                .try_concat()
+                .await
+                .unwrap_or_default(),
+            None => return Ok(None),
+        };
+        let value = parse_exclusion_map(&body, Utc::now());
+        tracing::debug!(
+            message = "exclusion map",
+            %key,
+            content_length = body.len(),
+            ?value
+        );
+        Ok(Some(value))
     }
 }
 
@@ -71,67 +754,78 @@ impl S3ExclusionMap {
 impl ExclusionMap for S3ExclusionMap {
     type Error = RusotoError<PutObjectError>;
 
-    fn add(&mut self, user_id: &str, sessions: &[AlertSession]) {
-        let vals = sessions
-            .iter()
-            .map(|session| {
-                (
-                    session.session.session_id.to_owned(),
-                    session.session.available_capacity,
-                )
-            })
-            .collect::<Vec<_>>();
-        if let Some(existing_vals) = self.exclusion_map.get_mut(user_id) {
-            vals.into_iter().for_each(|(session_id, capacity)| {
-                if let Some(mut exist_val) = existing_vals
-                    .iter_mut()
-                    .find(|(s_id, _cap)| s_id == &session_id)
-                {
-                    exist_val.1 = capacity;
-                } else {
-                    existing_vals.push((session_id, capacity));
-                }
-            });
-        } else {
-            self.exclusion_map.insert(user_id.to_owned(), vals);
-        }
+    fn add(&mut self, alert_key: &str, sessions: &[AlertSession], notified_at: DateTime<Utc>) {
+        self.exclusion_map.add(alert_key, sessions, notified_at)
     }
 
-    fn any_variance(&self, user_id: &str, session_id: &str, capacity: f32) -> bool {
-        let sessions_for_user = self
-            .exclusion_map
-            .get(user_id)
-            .map(|vals| vals.as_slice())
-            .unwrap_or_else(|| &[]);
-        sessions_for_user
-            .iter()
-            .find(|(s_id, _cap)| s_id == session_id)
-            .map(|(_s_id, cap)| (cap - capacity).abs().ge(VARIANCE_THRESHOLD))
-            .unwrap_or(true)
+    fn last_notified(&self, alert_key: &str, session_id: &str) -> Option<LastNotified> {
+        self.exclusion_map.last_notified(alert_key, session_id)
+    }
+
+    async fn deplete(
+        &mut self,
+        alert_key: &str,
+        session_ids: &[String],
+    ) -> Result<(), Self::Error> {
+        self.exclusion_map.mark_depleted(alert_key, session_ids);
+        Ok(())
+    }
+
+    async fn hold(
+        &mut self,
+        alert_key: &str,
+        sessions: &[AlertSession],
+        held_at: DateTime<Utc>,
+    ) -> Result<(), Self::Error> {
+        self.exclusion_map
+            .hold_sessions(alert_key, sessions, held_at);
+        Ok(())
+    }
+
+    fn held(&self, alert_key: &str) -> Vec<HeldSession> {
+        self.exclusion_map.held_sessions_of(alert_key)
+    }
+
+    async fn clear_held(&mut self, alert_key: &str) -> Result<(), Self::Error> {
+        self.exclusion_map.clear_held_sessions(alert_key);
+        Ok(())
+    }
+
+    fn notifications_since(&self, alert_key: &str, since: DateTime<Utc>) -> usize {
+        self.exclusion_map.notifications_since(alert_key, since)
+    }
+
+    fn expire(&mut self, today: NaiveDate, now: DateTime<Utc>) {
+        self.exclusion_map.expire(today, now)
     }
 
     #[tracing::instrument(level = "debug", skip(self))]
     async fn store(&self) -> Result<(), Self::Error> {
         let s3_client = &self.s3_client;
-        let exclusion_map = &self.exclusion_map;
-        let initial_content_length = self.initial_content_length;
-        let json = serde_json::to_string(exclusion_map)?.as_bytes().to_vec();
-        if initial_content_length != json.len() {
+        let exclusion_map = self.exclusion_map.entries();
+        if self.exclusion_map.is_dirty() {
+            let json = self.exclusion_map.to_json()?;
             let _resp = s3_client
                 .put_object(PutObjectRequest {
                     bucket: EXCLUSION_MAP_S3_BUCKET.to_string(),
-                    key: EXCLUSION_MAP_S3_KEY.to_string(),
+                    key: self.key.clone(),
                     body: Some(json.into()),
                     content_type: Some("appliaction/json".to_string()),
+                    metadata: self.exclusion_map.updated_at().map(|updated_at| {
+                        let mut metadata = HashMap::new();
+                        metadata.insert(
+                            EXCLUSION_MAP_UPDATED_AT_METADATA.to_string(),
+                            updated_at.to_rfc3339(),
+                        );
+                        metadata
+                    }),
                     ..Default::default()
                 })
                 .await?;
             tracing::debug!(message = "Stored the exclusion_map")
         } else {
             tracing::debug!(
-                message = "No change in content_length, not storing the exclusion_map",
-                initial_content_length,
-                current_content_length = json.len(),
+                message = "No change in the exclusion_map, not storing it",
                 ?exclusion_map
             );
         }
@@ -139,32 +833,111 @@ impl ExclusionMap for S3ExclusionMap {
     }
 }
 
+#[derive(Debug, Error, PartialEq)]
+#[error("Unknown exclusion map backend {0}, expected one of s3, dynamodb, file or memory")]
+pub struct UnknownBackendError(String);
+
+/// Store of the exclusion map used by the alert engine.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExclusionMapBackend {
+    /// JSON object per shard in S3.
+    S3,
+    /// Item per alerted session in DynamoDB, shared by the engines running at the same time.
+    DynamoDb,
+    /// JSON file per shard in the given directory, for running without AWS.
+    File(PathBuf),
+    /// Nothing kept across runs.
+    InMemory,
+}
+
+impl ExclusionMapBackend {
+    /// Backend named by the `EXCLUSION_MAP_BACKEND` env var, S3 by default. File maps are
+    /// kept in the `EXCLUSION_MAP_DIR` directory, the working directory by default.
+    pub fn from_env() -> Result<Self, UnknownBackendError> {
+        let backend = env::var("EXCLUSION_MAP_BACKEND").unwrap_or_else(|_| "s3".to_string());
+        let dir = env::var("EXCLUSION_MAP_DIR").unwrap_or_else(|_| ".".to_string());
+        Self::parse(&backend, dir)
+    }
+
+    fn parse(backend: &str, dir: impl Into<PathBuf>) -> Result<Self, UnknownBackendError> {
+        match backend.trim().to_ascii_lowercase().as_str() {
+            "s3" => Ok(Self::S3),
+            "dynamodb" => Ok(Self::DynamoDb),
+            "file" => Ok(Self::File(dir.into())),
+            "memory" => Ok(Self::InMemory),
+            _ => Err(UnknownBackendError(backend.to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ExclusionMapConfig {
+    ttl: Duration,
+}
+
+impl ExclusionMapConfig {
+    fn init() -> Self {
+        let ttl_hours = env::var("EXCLUSION_MAP_TTL_HOURS")
+            .ok()
+            .and_then(|ttl_hours| ttl_hours.parse().ok())
+            .unwrap_or(EXCLUSION_MAP_DEFAULT_TTL_HOURS);
+        Self {
+            ttl: Duration::hours(ttl_hours),
+        }
+    }
+}
+
 #[cfg(test)]
-mod test {
-    use std::collections::HashMap;
+pub(crate) mod test {
+    use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 
     use crate::{
-        alert_engine::alert_session::AlertSession,
+        alert_engine::{alert_session::AlertSession, shard::Shard},
+        api::alerts::RenotifyPolicy,
         covin::centers::{Center, Session},
     };
 
-    use super::{ExclusionMap, S3Client, S3ExclusionMap};
+    use super::{
+        exclusion_map_key, is_exclusion_map_key, merge_exclusion_maps, parse_exclusion_map,
+        ExclusionEntry, ExclusionMap, ExclusionMapBackend, InMemoryExclusionMap,
+        StoredExclusionMap, EXCLUSION_MAP_S3_KEY,
+    };
+
+    /// Checks of the exclusion maps as per the default renotify policy.
+    pub(crate) trait AnyVariance: ExclusionMap {
+        /// Whether the capacity of the session changed since it was alerted, at `now`.
+        fn any_variance(
+            &self,
+            alert_key: &str,
+            session_id: &str,
+            capacity: f32,
+            now: DateTime<Utc>,
+        ) -> bool {
+            self.should_notify(
+                alert_key,
+                session_id,
+                capacity,
+                &RenotifyPolicy::default(),
+                now,
+            )
+        }
+    }
+
+    impl<T: ExclusionMap + ?Sized> AnyVariance for T {}
+
+    fn exclusion_map() -> InMemoryExclusionMap {
+        InMemoryExclusionMap::new().with_ttl(Duration::hours(48))
+    }
 
     #[test]
     fn test_exclusion_map() {
-        let mut exclusion_map = S3ExclusionMap {
-            exclusion_map: HashMap::<String, Vec<(String, f32)>>::new(),
-            initial_content_length: 0,
-            s3_client: S3Client::new(rusoto_core::Region::ApSouth1),
-        };
+        let mut exclusion_map = exclusion_map();
         let user_id = "some-user-id";
+        let notified_at = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
 
         // Variance should be true when
         // the session id doesn't already exists for user in exclusion_map
-        assert_eq!(
-            exclusion_map.any_variance(user_id, "session-id-1", 1_f32),
-            true
-        );
+        assert!(exclusion_map.any_variance(user_id, "session-id-1", 1_f32, notified_at));
 
         let center = Center {
             ..Default::default()
@@ -178,15 +951,13 @@ mod test {
         let sessions_1 = vec![AlertSession {
             center: &center,
             session: &session,
+            distance_km: None,
         }];
-        exclusion_map.add(user_id, &sessions_1);
+        exclusion_map.add(user_id, &sessions_1, notified_at);
 
         // Variance should be false, when
         // the session id exists in the exclusion_map and no change in the capacity
-        assert_eq!(
-            exclusion_map.any_variance(user_id, "session-id-1", 1_f32),
-            false
-        );
+        assert!(!exclusion_map.any_variance(user_id, "session-id-1", 1_f32, notified_at));
         let session = Session {
             session_id: "session-id-1".to_string(),
             available_capacity: 5_f32,
@@ -195,14 +966,12 @@ mod test {
         let sessions_2 = vec![AlertSession {
             center: &center,
             session: &session,
+            distance_km: None,
         }];
-        exclusion_map.add(user_id, &sessions_2);
+        exclusion_map.add(user_id, &sessions_2, notified_at);
 
         // Variance should be true, when there is change in capacity
-        assert_eq!(
-            exclusion_map.any_variance(user_id, "session-id-1", 1_f32),
-            true
-        );
+        assert!(exclusion_map.any_variance(user_id, "session-id-1", 1_f32, notified_at));
 
         let session = Session {
             session_id: "session-id-2".to_string(),
@@ -212,20 +981,326 @@ mod test {
         let sessions_3 = vec![AlertSession {
             center: &center,
             session: &session,
+            distance_km: None,
         }];
-        exclusion_map.add(user_id, &sessions_3);
+        exclusion_map.add(user_id, &sessions_3, notified_at);
 
         // Variance should be false, when there is new session added
         // and previously added session should remain for the user!
+        assert!(!exclusion_map.any_variance(user_id, "session-id-1", 5_f32, notified_at));
+
+        // Newly added session should also remain
+        assert!(!exclusion_map.any_variance(user_id, "session-id-2", 5_f32, notified_at));
+
+        // Time of the latest notification is kept to be stored along with the map
+        assert_eq!(exclusion_map.updated_at, Some(notified_at));
+    }
+
+    #[test]
+    fn test_exclusion_map_key() {
+        assert_eq!(exclusion_map_key(&Shard::default()), EXCLUSION_MAP_S3_KEY);
         assert_eq!(
-            exclusion_map.any_variance(user_id, "session-id-1", 5_f32),
-            false
+            exclusion_map_key(&Shard::new(2, 4).unwrap()),
+            "exclusion_map/shard-2-of-4.json"
         );
+        assert!(is_exclusion_map_key(EXCLUSION_MAP_S3_KEY));
+        assert!(is_exclusion_map_key("exclusion_map/shard-0-of-2.json"));
+        assert!(!is_exclusion_map_key("exclusion_map/shard-0-of-2.json.tmp"));
+        assert!(!is_exclusion_map_key("locations.json"));
+    }
 
-        // Newly added session should also remain
+    #[test]
+    fn test_merge_exclusion_maps() {
+        let entry = |session_id: &str, capacity: f32, notified_at: i64| ExclusionEntry {
+            session_id: session_id.to_string(),
+            capacity,
+            session_date: "14-05-2021".to_string(),
+            notified_at,
+            notify_count: 1,
+            depleted: false,
+        };
+        let map = |entries: Vec<ExclusionEntry>, notified_at: Vec<i64>| StoredExclusionMap {
+            alerts: vec![("user-1#alert-1".to_string(), entries)]
+                .into_iter()
+                .collect(),
+            notifications: vec![("user-1#alert-1".to_string(), notified_at)]
+                .into_iter()
+                .collect(),
+            held: Default::default(),
+        };
+        let merged = merge_exclusion_maps(vec![
+            map(vec![entry("session-1", 5_f32, 100)], vec![100]),
+            map(
+                vec![
+                    entry("session-1", 3_f32, 200),
+                    entry("session-2", 1_f32, 50),
+                ],
+                vec![50, 100, 200],
+            ),
+        ]);
+        // The latest entry of each session is kept
+        assert_eq!(
+            merged.alerts["user-1#alert-1"],
+            vec![
+                entry("session-1", 3_f32, 200),
+                entry("session-2", 1_f32, 50)
+            ]
+        );
+        assert_eq!(merged.notifications["user-1#alert-1"], vec![50, 100, 200]);
+    }
+
+    #[test]
+    fn test_exclusion_map_expire() {
+        let mut exclusion_map = exclusion_map();
+        let center = Center::default();
+        let session = |session_id: &str, date: &str| Session {
+            session_id: session_id.to_string(),
+            date: date.to_string(),
+            available_capacity: 1_f32,
+            ..Default::default()
+        };
+        let (past, today, later) = (
+            session("session-id-1", "13-05-2021"),
+            session("session-id-2", "14-05-2021"),
+            session("session-id-3", "20-05-2021"),
+        );
+        let notified_at = Utc.ymd(2021, 5, 12).and_hms(20, 0, 0);
+        exclusion_map.add(
+            "alert-1",
+            &[
+                AlertSession::from((&past, &center)),
+                AlertSession::from((&today, &center)),
+            ],
+            notified_at,
+        );
+        exclusion_map.add(
+            "alert-2",
+            &[AlertSession::from((&later, &center))],
+            notified_at + Duration::hours(12),
+        );
+        exclusion_map.dirty = false;
+
+        // Nothing to expire yet
+        exclusion_map.expire(NaiveDate::from_ymd(2021, 5, 13), notified_at);
+        assert!(!exclusion_map.dirty);
+
+        // Session dated before today is expired
+        let today = NaiveDate::from_ymd(2021, 5, 14);
+        let now = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
+        exclusion_map.expire(today, now);
+        assert!(exclusion_map.dirty);
+        assert!(exclusion_map.any_variance("alert-1", "session-id-1", 1_f32, now));
+        assert!(!exclusion_map.any_variance("alert-1", "session-id-2", 1_f32, now));
+
+        // Sessions notified longer than the TTL ago are expired, along with the emptied alert
+        let now = notified_at + Duration::hours(50);
+        exclusion_map.expire(today, now);
+        assert!(!exclusion_map.exclusion_map.contains_key("alert-1"));
+        assert!(!exclusion_map.any_variance("alert-2", "session-id-3", 1_f32, now));
+    }
+
+    #[tokio::test]
+    async fn test_exclusion_map_renotify_state() {
+        let mut exclusion_map = exclusion_map();
+        let center = Center::default();
+        let session = Session {
+            session_id: "session-id-1".to_string(),
+            available_capacity: 5_f32,
+            ..Default::default()
+        };
+        let notified_at = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
+        let sessions = [AlertSession::from((&session, &center))];
+        assert_eq!(exclusion_map.last_notified("alert-1", "session-id-1"), None);
+
+        exclusion_map.add("alert-1", &sessions, notified_at);
+        exclusion_map.add("alert-1", &sessions, notified_at + Duration::hours(1));
+        let last = exclusion_map
+            .last_notified("alert-1", "session-id-1")
+            .unwrap();
+        assert_eq!(last.count, 2);
+        assert_eq!(last.notified_at, notified_at + Duration::hours(1));
+        assert!(!last.depleted);
+
+        // Only the sessions alerted before are marked depleted
+        exclusion_map.dirty = false;
+        exclusion_map
+            .deplete(
+                "alert-1",
+                &["session-id-1".to_string(), "session-id-2".to_string()],
+            )
+            .await
+            .unwrap();
+        assert!(exclusion_map.dirty);
+        assert!(
+            exclusion_map
+                .last_notified("alert-1", "session-id-1")
+                .unwrap()
+                .depleted
+        );
+        assert_eq!(exclusion_map.last_notified("alert-1", "session-id-2"), None);
+
+        // Alerting the session again resets it
+        exclusion_map.add("alert-1", &sessions, notified_at + Duration::hours(2));
+        assert!(
+            !exclusion_map
+                .last_notified("alert-1", "session-id-1")
+                .unwrap()
+                .depleted
+        );
+    }
+
+    #[test]
+    fn test_exclusion_map_notification_log() {
+        let mut exclusion_map = exclusion_map();
+        let center = Center::default();
+        let session = Session {
+            session_id: "session-id-1".to_string(),
+            date: "20-05-2021".to_string(),
+            available_capacity: 5_f32,
+            ..Default::default()
+        };
+        let sessions = [AlertSession::from((&session, &center))];
+        let notified_at = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
+        exclusion_map.add("alert-1", &sessions, notified_at);
+        exclusion_map.add("alert-1", &sessions, notified_at + Duration::minutes(30));
+        let now = notified_at + Duration::minutes(45);
+        assert_eq!(
+            exclusion_map.notifications_since("alert-1", now - Duration::hours(1)),
+            2
+        );
+        assert_eq!(exclusion_map.notifications_since("alert-1", notified_at), 1);
+        assert_eq!(exclusion_map.notifications_since("alert-2", notified_at), 0);
+
+        // The log is stored along with the entries
+        let stored = parse_exclusion_map(&exclusion_map.to_json().unwrap(), now);
+        assert_eq!(
+            stored.notifications["alert-1"],
+            vec![
+                notified_at.timestamp(),
+                (notified_at + Duration::minutes(30)).timestamp()
+            ]
+        );
+        let mut exclusion_map = exclusion_map.with_stored(stored);
+
+        // Notifications older than a day are dropped from the log
+        exclusion_map.dirty = false;
+        exclusion_map.expire(
+            NaiveDate::from_ymd(2021, 5, 14),
+            notified_at + Duration::hours(24) + Duration::minutes(15),
+        );
+        assert!(exclusion_map.dirty);
+        assert_eq!(
+            exclusion_map.notifications()["alert-1"],
+            vec![(notified_at + Duration::minutes(30)).timestamp()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exclusion_map_held_sessions() {
+        let mut exclusion_map = exclusion_map();
+        let center = Center {
+            center_id: 1,
+            sessions: vec![Session::default()],
+            ..Default::default()
+        };
+        let session = |capacity: f32| Session {
+            session_id: "session-id-1".to_string(),
+            date: "14-05-2021".to_string(),
+            available_capacity: capacity,
+            ..Default::default()
+        };
+        let held_at = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
+        let (first, restocked) = (session(5_f32), session(10_f32));
+        exclusion_map
+            .hold("alert-1", &[AlertSession::from((&first, &center))], held_at)
+            .await
+            .unwrap();
+
+        // The same session is held only again once its capacity changes
+        exclusion_map.dirty = false;
+        let later = held_at + Duration::minutes(5);
+        exclusion_map
+            .hold("alert-1", &[AlertSession::from((&first, &center))], later)
+            .await
+            .unwrap();
+        assert!(!exclusion_map.dirty);
+        exclusion_map
+            .hold(
+                "alert-1",
+                &[AlertSession::from((&restocked, &center))],
+                later,
+            )
+            .await
+            .unwrap();
+        let held = exclusion_map.held("alert-1");
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].session, restocked);
+        assert_eq!(held[0].held_at, later.timestamp());
+        assert!(held[0].center.sessions.is_empty());
+
+        // Held sessions are stored along with the entries, and dropped along with the session date
+        let stored = parse_exclusion_map(&exclusion_map.to_json().unwrap(), later);
+        assert_eq!(stored.held["alert-1"], held);
+        let mut exclusion_map = exclusion_map.with_stored(stored);
+        exclusion_map.expire(NaiveDate::from_ymd(2021, 5, 14), later);
+        assert_eq!(exclusion_map.held("alert-1"), held);
+        exclusion_map.expire(NaiveDate::from_ymd(2021, 5, 15), later);
+        assert!(exclusion_map.held("alert-1").is_empty());
+
+        exclusion_map
+            .hold("alert-1", &[AlertSession::from((&first, &center))], later)
+            .await
+            .unwrap();
+        exclusion_map.clear_held("alert-1").await.unwrap();
+        assert!(exclusion_map.held_sessions().is_empty());
+    }
+
+    #[test]
+    fn test_parse_legacy_exclusion_map() {
+        let loaded_at = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
+        let body = br#"{
+            "user-1": [["session-id-1", 5.0]],
+            "user-2#alert-2": [{"sessionId": "session-id-2", "capacity": 2.0, "sessionDate": "14-05-2021", "notifiedAt": 1620936000}]
+        }"#;
+        let exclusion_map = parse_exclusion_map(body, loaded_at).alerts;
+        // Entries keyed by the user alone belong to the alert migrated from the legacy table
+        assert!(!exclusion_map.contains_key("user-1"));
+        assert_eq!(
+            exclusion_map["user-1#default"],
+            vec![ExclusionEntry {
+                session_id: "session-id-1".to_string(),
+                capacity: 5_f32,
+                session_date: String::new(),
+                notified_at: loaded_at.timestamp(),
+                notify_count: 1,
+                depleted: false,
+            }]
+        );
+        assert_eq!(
+            exclusion_map["user-2#alert-2"][0].session_date,
+            "14-05-2021"
+        );
+        assert_eq!(exclusion_map["user-2#alert-2"][0].notified_at, 1620936000);
+    }
+
+    #[test]
+    fn test_exclusion_map_backend() {
+        assert_eq!(
+            ExclusionMapBackend::parse("s3", "."),
+            Ok(ExclusionMapBackend::S3)
+        );
+        assert_eq!(
+            ExclusionMapBackend::parse("DynamoDB", "."),
+            Ok(ExclusionMapBackend::DynamoDb)
+        );
+        assert_eq!(
+            ExclusionMapBackend::parse("file", "/var/lib/covin"),
+            Ok(ExclusionMapBackend::File("/var/lib/covin".into()))
+        );
         assert_eq!(
-            exclusion_map.any_variance(user_id, "session-id-2", 5_f32),
-            false
+            ExclusionMapBackend::parse("memory", "."),
+            Ok(ExclusionMapBackend::InMemory)
         );
+        assert!(ExclusionMapBackend::parse("redis", ".").is_err());
     }
 }
@@ -0,0 +1,348 @@
+use std::{env, fmt, str::FromStr, time::Duration};
+
+use futures::{stream, StreamExt, TryFutureExt};
+use thiserror::Error;
+
+use crate::covin::centers::{CenterResponse, FindCenters};
+
+use super::rate_limiter::TokenBucket;
+
+const FETCH_CONCURRENCY: usize = 8;
+const FETCH_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const FETCH_RATE_PER_SEC: f64 = 10_f64;
+const FETCH_BURST: u32 = 10;
+
+/// Limits of fetching the calendars from CoWIN within a run of the alert engine.
+#[derive(Debug, Clone)]
+pub struct FetchConfig {
+    /// Maximum number of requests in flight at a time.
+    pub concurrency: usize,
+    pub request_timeout: Duration,
+    /// Sustained number of requests a second, as allowed by the CoWIN rate limits.
+    pub rate_per_sec: f64,
+    /// Number of requests allowed at once before the rate limit kicks in.
+    pub burst: u32,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: FETCH_CONCURRENCY,
+            request_timeout: FETCH_REQUEST_TIMEOUT,
+            rate_per_sec: FETCH_RATE_PER_SEC,
+            burst: FETCH_BURST,
+        }
+    }
+}
+
+impl FetchConfig {
+    /// Defaults overridden by the `FETCH_CONCURRENCY`, `FETCH_TIMEOUT_MS`,
+    /// `FETCH_RATE_PER_SEC` and `FETCH_BURST` env vars. Rates that aren't finite and
+    /// positive fall back to the default.
+    pub fn from_env() -> Self {
+        fn var<T: FromStr>(key: &str) -> Option<T> {
+            env::var(key).ok().and_then(|val| val.parse().ok())
+        }
+        let default = Self::default();
+        Self {
+            concurrency: var("FETCH_CONCURRENCY").unwrap_or(default.concurrency),
+            request_timeout: var("FETCH_TIMEOUT_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.request_timeout),
+            // The rate limiter can't wait for a refill that never comes
+            rate_per_sec: var("FETCH_RATE_PER_SEC")
+                .filter(|rate: &f64| rate.is_finite() && *rate > 0_f64)
+                .unwrap_or(default.rate_per_sec),
+            burst: var("FETCH_BURST").unwrap_or(default.burst),
+        }
+    }
+}
+
+/// Calendar to fetch, of a district or a pincode, or only their sessions of a single day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FetchTarget {
+    District(u32),
+    Pincode(u32),
+    DistrictDay(u32),
+    PincodeDay(u32),
+}
+
+impl fmt::Display for FetchTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchTarget::District(district_id) => write!(f, "district {}", district_id),
+            FetchTarget::Pincode(pincode) => write!(f, "pincode {}", pincode),
+            FetchTarget::DistrictDay(district_id) => {
+                write!(f, "sessions of district {}", district_id)
+            }
+            FetchTarget::PincodeDay(pincode) => write!(f, "sessions of pincode {}", pincode),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FetchError<E: std::error::Error + 'static> {
+    #[error("Request failed: {0}")]
+    RequestFail(#[source] E),
+    #[error("Request timed out after {0:?}")]
+    Timeout(Duration),
+}
+
+pub type FetchResult<E> = (FetchTarget, String, Result<CenterResponse, FetchError<E>>);
+
+/// Fetches the calendar of each target on each date concurrently, within the limits
+/// of the `config`. Single day targets fetch only the sessions of the date, grouped
+/// into their centers. Results are in the same order as the `requests`, no matter the
+/// order in which the responses arrive.
+pub async fn fetch_all<Fc: FindCenters>(
+    find_centers: &Fc,
+    requests: Vec<(FetchTarget, String)>,
+    config: &FetchConfig,
+) -> Vec<FetchResult<Fc::Error>> {
+    let rate_limiter = TokenBucket::new(config.burst, config.rate_per_sec);
+    let rate_limiter = &rate_limiter;
+    stream::iter(requests)
+        .map(|(target, date)| async move {
+            rate_limiter.acquire().await;
+            let res = match target {
+                FetchTarget::District(district_id) => {
+                    let district_id = district_id.to_string();
+                    let fut =
+                        find_centers.get_all_centers_by_district_json(&district_id, &date, None);
+                    tokio::time::timeout(config.request_timeout, fut).await
+                }
+                FetchTarget::Pincode(pincode) => {
+                    let pincode = pincode.to_string();
+                    let fut = find_centers.get_all_centers_by_pin_json(&pincode, &date, None);
+                    tokio::time::timeout(config.request_timeout, fut).await
+                }
+                FetchTarget::DistrictDay(district_id) => {
+                    let district_id = district_id.to_string();
+                    let fut = find_centers
+                        .get_sessions_by_district_json(&district_id, &date, None)
+                        .map_ok(CenterResponse::from);
+                    tokio::time::timeout(config.request_timeout, fut).await
+                }
+                FetchTarget::PincodeDay(pincode) => {
+                    let pincode = pincode.to_string();
+                    let fut = find_centers
+                        .get_sessions_by_pin_json(&pincode, &date, None)
+                        .map_ok(CenterResponse::from);
+                    tokio::time::timeout(config.request_timeout, fut).await
+                }
+            };
+            let res = match res {
+                Ok(res) => res.map_err(FetchError::RequestFail),
+                Err(_elapsed) => Err(FetchError::Timeout(config.request_timeout)),
+            };
+            (target, date, res)
+        })
+        .buffered(config.concurrency.max(1))
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, convert::Infallible, sync::Mutex, time::Duration};
+
+    use async_trait::async_trait;
+    use tokio::time::Instant;
+
+    use super::{fetch_all, FetchConfig, FetchError, FetchTarget};
+    use crate::covin::centers::{CenterResponse, FindCenters};
+
+    /// Records when each call starts and ends, taking the `delays` given per
+    /// district to respond, 10ms by default.
+    struct TimedFindCenters {
+        delays: HashMap<String, Duration>,
+        calls: Mutex<Vec<(String, Instant, Instant)>>,
+    }
+
+    impl TimedFindCenters {
+        fn new(delays: &[(&str, u64)]) -> Self {
+            Self {
+                delays: delays
+                    .iter()
+                    .map(|(id, delay)| (id.to_string(), Duration::from_millis(*delay)))
+                    .collect(),
+                calls: Mutex::new(vec![]),
+            }
+        }
+
+        async fn respond(&self, id: &str) -> Result<CenterResponse, Infallible> {
+            let start = Instant::now();
+            let delay = self
+                .delays
+                .get(id)
+                .copied()
+                .unwrap_or_else(|| Duration::from_millis(10));
+            tokio::time::sleep(delay).await;
+            self.calls
+                .lock()
+                .unwrap()
+                .push((id.to_string(), start, Instant::now()));
+            Ok(CenterResponse { centers: vec![] })
+        }
+
+        /// Most calls in flight at the same time.
+        fn max_in_flight(&self) -> usize {
+            let calls = self.calls.lock().unwrap();
+            calls
+                .iter()
+                .map(|(_, start, _)| {
+                    calls
+                        .iter()
+                        .filter(|(_, other_start, other_end)| {
+                            other_start <= start && start < other_end
+                        })
+                        .count()
+                })
+                .max()
+                .unwrap_or_default()
+        }
+
+        fn starts(&self) -> Vec<Instant> {
+            let mut starts = self
+                .calls
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, start, _)| *start)
+                .collect::<Vec<_>>();
+            starts.sort();
+            starts
+        }
+    }
+
+    #[async_trait]
+    impl FindCenters for TimedFindCenters {
+        type Error = Infallible;
+
+        async fn get_all_centers_by_district(
+            &self,
+            _district_id: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<String, Self::Error> {
+            unimplemented!()
+        }
+
+        async fn get_all_centers_by_district_json(
+            &self,
+            district_id: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<CenterResponse, Self::Error> {
+            self.respond(district_id).await
+        }
+
+        async fn get_all_centers_by_pin(
+            &self,
+            _pincode: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<String, Self::Error> {
+            unimplemented!()
+        }
+
+        async fn get_all_centers_by_pin_json(
+            &self,
+            pincode: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<CenterResponse, Self::Error> {
+            self.respond(pincode).await
+        }
+    }
+
+    fn requests(ids: &[u32]) -> Vec<(FetchTarget, String)> {
+        ids.iter()
+            .map(|id| (FetchTarget::District(*id), "14-05-2021".to_string()))
+            .collect()
+    }
+
+    fn config(concurrency: usize, timeout_ms: u64) -> FetchConfig {
+        FetchConfig {
+            concurrency,
+            request_timeout: Duration::from_millis(timeout_ms),
+            rate_per_sec: 1000_f64,
+            burst: 100,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_bounded_concurrency() {
+        // Earlier districts respond slower than the later ones
+        let find_centers =
+            TimedFindCenters::new(&[("1", 60), ("2", 50), ("3", 40), ("4", 30), ("5", 20)]);
+        let results = fetch_all(
+            &find_centers,
+            requests(&[1, 2, 3, 4, 5, 6]),
+            &config(2, 500),
+        )
+        .await;
+
+        assert_eq!(find_centers.max_in_flight(), 2);
+        // Results are in the order of the requests, not of the responses
+        assert_eq!(
+            results
+                .iter()
+                .map(|(target, _, res)| (*target, res.is_ok()))
+                .collect::<Vec<_>>(),
+            (1..=6)
+                .map(|id| (FetchTarget::District(id), true))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_concurrent() {
+        let find_centers = TimedFindCenters::new(&[]);
+        let start = Instant::now();
+        fetch_all(&find_centers, requests(&[1, 2, 3, 4]), &config(4, 500)).await;
+
+        // All four calls overlap instead of running one after another
+        assert_eq!(find_centers.max_in_flight(), 4);
+        assert!(start.elapsed() < Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_timeout() {
+        let find_centers = TimedFindCenters::new(&[("2", 500)]);
+        let results = fetch_all(&find_centers, requests(&[1, 2, 3]), &config(3, 50)).await;
+
+        assert!(results[0].2.is_ok());
+        assert!(matches!(results[1].2, Err(FetchError::Timeout(_))));
+        assert!(results[2].2.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_rate_limit() {
+        let find_centers = TimedFindCenters::new(&[]);
+        let config = FetchConfig {
+            rate_per_sec: 20_f64,
+            burst: 1,
+            ..config(4, 500)
+        };
+        fetch_all(&find_centers, requests(&[1, 2, 3]), &config).await;
+
+        // A request every 50ms, even though the concurrency allows all of them at once
+        let starts = find_centers.starts();
+        assert_eq!(starts.len(), 3);
+        for pair in starts.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(45));
+        }
+    }
+
+    #[test]
+    fn test_fetch_config_rate_from_env() {
+        for rate in &["0", "-1", "NaN", "inf", "fast"] {
+            std::env::set_var("FETCH_RATE_PER_SEC", rate);
+            assert_eq!(FetchConfig::from_env().rate_per_sec, 10_f64, "{}", rate);
+        }
+        std::env::set_var("FETCH_RATE_PER_SEC", "2.5");
+        assert_eq!(FetchConfig::from_env().rate_per_sec, 2.5_f64);
+        std::env::remove_var("FETCH_RATE_PER_SEC");
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use thiserror::Error;
+
+use super::{
+    alert_session::{AlertSession, HeldSession},
+    exclusion_map::{
+        exclusion_map_key, exclusion_map_prefix, is_exclusion_map_key, merge_exclusion_maps,
+        parse_exclusion_map, ExclusionMap, InMemoryExclusionMap, StoredExclusionMap,
+    },
+    renotify::LastNotified,
+    shard::Shard,
+};
+
+#[derive(Debug, Error)]
+pub enum FileExclusionMapError {
+    #[error("Unable to read the exclusion map from {path}")]
+    ReadFail {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Unable to write the exclusion map to {path}")]
+    WriteFail {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Unable to serialize the exclusion map")]
+    SerializeFail(#[from] serde_json::Error),
+}
+
+/// Exclusion map kept in a JSON file on the local disk, in the same format as the S3 one.
+/// The file is replaced by renaming a fully written temporary file over it, so an engine
+/// killed while storing the map leaves the previous map intact.
+pub struct FileExclusionMap {
+    path: PathBuf,
+    exclusion_map: InMemoryExclusionMap,
+}
+
+impl FileExclusionMap {
+    /// Loads the map of the `shard` from the `dir`, at the same relative path as its S3 key.
+    /// A shard without a map of its own yet starts from the maps of the other shard counts.
+    pub async fn init_for_shard(
+        dir: impl AsRef<Path>,
+        shard: &Shard,
+    ) -> Result<Self, FileExclusionMapError> {
+        let dir = dir.as_ref();
+        let key = exclusion_map_key(shard);
+        let path = dir.join(&key);
+        let stored = match read(&path).await? {
+            Some(stored) => stored,
+            None => merge_exclusion_maps(read_other_maps(dir, &key).await?),
+        };
+        Ok(Self::with_stored(path, stored))
+    }
+
+    /// Loads the map from the file at `path`, a missing file is an empty map.
+    #[tracing::instrument(level = "debug")]
+    pub async fn init(path: PathBuf) -> Result<Self, FileExclusionMapError> {
+        let stored = read(&path).await?.unwrap_or_default();
+        Ok(Self::with_stored(path, stored))
+    }
+
+    fn with_stored(path: PathBuf, stored: StoredExclusionMap) -> Self {
+        tracing::debug!(message = "exclusion map", alerts = stored.alerts.len());
+        Self {
+            path,
+            exclusion_map: InMemoryExclusionMap::new().with_stored(stored),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn write(&self, json: Vec<u8>) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        // Temporary file in the same directory, renaming across file systems isn't atomic
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        tokio::fs::write(&tmp_path, json).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await
+    }
+}
+
+/// Map stored in the file at `path`, `None` when there is none.
+async fn read(path: &Path) -> Result<Option<StoredExclusionMap>, FileExclusionMapError> {
+    match tokio::fs::read(path).await {
+        Ok(body) => Ok(Some(parse_exclusion_map(&body, Utc::now()))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(FileExclusionMapError::ReadFail {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
+
+/// Maps stored in the `dir` at the paths of the other shard counts than the one at `key`.
+async fn read_other_maps(
+    dir: &Path,
+    key: &str,
+) -> Result<Vec<StoredExclusionMap>, FileExclusionMapError> {
+    let mut keys = vec![exclusion_map_key(&Shard::default())];
+    let shard_dir = dir.join(exclusion_map_prefix());
+    let read_dir_fail = |source| FileExclusionMapError::ReadFail {
+        path: shard_dir.clone(),
+        source,
+    };
+    match tokio::fs::read_dir(&shard_dir).await {
+        Ok(mut entries) => {
+            while let Some(entry) = entries.next_entry().await.map_err(read_dir_fail)? {
+                let file_name = entry.file_name();
+                keys.push(format!(
+                    "{}/{}",
+                    exclusion_map_prefix(),
+                    file_name.to_string_lossy()
+                ));
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(source) => return Err(read_dir_fail(source)),
+    }
+    let mut maps = vec![];
+    for other_key in keys
+        .iter()
+        .filter(|other_key| is_exclusion_map_key(other_key) && *other_key != key)
+    {
+        if let Some(stored) = read(&dir.join(other_key)).await? {
+            maps.push(stored);
+        }
+    }
+    Ok(maps)
+}
+
+#[async_trait]
+impl ExclusionMap for FileExclusionMap {
+    type Error = FileExclusionMapError;
+
+    fn add(&mut self, alert_key: &str, sessions: &[AlertSession], notified_at: DateTime<Utc>) {
+        self.exclusion_map.add(alert_key, sessions, notified_at)
+    }
+
+    fn last_notified(&self, alert_key: &str, session_id: &str) -> Option<LastNotified> {
+        self.exclusion_map.last_notified(alert_key, session_id)
+    }
+
+    async fn deplete(
+        &mut self,
+        alert_key: &str,
+        session_ids: &[String],
+    ) -> Result<(), Self::Error> {
+        self.exclusion_map.mark_depleted(alert_key, session_ids);
+        Ok(())
+    }
+
+    async fn hold(
+        &mut self,
+        alert_key: &str,
+        sessions: &[AlertSession],
+        held_at: DateTime<Utc>,
+    ) -> Result<(), Self::Error> {
+        self.exclusion_map
+            .hold_sessions(alert_key, sessions, held_at);
+        Ok(())
+    }
+
+    fn held(&self, alert_key: &str) -> Vec<HeldSession> {
+        self.exclusion_map.held_sessions_of(alert_key)
+    }
+
+    async fn clear_held(&mut self, alert_key: &str) -> Result<(), Self::Error> {
+        self.exclusion_map.clear_held_sessions(alert_key);
+        Ok(())
+    }
+
+    fn notifications_since(&self, alert_key: &str, since: DateTime<Utc>) -> usize {
+        self.exclusion_map.notifications_since(alert_key, since)
+    }
+
+    fn expire(&mut self, today: NaiveDate, now: DateTime<Utc>) {
+        self.exclusion_map.expire(today, now)
+    }
+
+    #[tracing::instrument(level = "debug", skip(self), fields(path = %self.path.display()))]
+    async fn store(&self) -> Result<(), Self::Error> {
+        if !self.exclusion_map.is_dirty() {
+            tracing::debug!(message = "No change in the exclusion_map, not storing it");
+            return Ok(());
+        }
+        let json = self.exclusion_map.to_json()?;
+        self.write(json)
+            .await
+            .map_err(|source| FileExclusionMapError::WriteFail {
+                path: self.path.clone(),
+                source,
+            })?;
+        tracing::debug!(message = "Stored the exclusion_map");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, path::PathBuf};
+
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use super::FileExclusionMap;
+    use crate::{
+        alert_engine::{
+            alert_session::AlertSession,
+            exclusion_map::{test::AnyVariance, ExclusionMap},
+            shard::Shard,
+        },
+        covin::centers::{Center, Session},
+    };
+
+    fn temp_dir() -> PathBuf {
+        env::temp_dir().join(format!(
+            "covin-exclusion-map-{}",
+            Utc::now().timestamp_nanos()
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_file_exclusion_map() {
+        let dir = temp_dir();
+        let shard = Shard::new(1, 2).unwrap();
+        let now = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
+        let center = Center::default();
+        let session = Session {
+            session_id: "session-id-1".to_string(),
+            date: "14-05-2021".to_string(),
+            available_capacity: 5_f32,
+            ..Default::default()
+        };
+
+        // Missing file is an empty map, which isn't written until it changes
+        let mut exclusion_map = FileExclusionMap::init_for_shard(&dir, &shard)
+            .await
+            .unwrap();
+        assert_eq!(
+            exclusion_map.path(),
+            dir.join("exclusion_map/shard-1-of-2.json")
+        );
+        assert!(exclusion_map.any_variance("alert-1", "session-id-1", 5_f32, now));
+        exclusion_map.store().await.unwrap();
+        assert!(!exclusion_map.path().exists());
+
+        exclusion_map.add("alert-1", &[AlertSession::from((&session, &center))], now);
+        exclusion_map.store().await.unwrap();
+
+        // The next run picks up the stored map, without any temporary file left behind
+        let mut exclusion_map = FileExclusionMap::init_for_shard(&dir, &shard)
+            .await
+            .unwrap();
+        assert!(!exclusion_map.any_variance("alert-1", "session-id-1", 5_f32, now));
+        assert!(!dir.join("exclusion_map/shard-1-of-2.json.tmp").exists());
+
+        // Expired entries are dropped from the stored map
+        exclusion_map.expire(NaiveDate::from_ymd(2021, 5, 15), now);
+        exclusion_map.store().await.unwrap();
+        let exclusion_map = FileExclusionMap::init_for_shard(&dir, &shard)
+            .await
+            .unwrap();
+        assert!(exclusion_map.any_variance("alert-1", "session-id-1", 5_f32, now));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_exclusion_map_seeded_from_other_shards() {
+        let dir = temp_dir();
+        let now = Utc.ymd(2021, 5, 13).and_hms(20, 0, 0);
+        let center = Center::default();
+        let session = |session_id: &str| Session {
+            session_id: session_id.to_string(),
+            date: "14-05-2021".to_string(),
+            available_capacity: 5_f32,
+            ..Default::default()
+        };
+        let (session_1, session_2) = (session("session-id-1"), session("session-id-2"));
+
+        // Alerted by the single shard, then by a shard of two
+        let mut exclusion_map = FileExclusionMap::init_for_shard(&dir, &Shard::default())
+            .await
+            .unwrap();
+        exclusion_map.add("alert-1", &[AlertSession::from((&session_1, &center))], now);
+        exclusion_map.store().await.unwrap();
+        let mut exclusion_map = FileExclusionMap::init_for_shard(&dir, &Shard::new(0, 2).unwrap())
+            .await
+            .unwrap();
+        exclusion_map.add("alert-2", &[AlertSession::from((&session_2, &center))], now);
+        exclusion_map.store().await.unwrap();
+
+        // A shard of another shard count carries over what every shard alerted
+        let exclusion_map = FileExclusionMap::init_for_shard(&dir, &Shard::new(2, 3).unwrap())
+            .await
+            .unwrap();
+        assert!(exclusion_map
+            .last_notified("alert-1", "session-id-1")
+            .is_some());
+        assert!(exclusion_map
+            .last_notified("alert-2", "session-id-2")
+            .is_some());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great-circle distance in kilometers between two `(lat, long)` coordinates in degrees,
+/// computed with the haversine formula.
+pub fn haversine_km((lat1, long1): (f64, f64), (lat2, long2): (f64, f64)) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_long = (long2 - long1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_long / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+#[cfg(test)]
+mod test {
+    use super::haversine_km;
+
+    #[test]
+    fn test_haversine_km() {
+        let kochi = (9.9312, 76.2673);
+        let thiruvananthapuram = (8.5241, 76.9366);
+
+        assert!(haversine_km(kochi, kochi).abs() < 1e-9);
+
+        let distance = haversine_km(kochi, thiruvananthapuram);
+        assert!((distance - 172.85).abs() < 0.01, "distance: {}", distance);
+
+        // Distance is symmetric
+        let reverse = haversine_km(thiruvananthapuram, kochi);
+        assert!((distance - reverse).abs() < 1e-9);
+    }
+}
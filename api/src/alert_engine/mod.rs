@@ -1,278 +1,1028 @@
 pub mod alert_session;
+pub mod chat_client;
+pub mod clock;
+pub mod dynamo_exclusion_map;
 pub mod email_client;
 pub mod exclusion_map;
+pub mod fetch;
+pub mod file_exclusion_map;
+pub mod geo;
+pub mod notifier;
+pub mod rate_limiter;
+pub mod renotify;
+pub mod run_report;
+pub mod shard;
+pub mod sms_client;
 pub mod template_engine;
+pub mod webhook_client;
 
-use std::collections::HashMap;
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+};
 
 use anyhow::Error;
-use chrono::{FixedOffset, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Timelike, Utc};
 
 use crate::{
-    alert_engine::alert_session::AlertSession,
-    api::alerts::{AlertFilter, DoseFilter, GetAlertsError},
-    covin::centers::{Center, FindCenters},
+    alert_engine::alert_session::{AlertSession, HeldSession},
+    api::alerts::{
+        AlertFilter, DaysOfWeekFilter, DeliveryMode, DoseFilter, GeoFilter, GetAlertsError,
+        RenotifyTrigger,
+    },
+    covin::centers::{Center, FindCenters, Session},
 };
 
 use self::{
-    email_client::EmailClient, exclusion_map::ExclusionMap, template_engine::TemplateEngine,
+    clock::Clock,
+    exclusion_map::ExclusionMap,
+    fetch::{fetch_all, FetchConfig, FetchTarget},
+    notifier::{Notification, Notifier},
+    run_report::RunReport,
+    shard::Shard,
 };
 
-const HOUR: i32 = 3600;
+/// Date format used by CoWIN, both in the calendar query and the session dates.
+const DATE_FORMAT: &str = "%d-%m-%Y";
+
+/// Calendar APIs of CoWIN return the sessions of seven days starting from the given date.
+const DAYS_PER_CALENDAR: u32 = 7;
+
+/// Number of calendar weeks to fetch to cover the date window of an alert,
+/// alerts without a date window watch a single calendar week. Alerts watching only
+/// today need no calendar, the sessions of the day are cheaper to fetch.
+fn weeks_to_fetch(alert: &AlertFilter) -> u32 {
+    match alert.days_ahead {
+        Some(days_ahead) if days_ahead <= 1 => 0,
+        days_ahead => {
+            let days_ahead = days_ahead.unwrap_or(DAYS_PER_CALENDAR);
+            (days_ahead + DAYS_PER_CALENDAR - 1) / DAYS_PER_CALENDAR
+        }
+    }
+}
+
+/// Requests of the calendar `weeks` starting from `today`, or of the sessions of `today`
+/// alone when no week is needed.
+fn fetch_requests(
+    calendar: FetchTarget,
+    day: FetchTarget,
+    weeks: u32,
+    today: NaiveDate,
+) -> Vec<(FetchTarget, String)> {
+    if weeks == 0 {
+        return vec![(day, today.format(DATE_FORMAT).to_string())];
+    }
+    (0..weeks)
+        .map(|week| {
+            let date = today + Duration::days((week * DAYS_PER_CALENDAR).into());
+            (calendar, date.format(DATE_FORMAT).to_string())
+        })
+        .collect()
+}
+
+/// Centers with at least one session, keyed by `center_id`. Sessions without capacity
+/// are kept as well, to tell when a session alerted before runs out of capacity.
+type CenterMap = HashMap<u32, Center>;
+
+/// Adds the `centers` of a calendar week to the `center_map`, sessions of a center
+/// already in the map are appended to it.
+fn extend_center_map(center_map: &mut CenterMap, centers: Vec<Center>) {
+    centers
+        .into_iter()
+        .filter(|center| !center.sessions.is_empty())
+        .for_each(|center| match center_map.entry(center.center_id) {
+            Entry::Occupied(mut entry) => entry.get_mut().sessions.extend(center.sessions),
+            Entry::Vacant(entry) => {
+                entry.insert(center);
+            }
+        })
+}
+
+/// Whether the session has capacity for the dose of the alert.
+fn has_capacity(dose: &DoseFilter, session: &Session) -> bool {
+    let capacity = match dose {
+        DoseFilter::Any => session.available_capacity,
+        DoseFilter::First => session.available_capacity_dose1,
+        DoseFilter::Second => session.available_capacity_dose2,
+    };
+    1_f32.le(&capacity)
+}
+
+/// Whether the alert is held back at `now`, during its quiet hours or once it reached its
+/// limit of notifications. Sessions of an alert held back are held in the exclusion map,
+/// and sent together in a digest in the first run after the alert is let through again,
+/// even the ones closed in the meantime.
+fn is_deferred<Em: ExclusionMap>(
+    alert: &AlertFilter,
+    exclusion_map: &Em,
+    now: DateTime<Utc>,
+) -> bool {
+    let hour = now.with_timezone(&clock::ist_offset()).hour();
+    if let Some(quiet_hours) = &alert.quiet_hours {
+        if quiet_hours.contains(hour) {
+            return true;
+        }
+    }
+    let alert_key = alert.alert_key();
+    let limits = [
+        (alert.max_per_hour, Duration::hours(1)),
+        (alert.max_per_day, Duration::days(1)),
+    ];
+    limits.iter().any(|(max, period)| {
+        max.map(|max| exclusion_map.notifications_since(&alert_key, now - *period) >= max as usize)
+            .unwrap_or(false)
+    })
+}
+
+/// Whether sessions can be held for the alert, which are the alerts which can be held
+/// back and the digest alerts.
+fn holds_sessions(alert: &AlertFilter) -> bool {
+    alert.quiet_hours.is_some()
+        || alert.max_per_hour.is_some()
+        || alert.max_per_day.is_some()
+        || alert.delivery == DeliveryMode::Digest
+}
+
+/// Channels and destinations of an alert, the digest alerts of a user sharing all of them
+/// share a digest.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct DigestKey {
+    user_id: String,
+    channels: [bool; 4],
+    email: String,
+    mobile_no: Option<String>,
+    webhook: Option<(String, String)>,
+    telegram_chat_id: Option<String>,
+}
+
+impl DigestKey {
+    fn new(alert: &AlertFilter) -> Self {
+        let channels = &alert.channels;
+        Self {
+            user_id: alert.user_id.clone(),
+            channels: [
+                channels.email,
+                channels.sms,
+                channels.webhook,
+                channels.chat,
+            ],
+            email: alert.email.clone(),
+            mobile_no: alert.mobile_no.clone(),
+            webhook: alert.webhook_url.clone().zip(alert.webhook_secret.clone()),
+            telegram_chat_id: alert.telegram_chat_id.clone(),
+        }
+    }
+}
+
+/// Sessions of the digest alerts, merged into a single digest per user and destinations.
+/// A digest is sent through the channels of the first of its alerts, whose keys it lists,
+/// with every session once, ordered by the date of the session. The first state of a
+/// session in `matches` is kept.
+fn group_digests<'a>(
+    matches: Vec<(AlertFilter, Vec<AlertSession<'a, 'a>>)>,
+) -> Vec<(AlertFilter, Vec<String>, Vec<AlertSession<'a, 'a>>)> {
+    let mut digests = BTreeMap::<DigestKey, (AlertFilter, Vec<String>, Vec<AlertSession>)>::new();
+    for (alert, sessions) in matches {
+        let alert_key = alert.alert_key();
+        let digest = digests
+            .entry(DigestKey::new(&alert))
+            .or_insert_with(|| (alert, vec![], vec![]));
+        digest.1.push(alert_key);
+        digest.2.extend(sessions);
+    }
+    digests
+        .into_iter()
+        .map(|(_digest_key, (alert, alert_keys, mut sessions))| {
+            let mut session_ids = HashSet::new();
+            sessions.retain(|alert_session| {
+                session_ids.insert(alert_session.session.session_id.clone())
+            });
+            sessions.sort_by_key(|alert_session| {
+                NaiveDate::parse_from_str(&alert_session.session.date, DATE_FORMAT).ok()
+            });
+            (alert, alert_keys, sessions)
+        })
+        .filter(|(_alert, _alert_keys, sessions)| !sessions.is_empty())
+        .collect()
+}
+
+/// Sessions from the `center_maps` watched by an alert, which satisfies all the filters
+/// of the alert but the capacity. Sessions already alerted are left out later on, as per
+/// the exclusion map.
+fn find_watched_sessions<'a>(
+    alert: &AlertFilter,
+    center_maps: &[&'a CenterMap],
+    today: NaiveDate,
+) -> Vec<AlertSession<'a, 'a>> {
+    let AlertFilter {
+        centers,
+        location,
+        age,
+        vaccine,
+        fee_type,
+        max_fee,
+        days_ahead,
+        days_of_week,
+        ..
+    } = alert;
+
+    let centers_to_check = match centers {
+        Some(centers) => centers
+            .iter()
+            .filter_map(|center_id| {
+                center_maps
+                    .iter()
+                    .find_map(|center_map| center_map.get(center_id))
+            })
+            .collect::<Vec<&Center>>(),
+        None => {
+            let mut centers = center_maps
+                .iter()
+                .flat_map(|center_map| center_map.values())
+                .collect::<Vec<&Center>>();
+            centers.sort_by_key(|center| center.center_id);
+            centers.dedup_by_key(|center| center.center_id);
+            centers
+        }
+    };
+
+    // Filter centers within the radius of the alert location, nearest centers first
+    let centers_to_check = match location {
+        Some(GeoFilter {
+            lat,
+            long,
+            radius_km,
+            ..
+        }) => {
+            let mut centers = centers_to_check
+                .into_iter()
+                .map(|center| {
+                    let distance_km =
+                        geo::haversine_km((*lat, *long), (center.lat.into(), center.long.into()));
+                    (center, Some(distance_km))
+                })
+                .filter(|(_center, distance_km)| {
+                    distance_km
+                        .map(|distance_km| distance_km.le(radius_km))
+                        .unwrap_or(false)
+                })
+                .collect::<Vec<_>>();
+            centers.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+            centers
+        }
+        None => centers_to_check
+            .into_iter()
+            .map(|center| (center, None))
+            .collect::<Vec<_>>(),
+    };
 
-fn get_date_today() -> String {
-    let ist_offset = FixedOffset::east(5 * HOUR + HOUR / 2);
-    let ist_date_tomorrow = Utc::now() + ist_offset;
-    ist_date_tomorrow.format("%d-%m-%Y").to_string()
+    centers_to_check
+        .into_iter()
+        .flat_map(|(center, distance_km)| {
+            center.sessions.iter().map(move |session| AlertSession {
+                distance_km,
+                ..AlertSession::from((session, center))
+            })
+        })
+        // Filter age requirement
+        .filter(|alert_session| {
+            age.map(|age| age.ge(&alert_session.session.min_age_limit))
+                .unwrap_or(true)
+        })
+        // Filter vaccine type
+        .filter(|alert_session| vaccine.matches(&alert_session.session.vaccine))
+        // Filter fee type
+        .filter(|alert_session| fee_type.matches(&alert_session.center.fee_type))
+        // Filter maximum fee, a paid center without a published fee for the vaccine is not excluded
+        .filter(|alert_session| {
+            let AlertSession {
+                session, center, ..
+            } = alert_session;
+            max_fee
+                .and_then(|max_fee| center.fee_for(&session.vaccine).map(|fee| fee.le(&max_fee)))
+                .unwrap_or(true)
+        })
+        // Filter session date within the date window
+        .filter(|alert_session| {
+            match NaiveDate::parse_from_str(&alert_session.session.date, DATE_FORMAT) {
+                Ok(date) => {
+                    let days_ahead = days_ahead.unwrap_or(DAYS_PER_CALENDAR);
+                    today <= date
+                        && date < today + Duration::days(days_ahead.into())
+                        && days_of_week.matches(&date)
+                }
+                // A session without a valid date can only be alerted when there is no date window
+                Err(_) => days_ahead.is_none() && *days_of_week == DaysOfWeekFilter::Any,
+            }
+        })
+        .collect::<Vec<_>>()
 }
 
-pub struct AlertEngine<GaFn, GaFnFut, Fc, Em, Ec, Te>
+pub struct AlertEngine<GaFn, GaFnFut, Fc, Em, Ck>
 where
     GaFn: Fn() -> GaFnFut,
     GaFnFut: futures::Future<Output = Result<Vec<AlertFilter>, GetAlertsError>>,
     Fc: FindCenters,
     Em: ExclusionMap,
-    Ec: EmailClient,
-    Te: TemplateEngine,
+    Ck: Clock,
 {
     exclusion_map: Em,
-    template_engine: Te,
-    email_client: Ec,
+    notifier: Notifier,
     find_centers: Fc,
+    fetch_config: FetchConfig,
+    shard: Shard,
+    delivery: DeliveryMode,
     get_alerts: GaFn,
+    clock: Ck,
 }
 
-impl<GaFn, GaFnFut, Fc, Em, Ec, Te> AlertEngine<GaFn, GaFnFut, Fc, Em, Ec, Te>
+impl<GaFn, GaFnFut, Fc, Em, Ck> AlertEngine<GaFn, GaFnFut, Fc, Em, Ck>
 where
     GaFn: Fn() -> GaFnFut,
     GaFnFut: futures::Future<Output = Result<Vec<AlertFilter>, GetAlertsError>>,
     Fc: FindCenters,
     Em: ExclusionMap,
-    Ec: EmailClient,
-    Te: TemplateEngine,
+    Ck: Clock,
 {
     pub fn new(
         get_alerts: GaFn,
         find_centers: Fc,
         exclusion_map: Em,
-        email_client: Ec,
-        template_engine: Te,
+        notifier: Notifier,
+        clock: Ck,
     ) -> Self {
         Self {
             exclusion_map,
-            template_engine,
-            email_client,
+            notifier,
             find_centers,
+            fetch_config: FetchConfig::default(),
+            shard: Shard::default(),
+            delivery: DeliveryMode::default(),
             get_alerts,
+            clock,
         }
     }
 
+    /// Replaces the default limits of fetching the calendars.
+    pub fn with_fetch_config(mut self, fetch_config: FetchConfig) -> Self {
+        self.fetch_config = fetch_config;
+        self
+    }
+
+    /// Limits the run to the alerts of the districts in the `shard`.
+    pub fn with_shard(mut self, shard: Shard) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    /// Sets the `delivery` mode of the run. Realtime runs alert the realtime alerts and
+    /// hold the sessions of the digest alerts, digest runs send the sessions held for the
+    /// digest alerts along with the ones open then, in a single digest per user and
+    /// destinations.
+    pub fn with_delivery(mut self, delivery: DeliveryMode) -> Self {
+        self.delivery = delivery;
+        self
+    }
+
+    /// Sends the alerts for the sessions available now. Failures to fetch a district or
+    /// to send an alert are isolated and collected in the report, so the exclusion map is
+    /// stored for all the alerts sent in the run regardless.
     #[tracing::instrument(level = "debug", skip(self))]
-    pub async fn run(&mut self) -> Result<(), Error> {
+    pub async fn run(&mut self) -> Result<RunReport, Error> {
         let exclusion_map = &mut self.exclusion_map;
-        let tera = &self.template_engine;
-        let ses_client = &mut self.email_client;
+        let notifier = &mut self.notifier;
         let find_centers = &self.find_centers;
 
-        let date_today = get_date_today();
+        let now = self.clock.now();
+        let date_today = self.clock.today();
+        exclusion_map.expire(date_today, now);
         let get_alerts = &self.get_alerts;
-        let alerts = get_alerts().await?;
-
-        let grouped = alerts.into_iter().fold(
-            HashMap::<u32, Vec<AlertFilter>>::new(),
-            |mut grouped, alert| {
-                let AlertFilter { district_id, .. } = alert;
-                if let Some(vals) = grouped.get_mut(&district_id) {
-                    vals.push(alert);
-                } else {
-                    grouped.insert(district_id, vec![alert]);
-                }
-                grouped
-            },
-        );
+        let shard = self.shard;
+        let delivery = self.delivery;
+        // An alert belongs to the shard of its district, even when it watches pincodes
+        // or nearby districts, so that it is sent by exactly one of the shards.
+        let alerts = get_alerts()
+            .await?
+            .into_iter()
+            .filter(|alert| shard.contains_district(alert.district_id))
+            .filter(|alert| delivery == DeliveryMode::Realtime || alert.delivery == delivery)
+            .collect::<Vec<_>>();
+        let mut report = RunReport {
+            shard,
+            delivery,
+            alerts_checked: alerts.len(),
+            ..Default::default()
+        };
 
-        for (district_id, alerts) in grouped {
-            let res = find_centers
-                .get_all_centers_by_district_json(&format!("{}", district_id), &date_today, None)
-                .await;
+        // Every district and pincode is fetched only once per run, no matter how many
+        // alerts are watching it, for as many weeks as the widest date window needs.
+        // When all of them watch only today, just the sessions of the day are fetched.
+        let mut district_weeks = BTreeMap::<u32, u32>::new();
+        let mut pincode_weeks = BTreeMap::<u32, u32>::new();
+        for alert in alerts.iter() {
+            let weeks = weeks_to_fetch(alert);
+            let district_ids = alert
+                .pincodes
+                .is_none()
+                .then(|| alert.district_id)
+                .into_iter()
+                .chain(
+                    alert
+                        .location
+                        .iter()
+                        .flat_map(|location| location.nearby_districts.iter().copied()),
+                );
+            for district_id in district_ids {
+                let entry = district_weeks.entry(district_id).or_insert(weeks);
+                *entry = weeks.max(*entry);
+            }
+            for pincode in alert.pincodes.iter().flatten().copied() {
+                let entry = pincode_weeks.entry(pincode).or_insert(weeks);
+                *entry = weeks.max(*entry);
+            }
+        }
+        let requests = district_weeks
+            .into_iter()
+            .flat_map(|(district_id, weeks)| {
+                fetch_requests(
+                    FetchTarget::District(district_id),
+                    FetchTarget::DistrictDay(district_id),
+                    weeks,
+                    date_today,
+                )
+            })
+            .chain(pincode_weeks.into_iter().flat_map(|(pincode, weeks)| {
+                fetch_requests(
+                    FetchTarget::Pincode(pincode),
+                    FetchTarget::PincodeDay(pincode),
+                    weeks,
+                    date_today,
+                )
+            }))
+            .collect::<Vec<_>>();
+        let results = fetch_all(find_centers, requests, &self.fetch_config).await;
 
+        // Results are in the order of the requests, so the sessions of a center are
+        // merged in the same order in every run, however the responses arrive.
+        let mut district_centers = HashMap::<u32, CenterMap>::new();
+        let mut pincode_centers = HashMap::<u32, CenterMap>::new();
+        for (target, date, res) in results {
             match res {
                 Ok(res) => {
-                    let centers = res.centers;
-                    if !centers.is_empty() {
-                        let center_map = centers
-                            .into_iter()
-                            .filter(|center| {
-                                center
-                                    .sessions
-                                    .iter()
-                                    .any(|session| session.available_capacity >= 1_f32)
-                            })
-                            .fold(HashMap::<u32, Center>::new(), |mut center_map, center| {
-                                let Center { center_id, .. } = center;
-                                center_map.insert(center_id, center);
-                                center_map
-                            });
-
-                        for alert in alerts {
-                            let AlertFilter {
-                                user_id,
-                                centers,
-                                age,
-                                email,
-                                dose,
-                                ..
-                            } = alert;
-
-                            let centers_to_check = centers
-                                .as_ref()
-                                .map(|centers| centers.iter().copied().collect::<Vec<u32>>())
-                                .unwrap_or_else(|| {
-                                    center_map.keys().into_iter().copied().collect::<Vec<u32>>()
-                                });
-                            let sessions_to_alert = centers_to_check
-                                .iter()
-                                .map(|center_id| center_map.get(center_id))
-                                .filter(|center| {
-                                    center
-                                        .map(|center| center.sessions.len().ge(&1))
-                                        .unwrap_or(false)
-                                })
-                                // We can safely call `.unwrap()` here since all the sessions that will reach here
-                                // should have `Some(..)` in it, so safe to `.unwrap()` at this point.
-                                .map(|center| center.unwrap())
-                                .map(|center| {
-                                    center
-                                        .sessions
-                                        .iter()
-                                        .map(|session| AlertSession::from((session, center)))
-                                        // Filter dose availability
-                                        .filter(|alert_session| match dose {
-                                            DoseFilter::Any => {
-                                                1_f32.le(&alert_session.session.available_capacity)
-                                            }
-                                            DoseFilter::First => 1_f32.le(&alert_session
-                                                .session
-                                                .available_capacity_dose1),
-                                            DoseFilter::Second => 1_f32.le(&alert_session
-                                                .session
-                                                .available_capacity_dose2),
-                                        })
-                                        // Filter age requirement
-                                        .filter(|alert_session| {
-                                            age.map(|age| {
-                                                age.ge(&alert_session.session.min_age_limit)
-                                            })
-                                            .unwrap_or(true)
-                                        })
-                                        // Filter if same alert has been sent already
-                                        .filter(|alert_session| {
-                                            let AlertSession { session, .. } = alert_session;
-                                            exclusion_map.any_variance(
-                                                &user_id,
-                                                &session.session_id,
-                                                session.available_capacity,
-                                            )
-                                        })
-                                        .collect::<Vec<_>>()
-                                })
-                                .flatten()
-                                .collect::<Vec<AlertSession>>();
-                            if !sessions_to_alert.is_empty() {
-                                let content = tera.generate_alert_content(&sessions_to_alert)?;
-                                tracing::debug!(message = "Found centers for user", %user_id, %email, ?centers, ?sessions_to_alert);
-                                ses_client.send_alert_email(&email, &content).await?;
-                                exclusion_map.add(&user_id, &sessions_to_alert);
-                            } else {
-                                tracing::debug!(message = "No centers found for user", %user_id, %email, ?centers);
-                            }
+                    if res.centers.is_empty() {
+                        tracing::debug!(message = "No centers found", %target, %date);
+                    }
+                    let center_map = match target {
+                        FetchTarget::District(district_id)
+                        | FetchTarget::DistrictDay(district_id) => {
+                            district_centers.entry(district_id).or_default()
+                        }
+                        FetchTarget::Pincode(pincode) | FetchTarget::PincodeDay(pincode) => {
+                            pincode_centers.entry(pincode).or_default()
+                        }
+                    };
+                    extend_center_map(center_map, res.centers);
+                }
+                Err(err) => {
+                    tracing::error!(message = "An error occured while fetching centers", %target, %date, error = ?err);
+                    report.fetches_failed += 1;
+                    report.add_error(format_args!("{} on {}", target, date), err);
+                }
+            }
+        }
+
+        report.districts_fetched = district_centers.len();
+        report.pincodes_fetched = pincode_centers.len();
+
+        let matches = alerts
+            .into_iter()
+            .map(|alert| {
+                // Radius based alerts look into every center fetched in this run,
+                // which includes the neighbouring districts of the alert.
+                let center_maps = match (&alert.location, &alert.pincodes) {
+                    (Some(_), _) => district_centers
+                        .values()
+                        .chain(pincode_centers.values())
+                        .collect::<Vec<_>>(),
+                    (None, Some(pincodes)) => pincodes
+                        .iter()
+                        .filter_map(|pincode| pincode_centers.get(pincode))
+                        .collect::<Vec<_>>(),
+                    (None, None) => district_centers
+                        .get(&alert.district_id)
+                        .into_iter()
+                        .collect::<Vec<_>>(),
+                };
+                let (sessions, depleted): (Vec<_>, Vec<_>) =
+                    find_watched_sessions(&alert, &center_maps, date_today)
+                        .into_iter()
+                        .partition(|alert_session| {
+                            has_capacity(&alert.dose, alert_session.session)
+                        });
+                // Sessions out of capacity matter only to alerts waiting for a restock
+                let depleted = match alert.renotify.trigger {
+                    RenotifyTrigger::Restock => depleted
+                        .into_iter()
+                        .map(|alert_session| alert_session.session.session_id.clone())
+                        .collect(),
+                    _ => vec![],
+                };
+                (alert, sessions, depleted)
+            })
+            .collect::<Vec<_>>();
+
+        // Digests sum up the sessions held by the realtime runs since the last digest along with
+        // the ones open now, whether or not they are still open, and leave the exclusions
+        // of the realtime alerts alone.
+        if delivery == DeliveryMode::Digest {
+            let alert_keys = matches
+                .iter()
+                .map(|(alert, _sessions, _depleted)| alert.alert_key())
+                .collect::<Vec<_>>();
+            exclusion_map.load_held(&alert_keys, now).await?;
+            let held = alert_keys
+                .iter()
+                .map(|alert_key| exclusion_map.held(alert_key))
+                .collect::<Vec<_>>();
+            let digests = group_digests(
+                matches
+                    .into_iter()
+                    .zip(held.iter())
+                    .map(|((alert, mut sessions, _depleted), held)| {
+                        sessions.extend(held.iter().map(HeldSession::alert_session));
+                        (alert, sessions)
+                    })
+                    .collect(),
+            );
+            for (alert, alert_keys, sessions) in digests {
+                let user_id = &alert.user_id;
+                report.sessions_matched += sessions.len();
+                let notify_report = notifier
+                    .notify(&Notification {
+                        alert: &alert,
+                        sessions: &sessions,
+                        generated_at: now,
+                        delivery,
+                    })
+                    .await;
+                if notify_report.is_notified() {
+                    for alert_key in alert_keys.iter() {
+                        if let Err(err) = exclusion_map.clear_held(alert_key).await {
+                            tracing::error!(message = "An error occured while clearing held sessions", %alert_key, error = ?err);
+                            report.add_error(format_args!("{} clear held", alert_key), err);
                         }
-                    } else {
-                        tracing::debug!(message = "No centers found in district", %district_id);
                     }
+                } else {
+                    tracing::error!(message = "An error occured while sending digest", %user_id, failed_channels = ?notify_report.failed_channels());
+                }
+                report.add_notify_report(&format!("{} digest", user_id), &notify_report);
+            }
+            tracing::info!(message = "Completed alert engine digest run", ?report);
+            exclusion_map.store().await?;
+            return Ok(report);
+        }
+
+        // Entries of all the matched sessions are loaded at once, for the maps which
+        // don't hold every entry in memory.
+        let exclusion_keys = matches
+            .iter()
+            .flat_map(|(alert, sessions, depleted)| {
+                let alert_key = alert.alert_key();
+                sessions
+                    .iter()
+                    .map(|session| &session.session.session_id)
+                    .chain(depleted.iter())
+                    .map(move |session_id| (alert_key.clone(), session_id.clone()))
+            })
+            .collect::<Vec<_>>();
+        exclusion_map.load(&exclusion_keys, now).await?;
+        let held_keys = matches
+            .iter()
+            .filter(|(alert, _sessions, _depleted)| holds_sessions(alert))
+            .map(|(alert, _sessions, _depleted)| alert.alert_key())
+            .collect::<Vec<_>>();
+        exclusion_map.load_held(&held_keys, now).await?;
+
+        for (alert, sessions, depleted) in matches {
+            let alert_key = alert.alert_key();
+            if !depleted.is_empty() {
+                if let Err(err) = exclusion_map.deplete(&alert_key, &depleted).await {
+                    tracing::error!(message = "An error occured while marking sessions depleted", user_id = %alert.user_id, alert_id = %alert.alert_id, error = ?err);
+                    report.add_error(format_args!("{} deplete", alert_key), err);
+                }
+            }
+            // Filter if same alert has been sent already
+            let sessions_to_alert = sessions
+                .into_iter()
+                .filter(|alert_session| {
+                    let AlertSession { session, .. } = alert_session;
+                    exclusion_map.should_notify(
+                        &alert_key,
+                        &session.session_id,
+                        session.available_capacity,
+                        &alert.renotify,
+                        now,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let AlertFilter {
+                user_id,
+                alert_id,
+                centers,
+                email,
+                ..
+            } = &alert;
+            // Sessions of the digest alerts are held for the digest run
+            if alert.delivery == DeliveryMode::Digest {
+                if let Err(err) = exclusion_map
+                    .hold(&alert_key, &sessions_to_alert, now)
+                    .await
+                {
+                    tracing::error!(message = "An error occured while holding sessions", %user_id, %alert_id, error = ?err);
+                    report.add_error(format_args!("{} hold", alert_key), err);
+                }
+                continue;
+            }
+            if is_deferred(&alert, exclusion_map, now) {
+                if sessions_to_alert.is_empty() {
+                    continue;
+                }
+                tracing::debug!(message = "Alert held back by its quiet hours or limits", %user_id, %alert_id, sessions = sessions_to_alert.len());
+                report.alerts_deferred += 1;
+                if let Err(err) = exclusion_map
+                    .hold(&alert_key, &sessions_to_alert, now)
+                    .await
+                {
+                    tracing::error!(message = "An error occured while holding sessions", %user_id, %alert_id, error = ?err);
+                    report.add_error(format_args!("{} hold", alert_key), err);
+                }
+                continue;
+            }
+
+            // Sessions held back before go along with the ones open now in a digest,
+            // the latest state of a session held back which is still open is sent.
+            let held = exclusion_map.held(&alert_key);
+            let delivery = if held.is_empty() {
+                delivery
+            } else {
+                DeliveryMode::Digest
+            };
+            let mut held_sessions = held
+                .iter()
+                .filter(|held| {
+                    !sessions_to_alert.iter().any(|alert_session| {
+                        alert_session.session.session_id == held.session.session_id
+                    })
+                })
+                .map(HeldSession::alert_session)
+                .collect::<Vec<_>>();
+            held_sessions.extend(sessions_to_alert);
+            let sessions_to_alert = held_sessions;
+            if sessions_to_alert.is_empty() {
+                tracing::debug!(message = "No centers found for user", %user_id, %alert_id, %email, ?centers);
+                continue;
+            }
+
+            let sessions_to_alert = match exclusion_map
+                .reserve(&alert_key, sessions_to_alert, now)
+                .await
+            {
+                Ok(sessions_to_alert) if sessions_to_alert.is_empty() => {
+                    tracing::debug!(message = "Sessions already reserved by another run", %user_id, %alert_id);
+                    continue;
                 }
+                Ok(sessions_to_alert) => sessions_to_alert,
                 Err(err) => {
-                    tracing::error!(message = "An error occured while calling centers by district api", error = ?err);
+                    tracing::error!(message = "An error occured while reserving sessions", %user_id, %alert_id, error = ?err);
+                    report.add_error(format_args!("{} reserve", alert_key), err);
+                    continue;
+                }
+            };
+
+            tracing::debug!(message = "Found centers for user", %user_id, %alert_id, %email, ?centers, ?sessions_to_alert);
+            report.sessions_matched += sessions_to_alert.len();
+            let notify_report = notifier
+                .notify(&Notification {
+                    alert: &alert,
+                    sessions: &sessions_to_alert,
+                    generated_at: now,
+                    delivery,
+                })
+                .await;
+            for (channel, res) in notify_report.results.iter() {
+                match res {
+                    Ok(()) => {
+                        tracing::debug!(message = "Sent alert", %user_id, %alert_id, ?channel);
+                    }
+                    Err(err) => {
+                        tracing::error!(message = "An error occured while sending alert", %user_id, %alert_id, ?channel, error = ?err);
+                    }
+                }
+            }
+            // Sessions are excluded only once they reached the user through some channel,
+            // so that an alert failed in every channel is sent again in the next run.
+            if notify_report.is_notified() {
+                exclusion_map.add(&alert_key, &sessions_to_alert, now);
+                if !held.is_empty() {
+                    if let Err(err) = exclusion_map.clear_held(&alert_key).await {
+                        tracing::error!(message = "An error occured while clearing held sessions", %user_id, %alert_id, error = ?err);
+                        report.add_error(format_args!("{} clear held", alert_key), err);
+                    }
                 }
+            } else if let Err(err) = exclusion_map.release(&alert_key, &sessions_to_alert).await {
+                tracing::error!(message = "An error occured while releasing sessions", %user_id, %alert_id, error = ?err);
+                report.add_error(format_args!("{} release", alert_key), err);
             }
+            report.add_notify_report(&alert_key, &notify_report);
         }
 
+        tracing::info!(message = "Completed alert engine run", ?report);
         exclusion_map.store().await?;
-        Ok(())
+        Ok(report)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::{collections::HashMap, convert::Infallible};
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        io,
+        sync::{Arc, Mutex},
+    };
 
     use async_trait::async_trait;
+    use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 
     use crate::{
-        api::alerts::{AlertFilter, DoseFilter, GetAlertsError},
-        covin::centers::{Center, CenterResponse, FindCenters, Session},
+        api::alerts::{
+            AlertFilter, ChannelPreferences, DaysOfWeekFilter, DeliveryMode, DoseFilter, FeeFilter,
+            GeoFilter, GetAlertsError, QuietHours, RenotifyPolicy, RenotifyTrigger, VaccineFilter,
+        },
+        covin::centers::{
+            Center, CenterResponse, FindCenters, Session, SessionResponse, VaccineFee,
+        },
     };
 
     use super::{
-        alert_session::AlertSession, email_client::EmailClient, exclusion_map::ExclusionMap,
-        template_engine::TemplateEngine, AlertEngine,
+        alert_session::AlertSession,
+        chat_client::ChatClient,
+        clock::{Clock, FixedClock},
+        email_client::EmailClient,
+        exclusion_map::{ExclusionMap, InMemoryExclusionMap},
+        group_digests,
+        notifier::{ChatChannel, EmailChannel, Notifier, SmsChannel, WebhookChannel},
+        renotify::LastNotified,
+        run_report::RunReport,
+        shard::Shard,
+        sms_client::InMemorySmsClient,
+        template_engine::TemplateEngine,
+        webhook_client::{WebhookClient, WebhookDocument},
+        AlertEngine, DATE_FORMAT,
     };
 
+    /// Alert of the dummy user `user_no` on the district `1`, the fixtures override only
+    /// the fields they are about.
+    fn dummy_alert(user_no: u32) -> AlertFilter {
+        AlertFilter {
+            user_id: format!("dummy-user-{}", user_no),
+            alert_id: format!("dummy-alert-{}", user_no),
+            district_id: 1,
+            email: format!("dummy-{}@email.com", user_no),
+            ..Default::default()
+        }
+    }
+
     async fn get_mock_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
         Ok(vec![
             AlertFilter {
-                user_id: "dummy-user-1".to_string(),
                 age: Some(18),
                 centers: Some(vec![1, 2, 3]),
-                district_id: 1,
-                dose: DoseFilter::Any,
-                email: "dummy-1@email.com".to_string(),
-                mobile_no: None,
+                ..dummy_alert(1)
             },
             AlertFilter {
-                user_id: "dummy-user-2".to_string(),
                 age: Some(45),
                 centers: Some(vec![1, 2, 3]),
-                district_id: 1,
-                dose: DoseFilter::Any,
-                email: "dummy-2@email.com".to_string(),
-                mobile_no: None,
+                ..dummy_alert(2)
             },
+            dummy_alert(3),
             AlertFilter {
-                user_id: "dummy-user-3".to_string(),
-                age: None,
-                centers: None,
-                district_id: 1,
-                dose: DoseFilter::Any,
-                email: "dummy-3@email.com".to_string(),
-                mobile_no: None,
-            },
-            AlertFilter {
-                user_id: "dummy-user-4".to_string(),
-                age: None,
-                centers: None,
-                district_id: 1,
                 dose: DoseFilter::First,
-                email: "dummy-4@email.com".to_string(),
-                mobile_no: None,
+                ..dummy_alert(4)
             },
             AlertFilter {
-                user_id: "dummy-user-5".to_string(),
-                age: None,
-                centers: None,
-                district_id: 1,
                 dose: DoseFilter::Second,
-                email: "dummy-5@email.com".to_string(),
-                mobile_no: None,
+                ..dummy_alert(5)
+            },
+        ])
+    }
+
+    async fn get_mock_vaccine_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        Ok(vec![
+            AlertFilter {
+                vaccine: VaccineFilter::Covishield,
+                ..dummy_alert(1)
+            },
+            AlertFilter {
+                age: Some(18),
+                vaccine: VaccineFilter::Covaxin,
+                ..dummy_alert(2)
+            },
+            AlertFilter {
+                vaccine: VaccineFilter::SputnikV,
+                ..dummy_alert(3)
+            },
+        ])
+    }
+
+    async fn get_mock_fee_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert = |user_no: u32, fee_type: FeeFilter, max_fee: Option<u32>| AlertFilter {
+            centers: Some(vec![2, 3]),
+            district_id: 2,
+            fee_type,
+            max_fee,
+            ..dummy_alert(user_no)
+        };
+        Ok(vec![
+            alert(1, FeeFilter::Free, None),
+            alert(2, FeeFilter::Paid, None),
+            alert(3, FeeFilter::Any, Some(1000)),
+            alert(4, FeeFilter::Paid, Some(500)),
+        ])
+    }
+
+    async fn get_mock_multi_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert = |alert_no: u32, district_id: u32, fee_type: FeeFilter| AlertFilter {
+            user_id: "dummy-user-1".to_string(),
+            name: Some(format!("Dummy Alert {}", alert_no)),
+            district_id,
+            dose: DoseFilter::First,
+            vaccine: VaccineFilter::Covishield,
+            fee_type,
+            ..dummy_alert(alert_no)
+        };
+        Ok(vec![
+            alert(1, 1, FeeFilter::Any),
+            alert(2, 2, FeeFilter::Free),
+        ])
+    }
+
+    async fn get_mock_restock_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        Ok(vec![AlertFilter {
+            renotify: RenotifyPolicy {
+                trigger: RenotifyTrigger::Restock,
+                ..Default::default()
+            },
+            ..dummy_alert(1)
+        }])
+    }
+
+    async fn get_mock_quiet_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        Ok(vec![AlertFilter {
+            quiet_hours: Some(QuietHours {
+                start_hour: 0,
+                end_hour: 6,
+            }),
+            max_per_hour: Some(1),
+            ..dummy_alert(1)
+        }])
+    }
+
+    async fn get_mock_digest_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert = |user_no: u32, alert_no: u32, delivery: DeliveryMode| AlertFilter {
+            alert_id: format!("dummy-alert-{}", alert_no),
+            delivery,
+            ..dummy_alert(user_no)
+        };
+        Ok(vec![
+            alert(1, 1, DeliveryMode::Digest),
+            alert(1, 2, DeliveryMode::Digest),
+            alert(2, 3, DeliveryMode::Realtime),
+        ])
+    }
+
+    async fn get_mock_sms_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert = |user_no: u32, sms: bool| AlertFilter {
+            district_id: 2,
+            fee_type: FeeFilter::Free,
+            channels: ChannelPreferences {
+                sms,
+                ..Default::default()
             },
+            mobile_no: Some(format!("+91912345678{}", user_no)),
+            ..dummy_alert(user_no)
+        };
+        Ok(vec![alert(1, true), alert(2, false)])
+    }
+
+    async fn get_mock_webhook_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert = |user_no: u32, webhook_url: Option<String>| AlertFilter {
+            district_id: 2,
+            fee_type: FeeFilter::Paid,
+            webhook_secret: webhook_url
+                .as_ref()
+                .map(|_| "dummy-webhook-secret".to_string()),
+            webhook_url,
+            ..dummy_alert(user_no)
+        };
+        Ok(vec![
+            alert(1, Some("https://example.com/hooks/1".to_string())),
+            alert(2, None),
+        ])
+    }
+
+    async fn get_mock_chat_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert = |user_no: u32, telegram_chat_id: Option<String>| AlertFilter {
+            district_id: 2,
+            fee_type: FeeFilter::Free,
+            telegram_chat_id,
+            ..dummy_alert(user_no)
+        };
+        Ok(vec![alert(1, Some("12345".to_string())), alert(2, None)])
+    }
+
+    async fn get_mock_pincode_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert = |user_no: u32, pincodes: Option<Vec<u32>>, fee_type: FeeFilter| AlertFilter {
+            pincodes,
+            fee_type,
+            ..dummy_alert(user_no)
+        };
+        Ok(vec![
+            alert(1, Some(vec![682001, 682002]), FeeFilter::Paid),
+            alert(2, Some(vec![682001]), FeeFilter::Free),
+            alert(3, Some(vec![682002]), FeeFilter::Any),
+            alert(4, None, FeeFilter::Any),
+        ])
+    }
+
+    async fn get_mock_location_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert = |user_no: u32, radius_km: f64| AlertFilter {
+            location: Some(GeoFilter {
+                lat: 9.9816,
+                long: 76.2999,
+                radius_km,
+                nearby_districts: vec![1],
+            }),
+            district_id: 2,
+            ..dummy_alert(user_no)
+        };
+        Ok(vec![alert(1, 2_f64), alert(2, 10_f64)])
+    }
+
+    async fn get_mock_date_window_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert =
+            |user_no: u32, days_ahead: Option<u32>, days_of_week: DaysOfWeekFilter| AlertFilter {
+                district_id: 3,
+                days_ahead,
+                days_of_week,
+                ..dummy_alert(user_no)
+            };
+        Ok(vec![
+            alert(1, Some(3), DaysOfWeekFilter::Any),
+            alert(2, None, DaysOfWeekFilter::Weekends),
+            alert(3, Some(10), DaysOfWeekFilter::Weekends),
         ])
     }
 
-    struct MockFindCenters;
+    async fn get_mock_single_day_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alerts = get_mock_date_window_alerts().await?;
+        Ok(alerts
+            .into_iter()
+            .take(2)
+            .map(|alert| AlertFilter {
+                days_ahead: Some(1),
+                days_of_week: DaysOfWeekFilter::Any,
+                ..alert
+            })
+            .collect())
+    }
+
+    async fn get_mock_failed_fetch_alerts() -> Result<Vec<AlertFilter>, GetAlertsError> {
+        let alert = |user_no: u32, district_id: u32| AlertFilter {
+            district_id,
+            ..dummy_alert(user_no)
+        };
+        Ok(vec![alert(1, 404), alert(2, 2), alert(3, 2)])
+    }
+
+    /// Fails to fetch the district `404`.
+    #[derive(Default)]
+    struct MockFindCenters(Mutex<Vec<String>>);
+
+    impl MockFindCenters {
+        fn new() -> Self {
+            Self::default()
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
 
     #[async_trait]
     impl FindCenters for MockFindCenters {
-        type Error = Infallible;
+        type Error = io::Error;
 
         async fn get_all_centers_by_district(
             &self,
@@ -285,17 +1035,33 @@ mod test {
 
         async fn get_all_centers_by_district_json(
             &self,
-            _district_id: &str,
-            _date: &str,
+            district_id: &str,
+            date: &str,
             _vaccine: Option<&str>,
         ) -> std::result::Result<CenterResponse, Self::Error> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("district:{}", district_id));
+            if district_id == "404" {
+                return Err(io::Error::new(io::ErrorKind::Other, "mock fetch failure"));
+            }
+            if district_id == "2" {
+                return Ok(get_mock_fee_centers());
+            }
+            if district_id == "3" {
+                return Ok(get_mock_calendar_centers(date));
+            }
             Ok(CenterResponse {
                 centers: vec![Center {
                     center_id: 1,
                     name: "Dummy Center Name 1".to_string(),
+                    lat: 9.9312,
+                    long: 76.2673,
                     sessions: vec![
                         Session {
                             session_id: "dummy-session-id-1".to_string(),
+                            vaccine: "COVISHIELD".to_string(),
                             min_age_limit: 18,
                             available_capacity: 0_f32,
                             available_capacity_dose1: 0_f32,
@@ -304,6 +1070,7 @@ mod test {
                         },
                         Session {
                             session_id: "dummy-session-id-2".to_string(),
+                            vaccine: "COVISHIELD".to_string(),
                             min_age_limit: 45,
                             available_capacity: 0_f32,
                             available_capacity_dose1: 0_f32,
@@ -312,6 +1079,7 @@ mod test {
                         },
                         Session {
                             session_id: "dummy-session-id-3".to_string(),
+                            vaccine: "COVISHIELD".to_string(),
                             min_age_limit: 18,
                             available_capacity: 1_f32,
                             available_capacity_dose1: 1_f32,
@@ -320,6 +1088,7 @@ mod test {
                         },
                         Session {
                             session_id: "dummy-session-id-4".to_string(),
+                            vaccine: "COVAXIN".to_string(),
                             min_age_limit: 45,
                             available_capacity: 1_f32,
                             available_capacity_dose1: 1_f32,
@@ -328,6 +1097,7 @@ mod test {
                         },
                         Session {
                             session_id: "dummy-session-id-5".to_string(),
+                            vaccine: "COVISHIELD".to_string(),
                             min_age_limit: 18,
                             available_capacity: 1_f32,
                             available_capacity_dose1: 1_f32,
@@ -336,6 +1106,7 @@ mod test {
                         },
                         Session {
                             session_id: "dummy-session-id-6".to_string(),
+                            vaccine: "COVAXIN".to_string(),
                             min_age_limit: 18,
                             available_capacity: 1_f32,
                             available_capacity_dose1: 0_f32,
@@ -347,65 +1118,339 @@ mod test {
                 }],
             })
         }
-    }
-
-    struct MockExclusionMap(HashMap<String, Vec<(String, f32)>>);
 
-    impl MockExclusionMap {
-        fn new() -> Self {
-            Self(HashMap::new())
+        async fn get_all_centers_by_pin(
+            &self,
+            _pincode: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<String, Self::Error> {
+            unimplemented!()
         }
-    }
 
-    #[async_trait]
-    impl ExclusionMap for MockExclusionMap {
-        type Error = Infallible;
+        async fn get_all_centers_by_pin_json(
+            &self,
+            pincode: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<CenterResponse, Self::Error> {
+            self.0.lock().unwrap().push(format!("pincode:{}", pincode));
+            if pincode == "682001" {
+                return Ok(get_mock_fee_centers());
+            }
+            Ok(CenterResponse { centers: vec![] })
+        }
 
-        async fn store(&self) -> Result<(), Self::Error> {
-            Ok(())
+        async fn get_sessions_by_district_json(
+            &self,
+            district_id: &str,
+            date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<SessionResponse, Self::Error> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("sessions:district:{}", district_id));
+            Ok(SessionResponse::on_date(
+                get_mock_calendar_centers(date),
+                date,
+            ))
         }
+    }
 
-        fn any_variance(&self, _user_id: &str, _session_id: &str, _capacity: f32) -> bool {
-            true
+    fn get_mock_fee_centers() -> CenterResponse {
+        CenterResponse {
+            centers: vec![
+                Center {
+                    center_id: 2,
+                    name: "Dummy Center Name 2".to_string(),
+                    fee_type: "Free".to_string(),
+                    lat: 9.9816,
+                    long: 76.2999,
+                    sessions: vec![Session {
+                        session_id: "dummy-session-id-7".to_string(),
+                        vaccine: "COVISHIELD".to_string(),
+                        min_age_limit: 18,
+                        available_capacity: 1_f32,
+                        available_capacity_dose1: 1_f32,
+                        available_capacity_dose2: 0_f32,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                Center {
+                    center_id: 3,
+                    name: "Dummy Center Name 3".to_string(),
+                    fee_type: "Paid".to_string(),
+                    lat: 8.5241,
+                    long: 76.9366,
+                    sessions: vec![
+                        Session {
+                            session_id: "dummy-session-id-8".to_string(),
+                            vaccine: "COVISHIELD".to_string(),
+                            min_age_limit: 18,
+                            available_capacity: 1_f32,
+                            available_capacity_dose1: 1_f32,
+                            available_capacity_dose2: 0_f32,
+                            ..Default::default()
+                        },
+                        Session {
+                            session_id: "dummy-session-id-9".to_string(),
+                            vaccine: "COVAXIN".to_string(),
+                            min_age_limit: 18,
+                            available_capacity: 1_f32,
+                            available_capacity_dose1: 1_f32,
+                            available_capacity_dose2: 0_f32,
+                            ..Default::default()
+                        },
+                    ],
+                    vaccine_fees: vec![
+                        VaccineFee {
+                            vaccine: "COVISHIELD".to_string(),
+                            fee: "780".to_string(),
+                        },
+                        VaccineFee {
+                            vaccine: "COVAXIN".to_string(),
+                            fee: "1410".to_string(),
+                        },
+                    ],
+                    ..Default::default()
+                },
+            ],
         }
+    }
 
-        fn add(&mut self, user_id: &str, sessions: &[AlertSession]) {
-            let vals = sessions
-                .iter()
-                .map(|session| {
-                    (
-                        session.session.session_id.to_owned(),
-                        session.session.available_capacity,
-                    )
-                })
-                .collect();
-            self.0.insert(user_id.to_owned(), vals);
+    /// A center with one session on each day of the calendar week starting from `date`.
+    fn get_mock_calendar_centers(date: &str) -> CenterResponse {
+        let date = NaiveDate::parse_from_str(date, DATE_FORMAT).unwrap();
+        CenterResponse {
+            centers: vec![Center {
+                center_id: 4,
+                name: "Dummy Center Name 4".to_string(),
+                sessions: (0..7)
+                    .map(|day| {
+                        let date = (date + Duration::days(day)).format(DATE_FORMAT).to_string();
+                        Session {
+                            session_id: format!("dummy-session-{}", date),
+                            date,
+                            min_age_limit: 18,
+                            available_capacity: 1_f32,
+                            available_capacity_dose1: 1_f32,
+                            available_capacity_dose2: 0_f32,
+                            ..Default::default()
+                        }
+                    })
+                    .collect(),
+                ..Default::default()
+            }],
         }
     }
 
-    struct MockEmailClient(HashMap<String, String>);
+    /// District with a single session, of the capacity set for the next run.
+    #[derive(Default)]
+    struct StockFindCenters(Mutex<f32>);
 
-    impl MockEmailClient {
-        fn new() -> Self {
-            Self(HashMap::new())
+    impl StockFindCenters {
+        fn set_capacity(&self, capacity: f32) {
+            *self.0.lock().unwrap() = capacity;
         }
     }
 
     #[async_trait]
-    impl EmailClient for MockEmailClient {
+    impl FindCenters for StockFindCenters {
         type Error = Infallible;
 
-        async fn send_alert_email(
-            &mut self,
-            email: &str,
-            content: &str,
-        ) -> Result<(), Self::Error> {
-            self.0.insert(email.to_string(), content.to_string());
-            Ok(())
-        }
-    }
-
-    struct MockTemplateEngine;
+        async fn get_all_centers_by_district(
+            &self,
+            _district_id: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<String, Self::Error> {
+            unimplemented!()
+        }
+
+        async fn get_all_centers_by_district_json(
+            &self,
+            _district_id: &str,
+            date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<CenterResponse, Self::Error> {
+            let capacity = *self.0.lock().unwrap();
+            Ok(CenterResponse {
+                centers: vec![Center {
+                    center_id: 1,
+                    name: "Dummy Center Name 1".to_string(),
+                    sessions: vec![Session {
+                        session_id: "dummy-session-id-1".to_string(),
+                        date: date.to_string(),
+                        vaccine: "COVISHIELD".to_string(),
+                        min_age_limit: 18,
+                        available_capacity: capacity,
+                        available_capacity_dose1: capacity,
+                        available_capacity_dose2: 0_f32,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            })
+        }
+
+        async fn get_all_centers_by_pin(
+            &self,
+            _pincode: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<String, Self::Error> {
+            unimplemented!()
+        }
+
+        async fn get_all_centers_by_pin_json(
+            &self,
+            _pincode: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<CenterResponse, Self::Error> {
+            unimplemented!()
+        }
+    }
+
+    struct MockExclusionMap(HashMap<String, Vec<(String, f32)>>);
+
+    impl MockExclusionMap {
+        fn new() -> Self {
+            Self(HashMap::new())
+        }
+    }
+
+    #[async_trait]
+    impl ExclusionMap for MockExclusionMap {
+        type Error = Infallible;
+
+        async fn store(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn last_notified(&self, _alert_key: &str, _session_id: &str) -> Option<LastNotified> {
+            None
+        }
+
+        fn add(&mut self, alert_key: &str, sessions: &[AlertSession], _notified_at: DateTime<Utc>) {
+            let vals = sessions
+                .iter()
+                .map(|session| {
+                    (
+                        session.session.session_id.to_owned(),
+                        session.session.available_capacity,
+                    )
+                })
+                .collect();
+            self.0.insert(alert_key.to_owned(), vals);
+        }
+    }
+
+    /// Clones share the sent emails, failing to send to the addresses in the fail list.
+    #[derive(Clone, Default)]
+    struct MockEmailClient {
+        emails: Arc<Mutex<HashMap<String, String>>>,
+        fail_for: Vec<String>,
+    }
+
+    impl MockEmailClient {
+        fn failing_for(email: &str) -> Self {
+            Self {
+                fail_for: vec![email.to_string()],
+                ..Default::default()
+            }
+        }
+
+        fn emails(&self) -> HashMap<String, String> {
+            self.emails.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl EmailClient for MockEmailClient {
+        type Error = io::Error;
+
+        async fn send_alert_email(
+            &mut self,
+            email: &str,
+            content: &str,
+        ) -> Result<(), Self::Error> {
+            if self.fail_for.iter().any(|fail_for| fail_for == email) {
+                return Err(io::Error::new(io::ErrorKind::Other, "mock email failure"));
+            }
+            self.emails
+                .lock()
+                .unwrap()
+                .insert(email.to_string(), content.to_string());
+            Ok(())
+        }
+    }
+
+    /// Webhook URL, alert id and session ids of a webhook request.
+    type WebhookRequest = (String, String, Vec<String>);
+
+    #[derive(Clone, Default)]
+    struct MockWebhookClient(Arc<Mutex<Vec<WebhookRequest>>>);
+
+    impl MockWebhookClient {
+        fn requests(&self) -> Vec<WebhookRequest> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl WebhookClient for MockWebhookClient {
+        type Error = Infallible;
+
+        async fn send_alert_webhook(
+            &mut self,
+            webhook_url: &str,
+            _secret: &str,
+            document: &WebhookDocument<'_>,
+        ) -> Result<(), Self::Error> {
+            let session_ids = document
+                .sessions
+                .iter()
+                .map(|session| session.session.session_id.to_owned())
+                .collect();
+            self.0.lock().unwrap().push((
+                webhook_url.to_string(),
+                document.alert_id.to_string(),
+                session_ids,
+            ));
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockChatClient(Arc<Mutex<HashMap<String, String>>>);
+
+    impl MockChatClient {
+        fn messages(&self) -> HashMap<String, String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl ChatClient for MockChatClient {
+        type Error = Infallible;
+
+        async fn send_alert_message(
+            &mut self,
+            chat_id: &str,
+            text: &str,
+        ) -> Result<(), Self::Error> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(chat_id.to_string(), text.to_string());
+            Ok(())
+        }
+    }
+
+    struct MockTemplateEngine;
 
     impl TemplateEngine for MockTemplateEngine {
         type Error = Infallible;
@@ -413,6 +1458,7 @@ mod test {
         fn generate_alert_content(
             &self,
             sessions_to_alert: &[AlertSession],
+            _generated_at: DateTime<Utc>,
         ) -> Result<String, Self::Error> {
             let mut res = String::new();
             sessions_to_alert.iter().for_each(|alert_serssion| {
@@ -422,37 +1468,77 @@ mod test {
             });
             Ok(res)
         }
+
+        fn generate_digest_content(
+            &self,
+            sessions: &[AlertSession],
+            generated_at: DateTime<Utc>,
+        ) -> Result<String, Self::Error> {
+            let content = self.generate_alert_content(sessions, generated_at)?;
+            Ok(format!("digest\n{}", content))
+        }
+    }
+
+    /// Clients of the notifier channels, kept to inspect the alerts sent by the engine.
+    #[derive(Default)]
+    struct MockClients {
+        email_client: MockEmailClient,
+        sms_client: InMemorySmsClient,
+        webhook_client: MockWebhookClient,
+        chat_client: MockChatClient,
+    }
+
+    impl MockClients {
+        fn notifier(&self) -> Notifier {
+            Notifier::new()
+                .with_channel(EmailChannel::new(
+                    self.email_client.clone(),
+                    MockTemplateEngine,
+                ))
+                .with_channel(SmsChannel::new(self.sms_client.clone()))
+                .with_channel(WebhookChannel::new(self.webhook_client.clone()))
+                .with_channel(ChatChannel::new(self.chat_client.clone()))
+        }
     }
 
-    impl<GaFn, GaFnFut, Fc, Em, Ec, Te> AlertEngine<GaFn, GaFnFut, Fc, Em, Ec, Te>
+    impl<GaFn, GaFnFut, Fc, Em, Ck> AlertEngine<GaFn, GaFnFut, Fc, Em, Ck>
     where
         GaFn: Fn() -> GaFnFut,
         GaFnFut: futures::Future<Output = Result<Vec<AlertFilter>, GetAlertsError>>,
         Fc: FindCenters,
         Em: ExclusionMap,
-        Ec: EmailClient,
-        Te: TemplateEngine,
+        Ck: Clock,
     {
-        fn get_all_internals(&self) -> (&Em, &Ec) {
-            (&self.exclusion_map, &self.email_client)
+        fn get_exclusion_map(&self) -> &Em {
+            &self.exclusion_map
+        }
+
+        fn get_find_centers(&self) -> &Fc {
+            &self.find_centers
+        }
+
+        fn get_clock(&self) -> &Ck {
+            &self.clock
+        }
+
+        fn set_delivery(&mut self, delivery: DeliveryMode) {
+            self.delivery = delivery;
         }
     }
 
     #[tokio::test]
     async fn test_alert_engine() {
-        let find_centers = MockFindCenters;
+        let find_centers = MockFindCenters::new();
         let exclusion_map = MockExclusionMap::new();
-        let email_client = MockEmailClient::new();
-        let template_engine = MockTemplateEngine;
+        let clients = MockClients::default();
         let mut alert_engine = AlertEngine::new(
             get_mock_alerts,
             find_centers,
             exclusion_map,
-            email_client,
-            template_engine,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
         );
         let _ = alert_engine.run().await;
-        let (_exclusion_map, email_client) = alert_engine.get_all_internals();
 
         let mut expected_email_map = HashMap::<String, String>::new();
         expected_email_map.insert(
@@ -478,6 +1564,707 @@ mod test {
             "dummy-session-id-6\n".to_string(),
         );
 
-        assert_eq!(email_client.0, expected_email_map);
+        assert_eq!(clients.email_client.emails(), expected_email_map);
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_vaccine_filter() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_vaccine_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        let mut expected_email_map = HashMap::<String, String>::new();
+        expected_email_map.insert(
+            "dummy-1@email.com".to_string(),
+            "dummy-session-id-3\ndummy-session-id-5\n".to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-2@email.com".to_string(),
+            "dummy-session-id-6\n".to_string(),
+        );
+
+        assert_eq!(clients.email_client.emails(), expected_email_map);
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_fee_filter() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_fee_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        let mut expected_email_map = HashMap::<String, String>::new();
+        expected_email_map.insert(
+            "dummy-1@email.com".to_string(),
+            "dummy-session-id-7\n".to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-2@email.com".to_string(),
+            "dummy-session-id-8\ndummy-session-id-9\n".to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-3@email.com".to_string(),
+            "dummy-session-id-7\ndummy-session-id-8\n".to_string(),
+        );
+
+        assert_eq!(clients.email_client.emails(), expected_email_map);
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_multiple_alerts_per_user() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_multi_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        let mut expected_email_map = HashMap::<String, String>::new();
+        expected_email_map.insert(
+            "dummy-1@email.com".to_string(),
+            "dummy-session-id-3\ndummy-session-id-5\n".to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-2@email.com".to_string(),
+            "dummy-session-id-7\n".to_string(),
+        );
+
+        assert_eq!(clients.email_client.emails(), expected_email_map);
+
+        let mut alert_keys = alert_engine
+            .get_exclusion_map()
+            .0
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        alert_keys.sort();
+        assert_eq!(
+            alert_keys,
+            vec![
+                "dummy-user-1#dummy-alert-1".to_string(),
+                "dummy-user-1#dummy-alert-2".to_string()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_pincodes() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_pincode_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        let mut expected_email_map = HashMap::<String, String>::new();
+        expected_email_map.insert(
+            "dummy-1@email.com".to_string(),
+            "dummy-session-id-8\ndummy-session-id-9\n".to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-2@email.com".to_string(),
+            "dummy-session-id-7\n".to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-4@email.com".to_string(),
+            "dummy-session-id-3\ndummy-session-id-4\ndummy-session-id-5\ndummy-session-id-6\n"
+                .to_string(),
+        );
+
+        assert_eq!(clients.email_client.emails(), expected_email_map);
+
+        // Each district and pincode is fetched exactly once
+        assert_eq!(
+            alert_engine.get_find_centers().calls(),
+            vec![
+                "district:1".to_string(),
+                "pincode:682001".to_string(),
+                "pincode:682002".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_location() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_location_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        // Nearest centers first, center 3 (~170 km away) is always out of the radius
+        let mut expected_email_map = HashMap::<String, String>::new();
+        expected_email_map.insert(
+            "dummy-1@email.com".to_string(),
+            "dummy-session-id-7\n".to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-2@email.com".to_string(),
+            "dummy-session-id-7\ndummy-session-id-3\ndummy-session-id-4\ndummy-session-id-5\ndummy-session-id-6\n"
+                .to_string(),
+        );
+
+        assert_eq!(clients.email_client.emails(), expected_email_map);
+
+        // Nearby districts are fetched along with the district of the alert
+        assert_eq!(
+            alert_engine.get_find_centers().calls(),
+            vec!["district:1".to_string(), "district:2".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_date_window() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        // Friday, 14-05-2021 01:30 IST
+        let mut alert_engine = AlertEngine::new(
+            get_mock_date_window_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        let mut expected_email_map = HashMap::<String, String>::new();
+        expected_email_map.insert(
+            "dummy-1@email.com".to_string(),
+            "dummy-session-14-05-2021\ndummy-session-15-05-2021\ndummy-session-16-05-2021\n"
+                .to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-2@email.com".to_string(),
+            "dummy-session-15-05-2021\ndummy-session-16-05-2021\n".to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-3@email.com".to_string(),
+            "dummy-session-15-05-2021\ndummy-session-16-05-2021\ndummy-session-22-05-2021\ndummy-session-23-05-2021\n"
+                .to_string(),
+        );
+
+        assert_eq!(clients.email_client.emails(), expected_email_map);
+
+        // The widest date window of 10 days needs two calendar weeks of the district
+        assert_eq!(
+            alert_engine.get_find_centers().calls(),
+            vec!["district:3".to_string(), "district:3".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_single_day() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        // Friday, 14-05-2021 01:30 IST
+        let mut alert_engine = AlertEngine::new(
+            get_mock_single_day_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        let mut expected_email_map = HashMap::<String, String>::new();
+        expected_email_map.insert(
+            "dummy-1@email.com".to_string(),
+            "dummy-session-14-05-2021\n".to_string(),
+        );
+        expected_email_map.insert(
+            "dummy-2@email.com".to_string(),
+            "dummy-session-14-05-2021\n".to_string(),
+        );
+        assert_eq!(clients.email_client.emails(), expected_email_map);
+
+        // Only the sessions of today are fetched, instead of the calendar week
+        assert_eq!(
+            alert_engine.get_find_centers().calls(),
+            vec!["sessions:district:3".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_sms() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_sms_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        // Both alerts are sent by email
+        assert_eq!(clients.email_client.emails().len(), 2);
+
+        // Only the alert opted into SMS is sent by SMS
+        let mut expected_sms_map = HashMap::<String, Vec<String>>::new();
+        expected_sms_map.insert(
+            "+919123456781".to_string(),
+            vec![
+                "Vaccine slots available\n COVISHIELD Dummy Center Name 2 0: D1 1 D2 0 18+"
+                    .to_string(),
+            ],
+        );
+        assert_eq!(clients.sms_client.messages(), expected_sms_map);
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_webhook() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_webhook_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        // Both alerts are sent by email
+        assert_eq!(clients.email_client.emails().len(), 2);
+
+        // Only the alert with a webhook is posted to it
+        assert_eq!(
+            clients.webhook_client.requests(),
+            vec![(
+                "https://example.com/hooks/1".to_string(),
+                "dummy-alert-1".to_string(),
+                vec![
+                    "dummy-session-id-8".to_string(),
+                    "dummy-session-id-9".to_string()
+                ]
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_chat() {
+        let find_centers = MockFindCenters::new();
+        let exclusion_map = MockExclusionMap::new();
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_chat_alerts,
+            find_centers,
+            exclusion_map,
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let _ = alert_engine.run().await;
+
+        // Both alerts are sent by email
+        assert_eq!(clients.email_client.emails().len(), 2);
+
+        // Only the alert linked to a chat is sent to it
+        let messages = clients.chat_client.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages["12345"].contains("*Dummy Center Name 2*"));
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_failed_channel() {
+        let clients = MockClients {
+            email_client: MockEmailClient::failing_for("dummy-1@email.com"),
+            ..Default::default()
+        };
+        let mut alert_engine = AlertEngine::new(
+            get_mock_chat_alerts,
+            MockFindCenters::new(),
+            MockExclusionMap::new(),
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        alert_engine.run().await.unwrap();
+
+        // A failed email neither stops the chat message of the alert, nor the other alerts
+        assert_eq!(
+            clients.chat_client.messages().keys().collect::<Vec<_>>(),
+            vec!["12345"]
+        );
+        assert_eq!(
+            clients.email_client.emails().keys().collect::<Vec<_>>(),
+            vec!["dummy-2@email.com"]
+        );
+
+        // Both alerts reached the user through at least one channel
+        let mut alert_keys = alert_engine
+            .get_exclusion_map()
+            .0
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        alert_keys.sort();
+        assert_eq!(
+            alert_keys,
+            vec![
+                "dummy-user-1#dummy-alert-1".to_string(),
+                "dummy-user-2#dummy-alert-2".to_string()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_all_channels_failed() {
+        let clients = MockClients {
+            email_client: MockEmailClient::failing_for("dummy-2@email.com"),
+            ..Default::default()
+        };
+        let mut alert_engine = AlertEngine::new(
+            get_mock_chat_alerts,
+            MockFindCenters::new(),
+            MockExclusionMap::new(),
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        alert_engine.run().await.unwrap();
+
+        // The alert without a chat is not excluded, to be sent again in the next run
+        assert_eq!(
+            alert_engine
+                .get_exclusion_map()
+                .0
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["dummy-user-1#dummy-alert-1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_run_report() {
+        let clients = MockClients {
+            email_client: MockEmailClient::failing_for("dummy-3@email.com"),
+            ..Default::default()
+        };
+        let mut alert_engine = AlertEngine::new(
+            get_mock_failed_fetch_alerts,
+            MockFindCenters::new(),
+            MockExclusionMap::new(),
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        let report = alert_engine.run().await.unwrap();
+
+        // A failed district and a failed alert don't stop the alerts of other districts and users
+        assert_eq!(
+            clients.email_client.emails().keys().collect::<Vec<_>>(),
+            vec!["dummy-2@email.com"]
+        );
+        assert_eq!(
+            report,
+            RunReport {
+                shard: Shard::default(),
+                delivery: DeliveryMode::Realtime,
+                alerts_checked: 3,
+                districts_fetched: 1,
+                pincodes_fetched: 0,
+                fetches_failed: 1,
+                sessions_matched: 6,
+                alerts_notified: 1,
+                alerts_failed: 1,
+                alerts_deferred: 0,
+                notifications_sent: 1,
+                notifications_failed: 1,
+                errors: vec![
+                    "district 404 on 14-05-2021: Request failed: mock fetch failure".to_string(),
+                    "dummy-user-3#dummy-alert-3 via Email: mock email failure".to_string(),
+                ],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_shard() {
+        let shard = Shard::new(1, 2).unwrap();
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_multi_alerts,
+            MockFindCenters::new(),
+            MockExclusionMap::new(),
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        )
+        .with_shard(shard);
+        let report = alert_engine.run().await.unwrap();
+
+        // Districts 1 and 2 fall into different shards, only the alert of district 1 is sent
+        assert!(shard.contains_district(1) && !shard.contains_district(2));
+        assert_eq!(report.shard, shard);
+        assert_eq!(report.alerts_checked, 1);
+        assert_eq!(
+            alert_engine.get_find_centers().calls(),
+            vec!["district:1".to_string()]
+        );
+        assert_eq!(
+            clients.email_client.emails().keys().collect::<Vec<_>>(),
+            vec!["dummy-1@email.com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_renotify_restock() {
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_restock_alerts,
+            StockFindCenters::default(),
+            InMemoryExclusionMap::new(),
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        // Capacity ticking down isn't alerted again, neither is the session running out,
+        // until the session is restocked
+        let runs = [(10_f32, 1), (8_f32, 0), (0_f32, 0), (5_f32, 1), (6_f32, 0)];
+        for (capacity, alerts_notified) in runs.iter() {
+            alert_engine.get_find_centers().set_capacity(*capacity);
+            let report = alert_engine.run().await.unwrap();
+            assert_eq!(
+                report.alerts_notified, *alerts_notified,
+                "capacity {}",
+                capacity
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_quiet_hours_and_limits() {
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_quiet_alerts,
+            StockFindCenters::default(),
+            InMemoryExclusionMap::new(),
+            clients.notifier(),
+            // 01:30 IST, within the quiet hours
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        alert_engine.get_find_centers().set_capacity(10_f32);
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!((report.alerts_notified, report.alerts_deferred), (0, 1));
+        assert!(clients.email_client.emails().is_empty());
+
+        // Sessions held back are sent once the quiet hours are over
+        alert_engine.get_clock().advance(Duration::hours(5));
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!((report.alerts_notified, report.alerts_deferred), (1, 0));
+
+        // Change in capacity waits for the hourly limit
+        alert_engine.get_find_centers().set_capacity(20_f32);
+        alert_engine.get_clock().advance(Duration::minutes(30));
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!((report.alerts_notified, report.alerts_deferred), (0, 1));
+        alert_engine.get_clock().advance(Duration::minutes(30));
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!((report.alerts_notified, report.alerts_deferred), (1, 0));
+        assert_eq!(
+            clients.email_client.emails().keys().collect::<Vec<_>>(),
+            vec!["dummy-1@email.com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_quiet_hours_digest() {
+        let clients = MockClients::default();
+        let mut alert_engine = AlertEngine::new(
+            get_mock_quiet_alerts,
+            StockFindCenters::default(),
+            InMemoryExclusionMap::new(),
+            clients.notifier(),
+            // 01:30 IST, within the quiet hours
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+        alert_engine.get_find_centers().set_capacity(10_f32);
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!(report.alerts_deferred, 1);
+        assert_eq!(
+            alert_engine.get_exclusion_map().held_sessions()["dummy-user-1#dummy-alert-1"].len(),
+            1
+        );
+
+        // Session closes before the quiet hours are over
+        alert_engine.get_find_centers().set_capacity(0_f32);
+        alert_engine.get_clock().advance(Duration::hours(2));
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!((report.alerts_notified, report.alerts_deferred), (0, 0));
+
+        // and is still sent once in a digest after them
+        alert_engine.get_clock().advance(Duration::hours(3));
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!((report.alerts_notified, report.sessions_matched), (1, 1));
+        assert_eq!(
+            clients.email_client.emails()["dummy-1@email.com"],
+            "digest\ndummy-session-id-1\n"
+        );
+        assert!(alert_engine.get_exclusion_map().held_sessions().is_empty());
+        alert_engine.get_clock().advance(Duration::hours(1));
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!(report.alerts_notified, 0);
+    }
+
+    #[tokio::test]
+    async fn test_alert_engine_digest() {
+        let clients = MockClients::default();
+        let find_centers = StockFindCenters::default();
+        find_centers.set_capacity(10_f32);
+        let mut alert_engine = AlertEngine::new(
+            get_mock_digest_alerts,
+            find_centers,
+            InMemoryExclusionMap::new(),
+            clients.notifier(),
+            FixedClock::new(Utc.ymd(2021, 5, 13).and_hms(20, 0, 0)),
+        );
+
+        // Realtime runs alert only the realtime alerts, and hold the sessions of the digest alerts
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!(report.alerts_checked, 3);
+        assert_eq!(
+            clients.email_client.emails().keys().collect::<Vec<_>>(),
+            vec!["dummy-2@email.com"]
+        );
+        let mut held = alert_engine
+            .get_exclusion_map()
+            .held_sessions()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        held.sort();
+        assert_eq!(
+            held,
+            vec!["dummy-user-1#dummy-alert-1", "dummy-user-1#dummy-alert-2"]
+        );
+
+        // Both the digest alerts of the user go into a single digest, with the session once,
+        // even though it closed since it was held
+        alert_engine.get_find_centers().set_capacity(0_f32);
+        alert_engine.get_clock().advance(Duration::hours(12));
+        alert_engine.set_delivery(DeliveryMode::Digest);
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!(report.delivery, DeliveryMode::Digest);
+        assert_eq!(report.alerts_checked, 2);
+        assert_eq!(report.sessions_matched, 1);
+        assert_eq!(report.alerts_notified, 1);
+        assert_eq!(
+            clients.email_client.emails()["dummy-1@email.com"],
+            "digest\ndummy-session-id-1\n"
+        );
+        // Digests leave the exclusions of the realtime alerts alone
+        assert_eq!(
+            alert_engine
+                .get_exclusion_map()
+                .entries()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["dummy-user-2#dummy-alert-3"]
+        );
+
+        // Sessions sent in a digest aren't sent again in the next one
+        assert!(alert_engine.get_exclusion_map().held_sessions().is_empty());
+        let report = alert_engine.run().await.unwrap();
+        assert_eq!(report.alerts_notified, 0);
+    }
+
+    #[test]
+    fn test_group_digests() {
+        let center = Center::default();
+        let session = |session_id: &str, date: &str| Session {
+            session_id: session_id.to_string(),
+            date: date.to_string(),
+            ..Default::default()
+        };
+        let sessions = [
+            session("session-id-1", "15-05-2021"),
+            session("session-id-2", "14-05-2021"),
+        ];
+        let alert_sessions = |range: std::ops::Range<usize>| {
+            sessions[range]
+                .iter()
+                .map(|session| AlertSession::from((session, &center)))
+                .collect::<Vec<_>>()
+        };
+        let alert = |alert_no: u32, email: &str| AlertFilter {
+            user_id: "dummy-user-1".to_string(),
+            alert_id: format!("dummy-alert-{}", alert_no),
+            email: email.to_string(),
+            delivery: DeliveryMode::Digest,
+            ..Default::default()
+        };
+        let digests = group_digests(vec![
+            (alert(1, "dummy-1@email.com"), alert_sessions(0..2)),
+            (alert(2, "dummy-1@email.com"), alert_sessions(0..1)),
+            (alert(3, "other@email.com"), alert_sessions(1..2)),
+            (alert(4, "other@email.com"), vec![]),
+        ]);
+
+        // Alerts sent to other destinations get a digest of their own
+        let digests = digests
+            .iter()
+            .map(|(alert, alert_keys, sessions)| {
+                (
+                    alert.email.as_str(),
+                    alert_keys.clone(),
+                    sessions
+                        .iter()
+                        .map(|alert_session| alert_session.session.session_id.as_str())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            digests,
+            vec![
+                (
+                    "dummy-1@email.com",
+                    vec![
+                        "dummy-user-1#dummy-alert-1".to_string(),
+                        "dummy-user-1#dummy-alert-2".to_string()
+                    ],
+                    vec!["session-id-2", "session-id-1"]
+                ),
+                (
+                    "other@email.com",
+                    vec![
+                        "dummy-user-1#dummy-alert-3".to_string(),
+                        "dummy-user-1#dummy-alert-4".to_string()
+                    ],
+                    vec!["session-id-2"]
+                ),
+            ]
+        );
     }
 }
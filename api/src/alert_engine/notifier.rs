@@ -0,0 +1,386 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::api::alerts::{AlertFilter, DeliveryMode};
+
+use super::{
+    alert_session::AlertSession,
+    chat_client::{render_alert_markdown, ChatClient},
+    email_client::EmailClient,
+    sms_client::{render_alert_sms, SmsClient},
+    template_engine::TemplateEngine,
+    webhook_client::{WebhookClient, WebhookDocument, WEBHOOK_VERSION},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Channel {
+    Email,
+    Sms,
+    Webhook,
+    Chat,
+}
+
+/// Sessions matched for an alert, to be sent through the channels of the alert.
+#[derive(Debug)]
+pub struct Notification<'a> {
+    pub alert: &'a AlertFilter,
+    pub sessions: &'a [AlertSession<'a, 'a>],
+    pub generated_at: DateTime<Utc>,
+    /// Digests are rendered as a summary of the sessions found since the previous
+    /// notification, some of which may have closed since.
+    pub delivery: DeliveryMode,
+}
+
+impl Notification<'_> {
+    /// Title of the notification in the channels rendering one.
+    pub fn title(&self) -> &'static str {
+        match self.delivery {
+            DeliveryMode::Realtime => "Vaccine slots available",
+            DeliveryMode::Digest => "Vaccine slots found since your last alert",
+        }
+    }
+}
+
+/// A channel renders the notification in its own format and sends it through its client.
+#[async_trait]
+pub trait NotificationChannel: Send {
+    fn channel(&self) -> Channel;
+
+    /// Whether the alert has opted into, and is configured for, this channel.
+    fn is_enabled(&self, alert: &AlertFilter) -> bool;
+
+    async fn notify(&mut self, notification: &Notification<'_>) -> Result<(), Error>;
+}
+
+/// Outcome of a notification in each of the enabled channels of the alert.
+#[derive(Debug, Default)]
+pub struct NotifyReport {
+    pub results: Vec<(Channel, Result<(), Error>)>,
+}
+
+impl NotifyReport {
+    /// Whether the notification was sent through at least one channel.
+    pub fn is_notified(&self) -> bool {
+        self.results.iter().any(|(_channel, result)| result.is_ok())
+    }
+
+    pub fn failed_channels(&self) -> Vec<Channel> {
+        self.results
+            .iter()
+            .filter(|(_channel, result)| result.is_err())
+            .map(|(channel, _result)| *channel)
+            .collect()
+    }
+}
+
+/// Fans out notifications to all the channels enabled for an alert.
+#[derive(Default)]
+pub struct Notifier {
+    channels: Vec<Box<dyn NotificationChannel>>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_channel<C: NotificationChannel + 'static>(mut self, channel: C) -> Self {
+        self.channels.push(Box::new(channel));
+        self
+    }
+
+    /// Sends the notification through every enabled channel, a failed channel
+    /// doesn't stop the notification from being sent through the rest.
+    pub async fn notify(&mut self, notification: &Notification<'_>) -> NotifyReport {
+        let mut report = NotifyReport::default();
+        for channel in self.channels.iter_mut() {
+            if channel.is_enabled(notification.alert) {
+                let result = channel.notify(notification).await;
+                report.results.push((channel.channel(), result));
+            }
+        }
+        report
+    }
+}
+
+pub struct EmailChannel<Ec: EmailClient, Te: TemplateEngine> {
+    email_client: Ec,
+    template_engine: Te,
+}
+
+impl<Ec: EmailClient, Te: TemplateEngine> EmailChannel<Ec, Te> {
+    pub fn new(email_client: Ec, template_engine: Te) -> Self {
+        Self {
+            email_client,
+            template_engine,
+        }
+    }
+}
+
+#[async_trait]
+impl<Ec, Te> NotificationChannel for EmailChannel<Ec, Te>
+where
+    Ec: EmailClient + Send,
+    Te: TemplateEngine + Send,
+{
+    fn channel(&self) -> Channel {
+        Channel::Email
+    }
+
+    fn is_enabled(&self, alert: &AlertFilter) -> bool {
+        alert.channels.email
+    }
+
+    async fn notify(&mut self, notification: &Notification<'_>) -> Result<(), Error> {
+        let Notification {
+            sessions,
+            generated_at,
+            ..
+        } = notification;
+        let content = match notification.delivery {
+            DeliveryMode::Realtime => self
+                .template_engine
+                .generate_alert_content(sessions, *generated_at)?,
+            DeliveryMode::Digest => self
+                .template_engine
+                .generate_digest_content(sessions, *generated_at)?,
+        };
+        self.email_client
+            .send_alert_email(&notification.alert.email, &content)
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct SmsChannel<Sc: SmsClient> {
+    sms_client: Sc,
+}
+
+impl<Sc: SmsClient> SmsChannel<Sc> {
+    pub fn new(sms_client: Sc) -> Self {
+        Self { sms_client }
+    }
+}
+
+#[async_trait]
+impl<Sc: SmsClient + Send> NotificationChannel for SmsChannel<Sc> {
+    fn channel(&self) -> Channel {
+        Channel::Sms
+    }
+
+    fn is_enabled(&self, alert: &AlertFilter) -> bool {
+        alert.channels.sms && alert.mobile_no.is_some()
+    }
+
+    async fn notify(&mut self, notification: &Notification<'_>) -> Result<(), Error> {
+        let mobile_no = notification.alert.mobile_no.as_deref().unwrap_or_default();
+        let parts = render_alert_sms(notification.title(), notification.sessions);
+        self.sms_client.send_alert_sms(mobile_no, &parts).await?;
+        Ok(())
+    }
+}
+
+pub struct WebhookChannel<Wc: WebhookClient> {
+    webhook_client: Wc,
+}
+
+impl<Wc: WebhookClient> WebhookChannel<Wc> {
+    pub fn new(webhook_client: Wc) -> Self {
+        Self { webhook_client }
+    }
+}
+
+#[async_trait]
+impl<Wc: WebhookClient + Send> NotificationChannel for WebhookChannel<Wc> {
+    fn channel(&self) -> Channel {
+        Channel::Webhook
+    }
+
+    fn is_enabled(&self, alert: &AlertFilter) -> bool {
+        alert.channels.webhook && alert.webhook_url.is_some() && alert.webhook_secret.is_some()
+    }
+
+    async fn notify(&mut self, notification: &Notification<'_>) -> Result<(), Error> {
+        let Notification {
+            alert,
+            sessions,
+            generated_at,
+            delivery,
+        } = notification;
+        let document = WebhookDocument {
+            version: WEBHOOK_VERSION,
+            alert_id: &alert.alert_id,
+            alert_name: alert.name.as_deref(),
+            generated_at: generated_at.to_rfc3339(),
+            delivery: *delivery,
+            sessions,
+        };
+        self.webhook_client
+            .send_alert_webhook(
+                alert.webhook_url.as_deref().unwrap_or_default(),
+                alert.webhook_secret.as_deref().unwrap_or_default(),
+                &document,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct ChatChannel<Cc: ChatClient> {
+    chat_client: Cc,
+}
+
+impl<Cc: ChatClient> ChatChannel<Cc> {
+    pub fn new(chat_client: Cc) -> Self {
+        Self { chat_client }
+    }
+}
+
+#[async_trait]
+impl<Cc: ChatClient + Send> NotificationChannel for ChatChannel<Cc> {
+    fn channel(&self) -> Channel {
+        Channel::Chat
+    }
+
+    fn is_enabled(&self, alert: &AlertFilter) -> bool {
+        alert.channels.chat && alert.telegram_chat_id.is_some()
+    }
+
+    async fn notify(&mut self, notification: &Notification<'_>) -> Result<(), Error> {
+        let chat_id = notification
+            .alert
+            .telegram_chat_id
+            .as_deref()
+            .unwrap_or_default();
+        let text = render_alert_markdown(notification.title(), notification.sessions);
+        self.chat_client.send_alert_message(chat_id, &text).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::{anyhow, Error};
+    use async_trait::async_trait;
+    use chrono::{TimeZone, Utc};
+
+    use super::{Channel, Notification, NotificationChannel, Notifier};
+    use crate::api::alerts::{AlertFilter, DeliveryMode};
+
+    /// Channel following the SMS preference of the alert when it is `Channel::Sms`, and
+    /// the email preference otherwise. Fails every notification when `fail` is set.
+    struct TestChannel {
+        channel: Channel,
+        fail: bool,
+        sent: Arc<Mutex<Vec<Channel>>>,
+    }
+
+    #[async_trait]
+    impl NotificationChannel for TestChannel {
+        fn channel(&self) -> Channel {
+            self.channel
+        }
+
+        fn is_enabled(&self, alert: &AlertFilter) -> bool {
+            match self.channel {
+                Channel::Sms => alert.channels.sms,
+                _ => alert.channels.email,
+            }
+        }
+
+        async fn notify(&mut self, _notification: &Notification<'_>) -> Result<(), Error> {
+            if self.fail {
+                return Err(anyhow!("channel failed"));
+            }
+            self.sent.lock().unwrap().push(self.channel);
+            Ok(())
+        }
+    }
+
+    fn alert() -> AlertFilter {
+        AlertFilter {
+            user_id: "dummy-user-1".to_string(),
+            alert_id: "dummy-alert-1".to_string(),
+            district_id: 1,
+            email: "dummy-1@email.com".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_channel_does_not_stop_others() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let channel = |channel: Channel, fail: bool| TestChannel {
+            channel,
+            fail,
+            sent: sent.clone(),
+        };
+        let mut notifier = Notifier::new()
+            .with_channel(channel(Channel::Webhook, true))
+            .with_channel(channel(Channel::Email, false))
+            .with_channel(channel(Channel::Sms, false));
+
+        let alert = alert();
+        let report = notifier
+            .notify(&Notification {
+                alert: &alert,
+                sessions: &[],
+                generated_at: Utc.ymd(2021, 5, 13).and_hms(20, 0, 0),
+                delivery: DeliveryMode::Realtime,
+            })
+            .await;
+
+        // SMS is not opted in for the alert
+        assert_eq!(*sent.lock().unwrap(), vec![Channel::Email]);
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.failed_channels(), vec![Channel::Webhook]);
+        assert!(report.is_notified());
+    }
+
+    #[tokio::test]
+    async fn test_all_channels_failed() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let mut notifier = Notifier::new().with_channel(TestChannel {
+            channel: Channel::Email,
+            fail: true,
+            sent,
+        });
+
+        let alert = alert();
+        let report = notifier
+            .notify(&Notification {
+                alert: &alert,
+                sessions: &[],
+                generated_at: Utc.ymd(2021, 5, 13).and_hms(20, 0, 0),
+                delivery: DeliveryMode::Realtime,
+            })
+            .await;
+
+        assert!(!report.is_notified());
+        assert_eq!(report.failed_channels(), vec![Channel::Email]);
+    }
+
+    #[test]
+    fn test_notification_title() {
+        let alert = alert();
+        let notification = |delivery: DeliveryMode| Notification {
+            alert: &alert,
+            sessions: &[],
+            generated_at: Utc.ymd(2021, 5, 13).and_hms(20, 0, 0),
+            delivery,
+        };
+        assert_eq!(
+            notification(DeliveryMode::Realtime).title(),
+            "Vaccine slots available"
+        );
+        assert_eq!(
+            notification(DeliveryMode::Digest).title(),
+            "Vaccine slots found since your last alert"
+        );
+    }
+}
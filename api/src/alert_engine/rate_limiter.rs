@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use tokio::{sync::Mutex, time::Instant};
+
+/// Token bucket holding up to `capacity` tokens, refilled at `refill_per_sec` tokens a
+/// second. Each request takes a token, waiting for the bucket to refill when it is empty.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// The bucket starts full, allowing a burst of `capacity` requests right away.
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        let capacity = f64::from(capacity.max(1));
+        Self {
+            capacity,
+            refill_per_sec,
+            state: Mutex::new(BucketState {
+                tokens: capacity,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token from the bucket, waiting until one is available.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let now = Instant::now();
+                let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+                state.tokens = (state.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+                state.refilled_at = now;
+                if state.tokens >= 1_f64 {
+                    state.tokens -= 1_f64;
+                    return;
+                }
+                Duration::from_secs_f64((1_f64 - state.tokens) / self.refill_per_sec)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::TokenBucket;
+
+    #[tokio::test]
+    async fn test_token_bucket_burst_then_refill() {
+        let bucket = TokenBucket::new(2, 20_f64);
+        let start = Instant::now();
+        bucket.acquire().await;
+        bucket.acquire().await;
+        assert!(start.elapsed() < Duration::from_millis(20));
+
+        // The third token is available only after a refill of 50ms
+        bucket.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+}
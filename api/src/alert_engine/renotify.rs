@@ -0,0 +1,155 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::api::alerts::{RenotifyPolicy, RenotifyTrigger};
+
+/// Change in capacity re-alerting a session with the `AnyChange` trigger.
+const VARIANCE_THRESHOLD: f32 = 1_f32;
+
+/// Latest alert of a session, as kept in the exclusion map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LastNotified {
+    /// Capacity the session was alerted with.
+    pub capacity: f32,
+    pub notified_at: DateTime<Utc>,
+    /// Number of alerts of the session so far.
+    pub count: u32,
+    /// Whether the session was seen without capacity since it was alerted.
+    pub depleted: bool,
+}
+
+/// Whether a session alerted before, now having the `capacity`, is alerted again as per
+/// the `policy` of the alert.
+pub fn should_renotify(
+    policy: &RenotifyPolicy,
+    last: &LastNotified,
+    capacity: f32,
+    now: DateTime<Utc>,
+) -> bool {
+    let RenotifyPolicy {
+        trigger,
+        min_rise,
+        min_rise_percent,
+        min_interval_minutes,
+        max_per_session,
+    } = policy;
+    if max_per_session
+        .map(|max_per_session| last.count >= max_per_session)
+        .unwrap_or(false)
+    {
+        return false;
+    }
+    if min_interval_minutes
+        .map(|minutes| now - last.notified_at < Duration::minutes(minutes.into()))
+        .unwrap_or(false)
+    {
+        return false;
+    }
+    let rise = capacity - last.capacity;
+    match trigger {
+        RenotifyTrigger::AnyChange => rise.abs() >= VARIANCE_THRESHOLD,
+        RenotifyTrigger::Restock => last.depleted && capacity >= 1_f32,
+        RenotifyTrigger::Rise => {
+            let rises_by = min_rise.map(|min_rise| rise >= min_rise).unwrap_or(false);
+            let rises_by_percent = min_rise_percent
+                .map(|percent| rise >= last.capacity * percent / 100_f32)
+                .unwrap_or(false);
+            rise > 0_f32 && (rises_by || rises_by_percent)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{should_renotify, LastNotified};
+    use crate::api::alerts::{RenotifyPolicy, RenotifyTrigger};
+
+    fn last_notified(capacity: f32) -> LastNotified {
+        LastNotified {
+            capacity,
+            notified_at: Utc.ymd(2021, 5, 13).and_hms(20, 0, 0),
+            count: 1,
+            depleted: false,
+        }
+    }
+
+    #[test]
+    fn test_any_change() {
+        let policy = RenotifyPolicy::default();
+        let last = last_notified(50_f32);
+        let now = last.notified_at + Duration::minutes(5);
+        assert!(!should_renotify(&policy, &last, 50_f32, now));
+        assert!(should_renotify(&policy, &last, 49_f32, now));
+        assert!(should_renotify(&policy, &last, 51_f32, now));
+    }
+
+    #[test]
+    fn test_restock() {
+        let policy = RenotifyPolicy {
+            trigger: RenotifyTrigger::Restock,
+            ..Default::default()
+        };
+        let last = last_notified(50_f32);
+        let now = last.notified_at + Duration::minutes(5);
+        // Capacity ticking down or up doesn't re-alert until the session runs out
+        assert!(!should_renotify(&policy, &last, 48_f32, now));
+        assert!(!should_renotify(&policy, &last, 60_f32, now));
+        let depleted = LastNotified {
+            depleted: true,
+            ..last
+        };
+        assert!(should_renotify(&policy, &depleted, 10_f32, now));
+    }
+
+    #[test]
+    fn test_rise() {
+        let last = last_notified(20_f32);
+        let now = last.notified_at + Duration::minutes(5);
+        let by_percent = RenotifyPolicy {
+            trigger: RenotifyTrigger::Rise,
+            min_rise_percent: Some(50_f32),
+            ..Default::default()
+        };
+        assert!(!should_renotify(&by_percent, &last, 19_f32, now));
+        assert!(!should_renotify(&by_percent, &last, 29_f32, now));
+        assert!(should_renotify(&by_percent, &last, 30_f32, now));
+
+        let by_amount = RenotifyPolicy {
+            min_rise: Some(5_f32),
+            ..by_percent
+        };
+        // Either of the rises re-alerts
+        assert!(should_renotify(&by_amount, &last, 25_f32, now));
+        assert!(!should_renotify(&by_amount, &last, 24_f32, now));
+    }
+
+    #[test]
+    fn test_min_interval_and_cap() {
+        let last = last_notified(20_f32);
+        let policy = RenotifyPolicy {
+            min_interval_minutes: Some(30),
+            max_per_session: Some(2),
+            ..Default::default()
+        };
+        assert!(!should_renotify(
+            &policy,
+            &last,
+            30_f32,
+            last.notified_at + Duration::minutes(29)
+        ));
+        assert!(should_renotify(
+            &policy,
+            &last,
+            30_f32,
+            last.notified_at + Duration::minutes(30)
+        ));
+        let capped = LastNotified { count: 2, ..last };
+        assert!(!should_renotify(
+            &policy,
+            &capped,
+            30_f32,
+            last.notified_at + Duration::hours(5)
+        ));
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::api::alerts::DeliveryMode;
+
+use super::{notifier::NotifyReport, shard::Shard};
+
+/// Summary of a run of the alert engine, returned by the Lambda handler.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunReport {
+    pub shard: Shard,
+    /// Delivery mode of the alerts run, realtime or digest.
+    pub delivery: DeliveryMode,
+    /// Alerts of the districts in the shard.
+    pub alerts_checked: usize,
+    /// Districts with at least one calendar week fetched successfully.
+    pub districts_fetched: usize,
+    /// Pincodes with at least one calendar week fetched successfully.
+    pub pincodes_fetched: usize,
+    /// Calendar fetches of a district or pincode which failed.
+    pub fetches_failed: usize,
+    /// Sessions matched across all the alerts, before they are sent.
+    pub sessions_matched: usize,
+    /// Alerts sent through at least one channel, in a digest run the digests sent.
+    pub alerts_notified: usize,
+    /// Alerts which couldn't be sent through any of their channels.
+    pub alerts_failed: usize,
+    /// Alerts with sessions to send held back by their quiet hours or limits, the sessions
+    /// are sent together in a later run.
+    pub alerts_deferred: usize,
+    /// Notifications sent, counting each channel of an alert.
+    pub notifications_sent: usize,
+    pub notifications_failed: usize,
+    pub errors: Vec<String>,
+}
+
+impl RunReport {
+    pub fn add_error(&mut self, context: impl Display, err: impl Display) {
+        self.errors.push(format!("{}: {}", context, err));
+    }
+
+    /// Adds the outcome of sending the alert with the `alert_key` through its channels.
+    pub fn add_notify_report(&mut self, alert_key: &str, notify_report: &NotifyReport) {
+        for (channel, res) in notify_report.results.iter() {
+            match res {
+                Ok(()) => self.notifications_sent += 1,
+                Err(err) => {
+                    self.notifications_failed += 1;
+                    self.add_error(
+                        format_args!("{} via {:?}", alert_key, channel),
+                        format!("{:#}", err),
+                    );
+                }
+            }
+        }
+        if notify_report.is_notified() {
+            self.alerts_notified += 1;
+        } else {
+            self.alerts_failed += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+
+    use super::RunReport;
+    use crate::alert_engine::notifier::{Channel, NotifyReport};
+
+    #[test]
+    fn test_add_notify_report() {
+        let mut report = RunReport::default();
+        report.add_notify_report(
+            "dummy-user-1#dummy-alert-1",
+            &NotifyReport {
+                results: vec![
+                    (Channel::Email, Ok(())),
+                    (Channel::Sms, Err(anyhow!("throttled"))),
+                ],
+            },
+        );
+        report.add_notify_report(
+            "dummy-user-2#dummy-alert-2",
+            &NotifyReport {
+                results: vec![(Channel::Email, Err(anyhow!("rejected")))],
+            },
+        );
+
+        assert_eq!(report.alerts_notified, 1);
+        assert_eq!(report.alerts_failed, 1);
+        assert_eq!(report.notifications_sent, 1);
+        assert_eq!(report.notifications_failed, 2);
+        assert_eq!(
+            report.errors,
+            vec![
+                "dummy-user-1#dummy-alert-1 via Sms: throttled".to_string(),
+                "dummy-user-2#dummy-alert-2 via Email: rejected".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ShardError {
+    #[error("Shard count must be at least 1")]
+    ZeroCount,
+    #[error("Shard index {index} is out of range for {count} shards")]
+    IndexOutOfRange { index: u32, count: u32 },
+}
+
+/// Slice of the districts processed by one invocation of the alert engine, out of
+/// `count` invocations running in parallel. Every district belongs to exactly one shard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Default for Shard {
+    /// Single shard covering every district.
+    fn default() -> Self {
+        Self { index: 0, count: 1 }
+    }
+}
+
+impl Shard {
+    pub fn new(index: u32, count: u32) -> Result<Self, ShardError> {
+        Self { index, count }.validate()
+    }
+
+    /// Checks a shard deserialized from an event payload.
+    pub fn validate(self) -> Result<Self, ShardError> {
+        if self.count == 0 {
+            Err(ShardError::ZeroCount)
+        } else if self.index >= self.count {
+            Err(ShardError::IndexOutOfRange {
+                index: self.index,
+                count: self.count,
+            })
+        } else {
+            Ok(self)
+        }
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.count == 1
+    }
+
+    /// Whether the district hashes into this shard. The hash is stable across runs and
+    /// builds, and spreads out the sequential district ids of a state over the shards.
+    pub fn contains_district(&self, district_id: u32) -> bool {
+        // Fibonacci hashing, taking the high bits of the product
+        let hash = u64::from(district_id).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32;
+        hash % u64::from(self.count) == u64::from(self.index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Shard, ShardError};
+
+    #[test]
+    fn test_shard_validate() {
+        assert_eq!(Shard::new(0, 0), Err(ShardError::ZeroCount));
+        assert_eq!(
+            Shard::new(4, 4),
+            Err(ShardError::IndexOutOfRange { index: 4, count: 4 })
+        );
+        assert_eq!(Shard::new(3, 4), Ok(Shard { index: 3, count: 4 }));
+    }
+
+    #[test]
+    fn test_shard_contains_district() {
+        let shards = (0..4)
+            .map(|index| Shard::new(index, 4).unwrap())
+            .collect::<Vec<_>>();
+        let mut per_shard = [0; 4];
+        for district_id in 1..=800 {
+            let owners = shards
+                .iter()
+                .filter(|shard| shard.contains_district(district_id))
+                .map(|shard| shard.index as usize)
+                .collect::<Vec<_>>();
+            // Exactly one shard owns a district
+            assert_eq!(owners.len(), 1);
+            per_shard[owners[0]] += 1;
+        }
+        // Districts are spread out evenly enough
+        assert!(per_shard.iter().all(|count| (150..=250).contains(count)));
+
+        assert!((1..=800).all(|district_id| Shard::default().contains_district(district_id)));
+    }
+}
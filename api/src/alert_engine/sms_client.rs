@@ -0,0 +1,465 @@
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use rusoto_core::RusotoError;
+use rusoto_sns::{MessageAttributeValue, PublishError, PublishInput, Sns, SnsClient};
+
+use super::alert_session::AlertSession;
+
+static CONFIG: Lazy<SmsConfig> = Lazy::new(SmsConfig::init);
+
+/// Maximum length of a single part SMS in the GSM 7-bit alphabet, counted in septets.
+const SMS_MAX_LEN_GSM7: usize = 160;
+/// Maximum length of a single part SMS with any char outside the GSM 7-bit alphabet, sent
+/// in UCS-2 and counted in UTF-16 code units.
+const SMS_MAX_LEN_UCS2: usize = 70;
+/// Maximum number of parts of an alert SMS, each part being charged as a separate SMS.
+const SMS_MAX_PARTS: usize = 3;
+/// Basic chars of the GSM 7-bit default alphabet, taking a septet each.
+const GSM7_BASIC_CHARS: &str = "@£$¥èéùìòÇ\nØø\rÅåΔ_ΦΓΛΩΠΨΣΘΞÆæßÉ !\"#¤%&'()*+,-./0123456789:;<=>?\
+    ¡ABCDEFGHIJKLMNOPQRSTUVWXYZÄÖÑÜ§¿abcdefghijklmnopqrstuvwxyzäöñüà";
+/// Chars of the GSM 7-bit extension table, taking two septets each.
+const GSM7_EXTENSION_CHARS: &str = "\x0c^{}\\[~]|€";
+
+#[async_trait]
+pub trait SmsClient {
+    type Error: std::error::Error + Sync + Send + 'static;
+
+    /// Sends each of the `parts` as a separate SMS to the `mobile_no`. Fails only when none
+    /// of the parts could be sent, so that a retry doesn't send any of them twice.
+    async fn send_alert_sms(
+        &mut self,
+        mobile_no: &str,
+        parts: &[String],
+    ) -> Result<(), Self::Error>;
+}
+
+/// Renders the `sessions_to_alert` under the `title` as a concise plain text SMS, split
+/// into parts fitting a single SMS each, see `split_sms`. Sessions beyond the first `SMS_MAX_PARTS` parts
+/// are left out and counted in a `+N more` tail.
+pub fn render_alert_sms(title: &str, sessions_to_alert: &[AlertSession]) -> Vec<String> {
+    let lines = sessions_to_alert
+        .iter()
+        .map(|alert_session| {
+            let AlertSession {
+                center, session, ..
+            } = alert_session;
+            format!(
+                "{} {} {} {}: D1 {} D2 {} {}+",
+                session.date,
+                session.vaccine,
+                center.name,
+                center.pincode,
+                session.available_capacity_dose1,
+                session.available_capacity_dose2,
+                session.min_age_limit,
+            )
+        })
+        .collect::<Vec<_>>();
+    let render = |shown: usize| {
+        let mut message = String::from(title);
+        for line in &lines[..shown] {
+            message.push('\n');
+            message.push_str(line);
+        }
+        if shown < lines.len() {
+            let _ = write!(message, "\n+{} more", lines.len() - shown);
+        }
+        split_sms(&message)
+    };
+    let mut parts = render(0);
+    for shown in 1..=lines.len() {
+        let next_parts = render(shown);
+        if next_parts.len() > SMS_MAX_PARTS {
+            break;
+        }
+        parts = next_parts;
+    }
+    parts
+}
+
+/// Splits the `message` into parts fitting a single SMS each, of 160 chars when all of
+/// it is in the GSM 7-bit alphabet or of 70 chars otherwise, preferring to break at a
+/// whitespace. Each part of a multi-part message is prefixed with its position, e.g. `(1/3) `.
+pub fn split_sms(message: &str) -> Vec<String> {
+    let encoding = SmsEncoding::of(message);
+    let max_len = encoding.max_len();
+    if encoding.len(message) <= max_len {
+        return vec![message.to_string()];
+    }
+    // The width of the position prefix depends on the number of parts
+    let mut parts_count = 2;
+    loop {
+        let prefix_len = format!("({}/{}) ", parts_count, parts_count).len();
+        let parts = split_at_whitespace(message, max_len - prefix_len, encoding);
+        if parts.len() <= parts_count {
+            let parts_count = parts.len();
+            return parts
+                .into_iter()
+                .enumerate()
+                .map(|(idx, part)| format!("({}/{}) {}", idx + 1, parts_count, part))
+                .collect();
+        }
+        parts_count = parts.len();
+    }
+}
+
+/// Encoding an SMS is sent in, as picked by the carrier from the chars of the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SmsEncoding {
+    Gsm7,
+    Ucs2,
+}
+
+impl SmsEncoding {
+    fn of(message: &str) -> Self {
+        let is_gsm7 = message
+            .chars()
+            .all(|c| GSM7_BASIC_CHARS.contains(c) || GSM7_EXTENSION_CHARS.contains(c));
+        if is_gsm7 {
+            SmsEncoding::Gsm7
+        } else {
+            SmsEncoding::Ucs2
+        }
+    }
+
+    fn max_len(self) -> usize {
+        match self {
+            SmsEncoding::Gsm7 => SMS_MAX_LEN_GSM7,
+            SmsEncoding::Ucs2 => SMS_MAX_LEN_UCS2,
+        }
+    }
+
+    /// Length of the `c` in the units of the `max_len`.
+    fn char_len(self, c: char) -> usize {
+        match self {
+            SmsEncoding::Gsm7 if GSM7_EXTENSION_CHARS.contains(c) => 2,
+            SmsEncoding::Gsm7 => 1,
+            SmsEncoding::Ucs2 => c.len_utf16(),
+        }
+    }
+
+    fn len(self, message: &str) -> usize {
+        message.chars().map(|c| self.char_len(c)).sum()
+    }
+}
+
+fn split_at_whitespace(message: &str, max_len: usize, encoding: SmsEncoding) -> Vec<String> {
+    let mut parts = vec![];
+    let mut rest = message.chars().collect::<Vec<char>>();
+    loop {
+        // Number of chars fitting in the `max_len`
+        let mut len = 0;
+        let fits = rest
+            .iter()
+            .take_while(|&&c| {
+                len += encoding.char_len(c);
+                len <= max_len
+            })
+            .count();
+        if fits == rest.len() {
+            break;
+        }
+        let split_at = rest[..=fits]
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .filter(|&idx| idx > 0)
+            .unwrap_or(fits);
+        parts.push(
+            rest[..split_at]
+                .iter()
+                .collect::<String>()
+                .trim_end()
+                .to_string(),
+        );
+        rest = rest[split_at..]
+            .iter()
+            .skip_while(|c| c.is_whitespace())
+            .copied()
+            .collect();
+    }
+    if !rest.is_empty() {
+        parts.push(rest.into_iter().collect());
+    }
+    parts
+}
+
+pub struct SnsSmsClient {
+    sns_client: SnsClient,
+}
+
+impl SnsSmsClient {
+    pub fn new() -> Self {
+        let sns_client = SnsClient::new(rusoto_core::Region::ApSouth1);
+        Self { sns_client }
+    }
+}
+
+impl Default for SnsSmsClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl SmsClient for SnsSmsClient {
+    type Error = RusotoError<PublishError>;
+
+    #[tracing::instrument(level = "debug", skip(self, parts))]
+    async fn send_alert_sms(
+        &mut self,
+        mobile_no: &str,
+        parts: &[String],
+    ) -> Result<(), Self::Error> {
+        let client = &self.sns_client;
+
+        let mut message_attributes = HashMap::new();
+        message_attributes.insert(
+            "AWS.SNS.SMS.SMSType".to_string(),
+            MessageAttributeValue {
+                data_type: "String".to_string(),
+                string_value: Some("Transactional".to_string()),
+                ..Default::default()
+            },
+        );
+        if let Some(sender_id) = &CONFIG.sender_id {
+            message_attributes.insert(
+                "AWS.SNS.SMS.SenderID".to_string(),
+                MessageAttributeValue {
+                    data_type: "String".to_string(),
+                    string_value: Some(sender_id.clone()),
+                    ..Default::default()
+                },
+            );
+        }
+
+        for (idx, part) in parts.iter().enumerate() {
+            let result = client
+                .publish(PublishInput {
+                    phone_number: Some(mobile_no.to_string()),
+                    message: part.clone(),
+                    message_attributes: Some(message_attributes.clone()),
+                    ..Default::default()
+                })
+                .await;
+            match result {
+                Ok(_resp) => {}
+                Err(err) if idx == 0 => return Err(err),
+                // A retry would send the parts already delivered again, so the alert is
+                // taken as sent once its first part is
+                Err(err) => {
+                    tracing::warn!(
+                        message = "Failed to send the rest of the SMS parts",
+                        sent = idx,
+                        parts = parts.len(),
+                        error = ?err
+                    );
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Keeps the sent messages in memory, keyed by the mobile number. Clones share the
+/// same messages, so that they can be inspected after handing over a clone.
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySmsClient(Arc<Mutex<HashMap<String, Vec<String>>>>);
+
+impl InMemorySmsClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn messages(&self) -> HashMap<String, Vec<String>> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl SmsClient for InMemorySmsClient {
+    type Error = std::convert::Infallible;
+
+    async fn send_alert_sms(
+        &mut self,
+        mobile_no: &str,
+        parts: &[String],
+    ) -> Result<(), Self::Error> {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(mobile_no.to_string())
+            .or_default()
+            .extend(parts.iter().cloned());
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct SmsConfig {
+    sender_id: Option<String>,
+}
+
+impl SmsConfig {
+    fn init() -> Self {
+        let sender_id = env::var("SMS_SENDER_ID")
+            .ok()
+            .filter(|sender_id| !sender_id.is_empty());
+        Self { sender_id }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        render_alert_sms, split_sms, InMemorySmsClient, SmsClient, SMS_MAX_LEN_GSM7,
+        SMS_MAX_LEN_UCS2, SMS_MAX_PARTS,
+    };
+    use crate::{
+        alert_engine::alert_session::AlertSession,
+        covin::centers::{Center, Session},
+    };
+
+    #[test]
+    fn test_render_alert_sms() {
+        let center = Center {
+            name: "Dummy Center 1".to_string(),
+            pincode: 682001,
+            ..Default::default()
+        };
+        let session = Session {
+            date: "14-05-2021".to_string(),
+            vaccine: "COVISHIELD".to_string(),
+            min_age_limit: 18,
+            available_capacity_dose1: 10_f32,
+            available_capacity_dose2: 2_f32,
+            ..Default::default()
+        };
+        let parts = render_alert_sms(
+            "Vaccine slots available",
+            &[AlertSession {
+                center: &center,
+                session: &session,
+                distance_km: None,
+            }],
+        );
+        assert_eq!(
+            parts,
+            vec![
+                "Vaccine slots available\n14-05-2021 COVISHIELD Dummy Center 1 682001: D1 10 D2 2 18+"
+                    .to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_alert_sms_max_parts() {
+        let center = Center {
+            name: "Dummy Center 1".to_string(),
+            pincode: 682001,
+            ..Default::default()
+        };
+        let sessions = (1..=20)
+            .map(|day| Session {
+                date: format!("{:02}-05-2021", day),
+                vaccine: "COVISHIELD".to_string(),
+                min_age_limit: 18,
+                available_capacity_dose1: 10_f32,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let alert_sessions = sessions
+            .iter()
+            .map(|session| AlertSession::from((session, &center)))
+            .collect::<Vec<_>>();
+
+        // Sessions that don't fit in the parts are counted at the end
+        let parts = render_alert_sms("Vaccine slots available", &alert_sessions);
+        assert_eq!(parts.len(), SMS_MAX_PARTS);
+        assert!(parts
+            .iter()
+            .all(|part| part.chars().count() <= SMS_MAX_LEN_GSM7));
+        let shown = parts
+            .iter()
+            .map(|part| part.matches("COVISHIELD").count())
+            .sum::<usize>();
+        assert!(shown > 0 && shown < 20);
+        assert!(parts[SMS_MAX_PARTS - 1].ends_with(&format!("+{} more", 20 - shown)));
+
+        // Sessions fitting in the parts are all sent
+        let parts = render_alert_sms("Vaccine slots available", &alert_sessions[..2]);
+        assert_eq!(parts.len(), 1);
+        assert!(!parts[0].contains("more"));
+    }
+
+    #[test]
+    fn test_split_sms() {
+        assert_eq!(
+            split_sms("short message"),
+            vec!["short message".to_string()]
+        );
+
+        let message = "word ".repeat(100);
+        let parts = split_sms(message.trim_end());
+        assert_eq!(parts.len(), 4);
+        assert!(parts
+            .iter()
+            .all(|part| part.chars().count() <= SMS_MAX_LEN_GSM7));
+        assert!(parts[0].starts_with("(1/4) word"));
+        assert!(parts[3].starts_with("(4/4) word"));
+        // Parts are split at whitespace and no word is lost
+        let joined = parts
+            .iter()
+            .map(|part| part[6..].to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(joined, message.trim_end());
+
+        // A message without whitespace is split at the maximum length
+        let message = "x".repeat(200);
+        let parts = split_sms(&message);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].chars().count(), SMS_MAX_LEN_GSM7);
+
+        // Chars of the extension table take two septets each
+        let message = "[x] ".repeat(40);
+        let parts = split_sms(message.trim_end());
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|part| part.chars().count()
+            + part.matches(&['[', ']'][..]).count()
+            <= SMS_MAX_LEN_GSM7));
+
+        // A char outside the GSM 7-bit alphabet sends the message in UCS-2
+        let message = format!("{} केंद्र", "word ".repeat(20).trim_end());
+        let parts = split_sms(&message);
+        assert_eq!(parts.len(), 2);
+        assert!(parts
+            .iter()
+            .all(|part| part.encode_utf16().count() <= SMS_MAX_LEN_UCS2));
+        assert!(parts[1].ends_with("word केंद्र"));
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_sms_client() {
+        let mut sms_client = InMemorySmsClient::new();
+        sms_client
+            .send_alert_sms(
+                "+919123456789",
+                &["part 1".to_string(), "part 2".to_string()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            sms_client.messages().get("+919123456789"),
+            Some(&vec!["part 1".to_string(), "part 2".to_string()])
+        );
+    }
+}
@@ -1,6 +1,8 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use tera::{Context as TeraContext, Tera};
 
-use super::alert_session::AlertSession;
+use super::{alert_session::AlertSession, clock::ist_offset};
 
 pub trait TemplateEngine {
     type Error: std::error::Error + Sync + Send + 'static;
@@ -8,9 +10,48 @@ pub trait TemplateEngine {
     fn generate_alert_content(
         &self,
         sessions_to_alert: &[AlertSession],
+        generated_at: DateTime<Utc>,
+    ) -> Result<String, Self::Error>;
+
+    /// Content of a digest, with the sessions found since the previous notification, some
+    /// of which may have closed since, ordered by the date of the session.
+    fn generate_digest_content(
+        &self,
+        sessions: &[AlertSession],
+        generated_at: DateTime<Utc>,
     ) -> Result<String, Self::Error>;
 }
 
+/// Sessions of a digest on the same date.
+#[derive(Debug, Serialize)]
+struct DigestDay<'a> {
+    date: &'a str,
+    sessions: Vec<&'a AlertSession<'a, 'a>>,
+}
+
+/// Groups the `sessions`, ordered by date, into the days of the digest.
+fn digest_days<'a>(sessions: &'a [AlertSession<'a, 'a>]) -> Vec<DigestDay<'a>> {
+    let mut days: Vec<DigestDay> = vec![];
+    for alert_session in sessions {
+        let date = alert_session.session.date.as_str();
+        match days.last_mut() {
+            Some(day) if day.date == date => day.sessions.push(alert_session),
+            _ => days.push(DigestDay {
+                date,
+                sessions: vec![alert_session],
+            }),
+        }
+    }
+    days
+}
+
+fn format_generated_at(generated_at: DateTime<Utc>) -> String {
+    generated_at
+        .with_timezone(&ist_offset())
+        .format("%d-%m-%Y %H:%M IST")
+        .to_string()
+}
+
 pub struct TeraTemplateEngine {
     tera: Tera,
 }
@@ -29,7 +70,37 @@ impl TeraTemplateEngine {
       {%- for session in sessions -%}
           {%- include "available_session" -%}
       {%- endfor -%}
+      {%- include "generated_at" -%}
       "###),
+      ("digest", r###"
+      {%- for day in days -%}
+          {%- include "digest_day" -%}
+          {%- for session in day.sessions -%}
+              {%- include "available_session" -%}
+          {%- endfor -%}
+      {%- endfor -%}
+      {%- include "generated_at" -%}
+      "###),
+      (
+          "digest_day",
+          r###"
+<tr style="border-collapse:collapse">
+<td align="left" style="margin:0;padding-top:15px;padding-bottom:5px;padding-left:40px;padding-right:40px">
+ <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#333333;font-size:17px">
+  {{ day.date }}: {{ day.sessions | length }} session{{ day.sessions | length | pluralize }} found
+ </p>
+</td></tr>"###,
+      ),
+      (
+          "generated_at",
+          r###"
+<tr style="border-collapse:collapse">
+<td align="left" style="margin:0;padding-top:5px;padding-bottom:5px;padding-left:40px;padding-right:40px">
+ <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:12px">
+  availability as of {{ generated_at }}
+ </p>
+</td></tr>"###,
+      ),
       (
           "available_session",
           r###"
@@ -44,6 +115,11 @@ impl TeraTemplateEngine {
       <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:15px">
        {{ session.center.name }}, {{ session.center.block_name }}, {{ session.center.district_name }}, {{ session.center.pincode }}
       </p>
+      {%- if session.distance_km is number %}
+      <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:15px">
+       {{ session.distance_km | round(precision=1) }} km away
+      </p>
+      {%- endif %}
      </td>
     </tr>
     <tr style="border-collapse:collapse">
@@ -55,6 +131,9 @@ impl TeraTemplateEngine {
       <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:15px">
           date: {{ session.session.date }}
       </p>
+      <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:15px">
+          vaccine: {{ session.session.vaccine }}
+      </p>
       <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:15px">
           available capacity (all): {{ session.session.available_capacity }}
       </p>
@@ -85,16 +164,33 @@ impl TemplateEngine for TeraTemplateEngine {
     fn generate_alert_content(
         &self,
         sessions_to_alert: &[AlertSession],
+        generated_at: DateTime<Utc>,
     ) -> Result<String, Self::Error> {
         let mut tera_context = TeraContext::new();
         tera_context.insert("sessions", &sessions_to_alert);
+        tera_context.insert("generated_at", &format_generated_at(generated_at));
         let content = self.tera.render("container", &tera_context)?;
         Ok(content)
     }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    fn generate_digest_content(
+        &self,
+        sessions: &[AlertSession],
+        generated_at: DateTime<Utc>,
+    ) -> Result<String, Self::Error> {
+        let mut tera_context = TeraContext::new();
+        tera_context.insert("days", &digest_days(sessions));
+        tera_context.insert("generated_at", &format_generated_at(generated_at));
+        let content = self.tera.render("digest", &tera_context)?;
+        Ok(content)
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use chrono::{TimeZone, Utc};
+
     use super::{TemplateEngine, TeraTemplateEngine};
     use crate::{
         alert_engine::alert_session::AlertSession,
@@ -106,28 +202,33 @@ mod test {
         let template_engine = TeraTemplateEngine::try_init().unwrap();
 
         let mut alert_content = template_engine
-            .generate_alert_content(&[AlertSession {
-                center: &Center {
-                    center_id: 1,
-                    name: "Dummy Center 1".to_string(),
-                    block_name: "Dummy Block".to_string(),
-                    district_name: "Dummy District".to_string(),
-                    fee_type: "Free".to_string(),
-                    pincode: 612343,
-                    from: "09:00:00".to_string(),
-                    state_name: "Kerala".to_string(),
-                    ..Default::default()
-                },
-                session: &Session {
-                    session_id: "dummy-session-1".to_string(),
-                    date: "12-01-2021".to_string(),
-                    min_age_limit: 18,
-                    available_capacity: 1_f32,
-                    available_capacity_dose1: 1_f32,
-                    available_capacity_dose2: 0_f32,
-                    slots: vec![],
-                },
-            }])
+            .generate_alert_content(
+                &[AlertSession {
+                    center: &Center {
+                        center_id: 1,
+                        name: "Dummy Center 1".to_string(),
+                        block_name: "Dummy Block".to_string(),
+                        district_name: "Dummy District".to_string(),
+                        fee_type: "Free".to_string(),
+                        pincode: 612343,
+                        from: "09:00:00".to_string(),
+                        state_name: "Kerala".to_string(),
+                        ..Default::default()
+                    },
+                    session: &Session {
+                        session_id: "dummy-session-1".to_string(),
+                        date: "12-01-2021".to_string(),
+                        vaccine: "COVISHIELD".to_string(),
+                        min_age_limit: 18,
+                        available_capacity: 1_f32,
+                        available_capacity_dose1: 1_f32,
+                        available_capacity_dose2: 0_f32,
+                        slots: vec![],
+                    },
+                    distance_km: None,
+                }],
+                Utc.ymd(2021, 5, 13).and_hms(20, 0, 0),
+            )
             .unwrap();
 
         let mut expected_alert_content = r###"
@@ -153,6 +254,9 @@ mod test {
        <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:15px">
            date: 12-01-2021
        </p>
+       <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:15px">
+           vaccine: COVISHIELD
+       </p>
        <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:15px">
            available capacity (all): 1
        </p>
@@ -169,11 +273,78 @@ mod test {
            slots: 
        </p>
       </td>
-     </tr></table></td></tr></table></td></tr>"###.to_string();
+     </tr></table></td></tr></table></td></tr>
+<tr style="border-collapse:collapse">
+<td align="left" style="margin:0;padding-top:5px;padding-bottom:5px;padding-left:40px;padding-right:40px">
+ <p style="margin:0;-webkit-text-size-adjust:none;-ms-text-size-adjust:none;mso-line-height-rule:exactly;font-family:helvetica, 'helvetica neue', arial, verdana, sans-serif;line-height:23px;color:#555555;font-size:12px">
+  availability as of 14-05-2021 01:30 IST
+ </p>
+</td></tr>"###.to_string();
 
         alert_content.retain(|c| !c.is_whitespace());
         expected_alert_content.retain(|c| !c.is_whitespace());
 
         assert_eq!(alert_content, expected_alert_content);
     }
+
+    #[test]
+    fn test_email_template_distance() {
+        let template_engine = TeraTemplateEngine::try_init().unwrap();
+
+        let alert_content = template_engine
+            .generate_alert_content(
+                &[AlertSession {
+                    center: &Center {
+                        center_id: 1,
+                        name: "Dummy Center 1".to_string(),
+                        ..Default::default()
+                    },
+                    session: &Session {
+                        session_id: "dummy-session-1".to_string(),
+                        ..Default::default()
+                    },
+                    distance_km: Some(6.644),
+                }],
+                Utc.ymd(2021, 5, 13).and_hms(20, 0, 0),
+            )
+            .unwrap();
+
+        assert!(alert_content.contains("6.6 km away"));
+    }
+
+    #[test]
+    fn test_digest_template() {
+        let template_engine = TeraTemplateEngine::try_init().unwrap();
+        let center = Center {
+            center_id: 1,
+            name: "Dummy Center 1".to_string(),
+            ..Default::default()
+        };
+        let session = |session_id: &str, date: &str| Session {
+            session_id: session_id.to_string(),
+            date: date.to_string(),
+            available_capacity: 1_f32,
+            ..Default::default()
+        };
+        let sessions = [
+            session("dummy-session-1", "14-05-2021"),
+            session("dummy-session-2", "14-05-2021"),
+            session("dummy-session-3", "15-05-2021"),
+        ];
+        let alert_sessions = sessions
+            .iter()
+            .map(|session| AlertSession::from((session, &center)))
+            .collect::<Vec<_>>();
+
+        let mut digest_content = template_engine
+            .generate_digest_content(&alert_sessions, Utc.ymd(2021, 5, 13).and_hms(20, 0, 0))
+            .unwrap();
+        digest_content.retain(|c| !c.is_whitespace());
+
+        let first_day = digest_content.find("14-05-2021:2sessionsfound").unwrap();
+        let second_day = digest_content.find("15-05-2021:1sessionfound<").unwrap();
+        assert!(first_day < second_day);
+        assert_eq!(digest_content.matches("DummyCenter1").count(), 3);
+        assert!(digest_content.ends_with("availabilityasof14-05-202101:30IST</p></td></tr>"));
+    }
 }
@@ -0,0 +1,485 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::StatusCode;
+use serde::Serialize;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::api::alerts::DeliveryMode;
+
+use super::alert_session::AlertSession;
+
+/// Version of the JSON document posted to the webhooks, bumped on breaking changes.
+pub const WEBHOOK_VERSION: &str = "1";
+
+pub const SIGNATURE_HEADER: &str = "X-Covin-Signature";
+pub const VERSION_HEADER: &str = "X-Covin-Webhook-Version";
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(2);
+const WEBHOOK_MAX_RETRIES: u32 = 2;
+const WEBHOOK_BASE_BACKOFF: Duration = Duration::from_millis(200);
+/// Sending to a webhook, retries included, gives up after this long. Webhooks are sent
+/// one after another within the 15 seconds of an engine run, so a slow endpoint must
+/// not hold up the alerts of the others.
+const WEBHOOK_DEADLINE: Duration = Duration::from_secs(3);
+
+/// Document posted to the webhook of an alert.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDocument<'a> {
+    pub version: &'static str,
+    pub alert_id: &'a str,
+    pub alert_name: Option<&'a str>,
+    /// RFC 3339 timestamp of when the sessions were found.
+    pub generated_at: String,
+    /// Digests list the sessions found since the previous notification, some of which
+    /// may have closed since.
+    pub delivery: DeliveryMode,
+    pub sessions: &'a [AlertSession<'a, 'a>],
+}
+
+#[derive(Debug, Error)]
+pub enum WebhookError {
+    #[error("Webhook request failed")]
+    RequestFail(#[from] reqwest::Error),
+    #[error("Webhook responded with status {0}")]
+    UnexpectedStatus(StatusCode),
+    #[error("JSON serialization failed")]
+    JsonSerializeFail(#[from] serde_json::Error),
+    #[error("Invalid webhook secret")]
+    InvalidSecret,
+    #[error("Invalid webhook URL")]
+    InvalidUrl,
+    #[error("Unable to resolve the webhook host")]
+    ResolveFail(#[source] std::io::Error),
+    #[error("Webhook host {0} resolves to a non-public address")]
+    ForbiddenHost(String),
+    #[error("Webhook not sent within {0:?}")]
+    DeadlineExceeded(Duration),
+}
+
+#[async_trait]
+pub trait WebhookClient {
+    type Error: std::error::Error + Sync + Send + 'static;
+
+    async fn send_alert_webhook(
+        &mut self,
+        webhook_url: &str,
+        secret: &str,
+        document: &WebhookDocument<'_>,
+    ) -> Result<(), Self::Error>;
+}
+
+/// Hex encoded HMAC-SHA256 signature of the `body`, sent as `sha256=<signature>`
+/// in the `X-Covin-Signature` header.
+pub fn sign(secret: &str, body: &[u8]) -> Result<String, WebhookError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|_| WebhookError::InvalidSecret)?;
+    mac.update(body);
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether the address is reachable over the internet, rather than one of the private,
+/// loopback or link-local addresses around the engine, such as the instance metadata.
+fn is_public_addr(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => is_public_ipv4(addr),
+        // IPv4-mapped and IPv4-compatible addresses, `::1` included as 0.0.0.1
+        IpAddr::V6(addr) => match addr.to_ipv4() {
+            Some(addr) => is_public_ipv4(addr),
+            None => is_public_ipv6(addr),
+        },
+    }
+}
+
+fn is_public_ipv4(addr: Ipv4Addr) -> bool {
+    let [a, b, ..] = addr.octets();
+    !(addr.is_private()
+        || addr.is_loopback()
+        || addr.is_link_local()
+        || addr.is_unspecified()
+        || addr.is_broadcast()
+        || addr.is_documentation()
+        || addr.is_multicast()
+        || a == 0
+        // Shared address space of carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64))
+}
+
+fn is_public_ipv6(addr: Ipv6Addr) -> bool {
+    let first = addr.segments()[0];
+    !(addr.is_loopback()
+        || addr.is_unspecified()
+        || addr.is_multicast()
+        // Unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80)
+}
+
+pub struct ReqwestWebhookClient {
+    client: reqwest::Client,
+    max_retries: u32,
+    base_backoff: Duration,
+    deadline: Duration,
+    /// Only the stand-in webhooks of the tests listen on loopback addresses.
+    allow_private_hosts: bool,
+}
+
+impl ReqwestWebhookClient {
+    pub fn new() -> Self {
+        Self::with_retries(WEBHOOK_TIMEOUT, WEBHOOK_MAX_RETRIES, WEBHOOK_BASE_BACKOFF)
+    }
+
+    /// Failed requests are retried `max_retries` times, waiting `base_backoff`
+    /// doubled on every attempt in between.
+    pub fn with_retries(timeout: Duration, max_retries: u32, base_backoff: Duration) -> Self {
+        // Redirects aren't followed, as they could lead to hosts webhooks can't be set to
+        let client = reqwest::ClientBuilder::new()
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Unable to build the webhook client");
+        Self {
+            client,
+            max_retries,
+            base_backoff,
+            deadline: WEBHOOK_DEADLINE,
+            allow_private_hosts: false,
+        }
+    }
+
+    /// Sending gives up after the `deadline`, retries included.
+    pub fn with_deadline(self, deadline: Duration) -> Self {
+        Self { deadline, ..self }
+    }
+
+    #[cfg(test)]
+    fn with_private_hosts(self) -> Self {
+        Self {
+            allow_private_hosts: true,
+            ..self
+        }
+    }
+
+    /// Resolves the host of the webhook, which has to resolve to public addresses only.
+    /// Names resolving to private addresses get past the validation of the webhook URL,
+    /// and redirects aren't followed, so the addresses are checked on every attempt.
+    async fn check_host(&self, webhook_url: &str) -> Result<(), WebhookError> {
+        let url = reqwest::Url::parse(webhook_url).map_err(|_| WebhookError::InvalidUrl)?;
+        let host = url.host_str().ok_or(WebhookError::InvalidUrl)?;
+        let port = url
+            .port_or_known_default()
+            .ok_or(WebhookError::InvalidUrl)?;
+        // IPv6 literals are bracketed in the URL
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addrs = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(WebhookError::ResolveFail)?
+            .collect::<Vec<_>>();
+        if addrs.is_empty() || addrs.iter().any(|addr| !is_public_addr(addr.ip())) {
+            return Err(WebhookError::ForbiddenHost(host.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn post(
+        &self,
+        webhook_url: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<(), WebhookError> {
+        if !self.allow_private_hosts {
+            self.check_host(webhook_url).await?;
+        }
+        let resp = self
+            .client
+            .post(webhook_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+            .header(VERSION_HEADER, WEBHOOK_VERSION)
+            .body(body.to_vec())
+            .send()
+            .await?;
+        let status = resp.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(WebhookError::UnexpectedStatus(status))
+        }
+    }
+}
+
+impl Default for ReqwestWebhookClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Only failures which may succeed on a later attempt are retried.
+fn is_retryable(err: &WebhookError) -> bool {
+    match err {
+        WebhookError::RequestFail(err) => err.is_timeout() || err.is_connect(),
+        WebhookError::UnexpectedStatus(status) => {
+            status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+        }
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl WebhookClient for ReqwestWebhookClient {
+    type Error = WebhookError;
+
+    #[tracing::instrument(level = "debug", skip(self, secret, document))]
+    async fn send_alert_webhook(
+        &mut self,
+        webhook_url: &str,
+        secret: &str,
+        document: &WebhookDocument<'_>,
+    ) -> Result<(), Self::Error> {
+        let body = serde_json::to_vec(document)?;
+        let signature = sign(secret, &body)?;
+
+        let send = async {
+            let mut attempt = 0;
+            loop {
+                match self.post(webhook_url, &signature, &body).await {
+                    Err(err) if attempt < self.max_retries && is_retryable(&err) => {
+                        let backoff = self.base_backoff * 2_u32.pow(attempt);
+                        tracing::debug!(message = "Retrying webhook", attempt, ?backoff, error = ?err);
+                        tokio::time::sleep(backoff).await;
+                        attempt += 1;
+                    }
+                    res => return res,
+                }
+            }
+        };
+        tokio::time::timeout(self.deadline, send)
+            .await
+            .unwrap_or(Err(WebhookError::DeadlineExceeded(self.deadline)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use warp::{http::StatusCode, hyper::body::Bytes, Filter};
+
+    use super::{
+        is_public_addr, sign, ReqwestWebhookClient, WebhookClient, WebhookDocument, WebhookError,
+        WEBHOOK_VERSION,
+    };
+    use crate::{
+        alert_engine::alert_session::AlertSession,
+        api::alerts::DeliveryMode,
+        covin::centers::{Center, Session},
+    };
+
+    type Requests = Arc<Mutex<Vec<(Option<String>, Bytes)>>>;
+
+    /// Stand-in webhook server, responding with the `statuses` in order and
+    /// 200 OK afterwards, after waiting for the `delay`.
+    fn serve(statuses: Vec<StatusCode>, delay: Duration) -> (SocketAddr, Requests) {
+        let requests = Requests::default();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let route = {
+            let requests = requests.clone();
+            warp::post()
+                .and(warp::path("hook"))
+                .and(warp::header::optional::<String>("x-covin-signature"))
+                .and(warp::body::bytes())
+                .and_then(move |signature: Option<String>, body: Bytes| {
+                    let requests = requests.clone();
+                    let attempts = attempts.clone();
+                    let statuses = statuses.clone();
+                    async move {
+                        tokio::time::sleep(delay).await;
+                        requests.lock().unwrap().push((signature, body));
+                        let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                        let status = statuses.get(attempt).copied().unwrap_or(StatusCode::OK);
+                        Ok::<_, warp::Rejection>(warp::reply::with_status("", status))
+                    }
+                })
+        };
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (addr, requests)
+    }
+
+    fn client() -> ReqwestWebhookClient {
+        ReqwestWebhookClient::with_retries(Duration::from_millis(500), 2, Duration::from_millis(10))
+            .with_private_hosts()
+    }
+
+    async fn send(
+        webhook_client: &mut ReqwestWebhookClient,
+        addr: SocketAddr,
+    ) -> Result<(), WebhookError> {
+        let center = Center {
+            center_id: 1,
+            name: "Dummy Center 1".to_string(),
+            ..Default::default()
+        };
+        let session = Session {
+            session_id: "dummy-session-1".to_string(),
+            ..Default::default()
+        };
+        let sessions = [AlertSession::from((&session, &center))];
+        let document = WebhookDocument {
+            version: WEBHOOK_VERSION,
+            alert_id: "dummy-alert-1",
+            alert_name: Some("Dummy Alert"),
+            generated_at: "2021-05-13T20:00:00+00:00".to_string(),
+            delivery: DeliveryMode::Realtime,
+            sessions: &sessions,
+        };
+        webhook_client
+            .send_alert_webhook(&format!("http://{}/hook", addr), "secret", &document)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_webhook_signed_document() {
+        let (addr, requests) = serve(vec![], Duration::from_millis(0));
+        send(&mut client(), addr).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let (signature, body) = &requests[0];
+        assert_eq!(
+            signature.as_deref(),
+            Some(format!("sha256={}", sign("secret", body).unwrap()).as_str())
+        );
+        let document: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(document["version"], "1");
+        assert_eq!(document["alertId"], "dummy-alert-1");
+        assert_eq!(document["delivery"], "realtime");
+        assert_eq!(
+            document["sessions"][0]["session"]["session_id"],
+            "dummy-session-1"
+        );
+    }
+
+    #[test]
+    fn test_sign() {
+        // Test vector from RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?").unwrap(),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_retries_server_errors() {
+        let (addr, requests) = serve(
+            vec![StatusCode::INTERNAL_SERVER_ERROR, StatusCode::BAD_GATEWAY],
+            Duration::from_millis(0),
+        );
+        send(&mut client(), addr).await.unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_gives_up_after_max_retries() {
+        let (addr, requests) = serve(
+            vec![StatusCode::SERVICE_UNAVAILABLE; 5],
+            Duration::from_millis(0),
+        );
+        let res = send(&mut client(), addr).await;
+        assert!(matches!(
+            res,
+            Err(WebhookError::UnexpectedStatus(
+                StatusCode::SERVICE_UNAVAILABLE
+            ))
+        ));
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_does_not_retry_client_errors() {
+        let (addr, requests) = serve(vec![StatusCode::BAD_REQUEST], Duration::from_millis(0));
+        let res = send(&mut client(), addr).await;
+        assert!(matches!(
+            res,
+            Err(WebhookError::UnexpectedStatus(StatusCode::BAD_REQUEST))
+        ));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_timeout() {
+        let (addr, _requests) = serve(vec![], Duration::from_secs(2));
+        let mut webhook_client = ReqwestWebhookClient::with_retries(
+            Duration::from_millis(100),
+            0,
+            Duration::from_millis(10),
+        )
+        .with_private_hosts();
+        let res = send(&mut webhook_client, addr).await;
+        assert!(matches!(res, Err(WebhookError::RequestFail(err)) if err.is_timeout()));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_deadline() {
+        let (addr, requests) = serve(
+            vec![StatusCode::SERVICE_UNAVAILABLE; 5],
+            Duration::from_millis(100),
+        );
+        let mut webhook_client = client().with_deadline(Duration::from_millis(150));
+        let res = send(&mut webhook_client, addr).await;
+        assert!(matches!(res, Err(WebhookError::DeadlineExceeded(_))));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_rejects_private_host() {
+        let (addr, requests) = serve(vec![], Duration::from_millis(0));
+        let mut webhook_client = ReqwestWebhookClient::new();
+        let res = send(&mut webhook_client, addr).await;
+        assert!(matches!(res, Err(WebhookError::ForbiddenHost(host)) if host == "127.0.0.1"));
+
+        // Names are checked by the addresses they resolve to
+        let res = webhook_client
+            .check_host(&format!("http://localhost:{}/hook", addr.port()))
+            .await;
+        assert!(matches!(res, Err(WebhookError::ForbiddenHost(host)) if host == "localhost"));
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_is_public_addr() {
+        let is_public = |addr: &str| is_public_addr(addr.parse().unwrap());
+        assert!(is_public("8.8.8.8"));
+        assert!(is_public("2001:4860:4860::8888"));
+        for addr in &[
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public(addr), "{}", addr);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use http_api_problem::HttpApiProblem as Problem;
-use std::convert::Infallible;
+use std::{convert::Infallible, time::Duration};
 use warp::http;
 use warp::{Rejection, Reply};
 
@@ -8,6 +8,9 @@ use crate::common::{
     auth::{AuthError, VerifierError},
     validation,
 };
+use crate::covin::{
+    centers::FindCentersError, centers_cache::CachedFindCentersError, states::LocationsError,
+};
 
 pub fn build<E: Into<anyhow::Error>>(err: E) -> Rejection {
     warp::reject::custom(pack(err.into()))
@@ -44,33 +47,58 @@ pub fn pack(err: anyhow::Error) -> Problem {
         Ok(alert_err) => match alert_err {
             AlertError::NothingFound
             | AlertError::UnableToGet(_)
+            | AlertError::UnableToList(_)
             | AlertError::UnableToParseAttr(_) => {
                 return Problem::with_title(http::StatusCode::NOT_FOUND)
             }
-            AlertError::UnableToCreate(_) | AlertError::UnableToDelete(_) => {
+            AlertError::UnableToCreate(_)
+            | AlertError::UnableToUpdate(_)
+            | AlertError::UnableToDelete(_) => {
                 return Problem::with_title(http::StatusCode::NOT_MODIFIED)
             }
         },
         Err(err) => err,
     };
 
+    // Errors of the cached calendars are shared by the requests waiting on them
+    let err = match err.downcast::<CachedFindCentersError<FindCentersError>>() {
+        Ok(CachedFindCentersError::FetchFail(find_err)) => {
+            if let Some(problem) = find_centers_problem(&find_err) {
+                return problem;
+            }
+            CachedFindCentersError::FetchFail(find_err).into()
+        }
+        Ok(cache_err) => cache_err.into(),
+        Err(err) => err,
+    };
+
+    let err = match err.downcast::<LocationsError<FindCentersError>>() {
+        Ok(LocationsError::Unavailable(retry_in)) => return unavailable_problem(retry_in),
+        Ok(LocationsError::LoadFail(find_err)) => {
+            if let Some(problem) = find_centers_problem(&find_err) {
+                return problem;
+            }
+            LocationsError::LoadFail(find_err).into()
+        }
+        Err(err) => err,
+    };
+
+    let err = match err.downcast::<FindCentersError>() {
+        Ok(find_err) => {
+            if let Some(problem) = find_centers_problem(&find_err) {
+                return problem;
+            }
+            find_err.into()
+        }
+        Err(err) => err,
+    };
+
     let err: anyhow::Error = match err.downcast::<reqwest::Error>() {
         Ok(req_err) => {
-            let status = req_err.status();
-            match (status, req_err.is_status()) {
-                (Some(status), true) => {
-                    let content = req_err.to_string();
-                    let problem = {
-                        let mut problem = Problem::with_title_and_type(status)
-                            .title("Proxy Error")
-                            .detail("A proxy error occured refer the errors property for details");
-                        problem.set_value("errors", &content);
-                        problem
-                    };
-                    return problem;
-                }
-                _ => req_err.into(),
+            if let Some(problem) = proxy_problem(&req_err) {
+                return problem;
             }
+            req_err.into()
         }
         Err(err) => err,
     };
@@ -80,6 +108,41 @@ pub fn pack(err: anyhow::Error) -> Problem {
     Problem::with_title_and_type(http::StatusCode::INTERNAL_SERVER_ERROR)
 }
 
+/// Error status of CoWIN passed on to the client.
+fn proxy_problem(req_err: &reqwest::Error) -> Option<Problem> {
+    match (req_err.status(), req_err.is_status()) {
+        (Some(status), true) => {
+            let content = req_err.to_string();
+            let problem = {
+                let mut problem = Problem::with_title_and_type(status)
+                    .title("Proxy Error")
+                    .detail("A proxy error occured refer the errors property for details");
+                problem.set_value("errors", &content);
+                problem
+            };
+            Some(problem)
+        }
+        _ => None,
+    }
+}
+
+fn find_centers_problem(find_err: &FindCentersError) -> Option<Problem> {
+    match find_err {
+        FindCentersError::CircuitOpen(retry_in) => Some(unavailable_problem(*retry_in)),
+        FindCentersError::RequestFail(req_err) => proxy_problem(req_err),
+        FindCentersError::JsonDeserializeFail(_) => None,
+    }
+}
+
+/// CoWIN left alone by the circuit breaker for the time being.
+fn unavailable_problem(retry_in: Duration) -> Problem {
+    let mut problem = Problem::with_title_and_type(http::StatusCode::SERVICE_UNAVAILABLE)
+        .title("CoWIN Unavailable")
+        .detail("CoWIN is failing the requests, retry after the retryAfter property seconds");
+    problem.set_value("retryAfter", &retry_in.as_secs().max(1));
+    problem
+}
+
 fn reply_from_problem(problem: &Problem) -> impl Reply {
     let code = problem
         .status
@@ -1,13 +1,20 @@
 use std::collections::HashMap;
 
 use serde::de::DeserializeOwned;
-use validator::{Validate, ValidationErrors, ValidationErrorsKind};
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
 use warp::{Filter, Rejection};
 
 #[derive(Debug)]
 pub(crate) struct Error(ValidationErrors);
 
 impl Error {
+    /// Error of a single field, for the checks made past `with_validated_json`.
+    pub fn field(field: &'static str, code: &'static str) -> Self {
+        let mut errors = ValidationErrors::new();
+        errors.add(field, ValidationError::new(code));
+        Self(errors)
+    }
+
     pub fn errors(&self) -> &HashMap<&'static str, ValidationErrorsKind> {
         &self.0.errors()
     }
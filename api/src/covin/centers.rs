@@ -1,22 +1,39 @@
 use crate::common::problem;
+use once_cell::sync::Lazy;
 use serde::Deserialize;
-pub use service::{Center, CenterResponse, CovinFindCenters, FindCenters, Session};
-use warp::Filter;
+pub use service::{
+    Center, CenterResponse, CovinFindCenters, DaySession, FindCenters, FindCentersError, Session,
+    SessionResponse, VaccineFee,
+};
+use warp::{
+    http::{header, Response, StatusCode},
+    Filter,
+};
+
+use super::centers_cache::{CacheConfig, CachedCalendar, CachedFindCenters, CalendarKey};
+
+/// Calendars cached across the requests served by this instance of the proxy.
+pub(super) static FIND_CENTERS: Lazy<CachedFindCenters<CovinFindCenters>> = Lazy::new(|| {
+    CachedFindCenters::new(CovinFindCenters::new()).with_config(CacheConfig::from_env())
+});
 
 pub fn routes() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    warp::path("centers")
-        .and(warp::get())
-        .and(warp::path::end())
+    let by_district = warp::path::end()
         .and(warp::query::<CenterQueryParams>())
+        .and(warp::header::optional::<String>("if-none-match"))
         .and_then(
             |CenterQueryParams {
                  district_id,
                  date,
                  vaccine,
-             }| async move {
-                let find_centers = CovinFindCenters::new();
-                let centers = find_centers
-                    .get_all_centers_by_district(&district_id, &date, vaccine.as_deref())
+             },
+             if_none_match: Option<String>| async move {
+                let calendar = FIND_CENTERS
+                    .get_calendar(CalendarKey::district(
+                        &district_id,
+                        &date,
+                        vaccine.as_deref(),
+                    ))
                     .await
                     .map_err(problem::build)?;
                 tracing::info!(
@@ -25,32 +42,95 @@ pub fn routes() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Reje
                     %date,
                     %district_id,
                     vaccine = vaccine.as_deref().unwrap_or("*"),
-                    %centers
+                    centers = %calendar.body
+                );
+                calendar_reply(&calendar, if_none_match)
+            },
+        );
+
+    let by_pin = warp::path("pin")
+        .and(warp::path::end())
+        .and(warp::query::<CenterByPinQueryParams>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and_then(
+            |CenterByPinQueryParams {
+                 pincode,
+                 date,
+                 vaccine,
+             },
+             if_none_match: Option<String>| async move {
+                let calendar = FIND_CENTERS
+                    .get_calendar(CalendarKey::pin(&pincode, &date, vaccine.as_deref()))
+                    .await
+                    .map_err(problem::build)?;
+                tracing::info!(
+                    target: "covin::proxy",
+                    message = "vaccination centers by pincode",
+                    %date,
+                    %pincode,
+                    vaccine = vaccine.as_deref().unwrap_or("*"),
+                    centers = %calendar.body
                 );
-                Ok::<_, warp::reject::Rejection>(warp::reply::with_header(
-                    centers,
-                    "Content-Type",
-                    "application/json",
-                ))
+                calendar_reply(&calendar, if_none_match)
             },
-        )
+        );
+
+    warp::path("centers")
+        .and(warp::get())
+        .and(by_district.or(by_pin))
         .with(warp::trace::named("centers"))
 }
 
+/// Calendar with its caching headers, an empty `304 Not Modified` when the client already
+/// has the same calendar as per `If-None-Match`.
+fn calendar_reply(
+    calendar: &CachedCalendar,
+    if_none_match: Option<String>,
+) -> Result<Response<String>, warp::Rejection> {
+    let not_modified = if_none_match
+        .map(|if_none_match| {
+            if_none_match
+                .split(',')
+                .any(|etag| etag.trim() == calendar.etag || etag.trim() == "*")
+        })
+        .unwrap_or(false);
+    let builder = Response::builder()
+        .header(header::ETAG, &calendar.etag)
+        .header(header::CACHE_CONTROL, FIND_CENTERS.cache_control(calendar));
+    let reply = if not_modified {
+        builder.status(StatusCode::NOT_MODIFIED).body(String::new())
+    } else {
+        builder
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(calendar.body.to_string())
+    };
+    reply.map_err(problem::build)
+}
+
 #[derive(Debug, Deserialize)]
 struct CenterQueryParams {
     pub district_id: String,
     pub date: String,
     pub vaccine: Option<String>,
 }
+
+#[derive(Debug, Deserialize)]
+struct CenterByPinQueryParams {
+    pub pincode: String,
+    pub date: String,
+    pub vaccine: Option<String>,
+}
+
 mod service {
-    use std::env;
+    use std::{env, sync::Arc, time::Duration};
 
     use async_trait::async_trait;
     use once_cell::sync::Lazy;
     use serde::{Deserialize, Serialize};
     use thiserror::Error;
 
+    use crate::covin::upstream::{UpstreamConfig, UpstreamStats, Upstreams};
+
     static CONFIG: Lazy<CentersConfig> = Lazy::new(CentersConfig::init);
 
     #[derive(Debug, Error)]
@@ -59,10 +139,12 @@ mod service {
         RequestFail(#[from] reqwest::Error),
         #[error("JSON deserialization failed")]
         JsonDeserializeFail(#[from] serde_json::Error),
+        #[error("CoWIN is unavailable, retry in {0:?}")]
+        CircuitOpen(Duration),
     }
 
     #[async_trait]
-    pub trait FindCenters {
+    pub trait FindCenters: Sync {
         type Error: std::error::Error + Sync + Send + 'static;
 
         async fn get_all_centers_by_district(
@@ -78,15 +160,72 @@ mod service {
             date: &str,
             vaccine: Option<&str>,
         ) -> std::result::Result<CenterResponse, Self::Error>;
+
+        async fn get_all_centers_by_pin(
+            &self,
+            pincode: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<String, Self::Error>;
+
+        async fn get_all_centers_by_pin_json(
+            &self,
+            pincode: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<CenterResponse, Self::Error>;
+
+        /// Sessions of the district on the single `date`, out of its calendar unless
+        /// there is a cheaper way to find them.
+        async fn get_sessions_by_district_json(
+            &self,
+            district_id: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<SessionResponse, Self::Error> {
+            let centers = self
+                .get_all_centers_by_district_json(district_id, date, vaccine)
+                .await?;
+            Ok(SessionResponse::on_date(centers, date))
+        }
+
+        /// Sessions of the pincode on the single `date`, out of its calendar unless
+        /// there is a cheaper way to find them.
+        async fn get_sessions_by_pin_json(
+            &self,
+            pincode: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<SessionResponse, Self::Error> {
+            let centers = self
+                .get_all_centers_by_pin_json(pincode, date, vaccine)
+                .await?;
+            Ok(SessionResponse::on_date(centers, date))
+        }
     }
 
-    #[derive(Default)]
+    /// Finds the centers in the calendars of CoWIN. Failed requests are retried as per the
+    /// `UpstreamConfig`, and fall over to the next of the upstreams. Clones share the health
+    /// of the upstreams.
+    #[derive(Clone)]
     pub struct CovinFindCenters {
         client: reqwest::Client,
+        config: UpstreamConfig,
+        upstreams: Arc<Upstreams>,
+    }
+
+    impl Default for CovinFindCenters {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl CovinFindCenters {
         pub fn new() -> Self {
+            Self::with_config(UpstreamConfig::from_env())
+        }
+
+        pub fn with_config(config: UpstreamConfig) -> Self {
             let headers = {
                 let mut headers = reqwest::header::HeaderMap::new();
                 headers.insert(
@@ -105,41 +244,126 @@ mod service {
             };
             let client = reqwest::ClientBuilder::new()
                 .default_headers(headers)
+                .timeout(config.request_timeout)
                 .build()
                 .unwrap_or_default();
-            Self { client }
+            let upstreams = Upstreams::new(&CONFIG.base_urls, &config);
+            Self {
+                client,
+                config,
+                upstreams: Arc::new(upstreams),
+            }
         }
 
-        async fn get_all_centers_by_district_base(
+        /// Responses served by each of the upstreams, and their health.
+        pub fn upstream_stats(&self) -> Vec<UpstreamStats> {
+            self.upstreams.stats()
+        }
+
+        async fn get_base(
             &self,
-            district_id: &str,
-            date: &str,
-            vaccine: Option<&str>,
+            path: &str,
+            query: &[(&str, &str)],
         ) -> std::result::Result<reqwest::Response, FindCentersError> {
             let client = &self.client;
+            self.upstreams
+                .send(&self.config, |base_url| {
+                    client
+                        .get(format!("{}/v2/{}", base_url, path))
+                        .query(query)
+                        .send()
+                })
+                .await
+        }
+
+        /// Location metadata of CoWIN, as in `states` or `districts/{state_id}`.
+        pub async fn get_location_base(
+            &self,
+            location: &str,
+        ) -> std::result::Result<reqwest::Response, FindCentersError> {
+            self.get_base(&format!("admin/location/{}", location), &[])
+                .await
+        }
+
+        async fn get_calendar_base(
+            &self,
+            calendar: &str,
+            query: &[(&str, &str)],
+            vaccine: Option<&str>,
+        ) -> std::result::Result<reqwest::Response, FindCentersError> {
             let query = {
-                let mut query = vec![("district_id", district_id), ("date", date)];
-                if vaccine.is_some() {
-                    query.push(("vaccine", vaccine.unwrap()))
+                let mut query = query.to_vec();
+                if let Some(vaccine) = vaccine {
+                    query.push(("vaccine", vaccine))
                 }
                 query
             };
-            Ok(client
-                .get(format!(
-                    "{}/{}",
-                    CONFIG.base_url, "v2/appointment/sessions/public/calendarByDistrict"
-                ))
-                .query(&query)
-                .send()
+            self.get_base(&format!("appointment/sessions/public/{}", calendar), &query)
                 .await
-                .and_then(|resp| {
-                    let status = resp.status();
-                    if status.is_success() || status.is_redirection() {
-                        Ok(resp)
-                    } else {
-                        resp.error_for_status()
-                    }
-                })?)
+        }
+
+        async fn get_all_centers_by_district_base(
+            &self,
+            district_id: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<reqwest::Response, FindCentersError> {
+            self.get_calendar_base(
+                "calendarByDistrict",
+                &[("district_id", district_id), ("date", date)],
+                vaccine,
+            )
+            .await
+        }
+
+        async fn get_all_centers_by_pin_base(
+            &self,
+            pincode: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<reqwest::Response, FindCentersError> {
+            self.get_calendar_base(
+                "calendarByPin",
+                &[("pincode", pincode), ("date", date)],
+                vaccine,
+            )
+            .await
+        }
+
+        #[tracing::instrument(skip(self))]
+        pub async fn get_sessions_by_district(
+            &self,
+            district_id: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<String, FindCentersError> {
+            Ok(self
+                .get_calendar_base(
+                    "findByDistrict",
+                    &[("district_id", district_id), ("date", date)],
+                    vaccine,
+                )
+                .await?
+                .text()
+                .await?)
+        }
+
+        #[tracing::instrument(skip(self))]
+        pub async fn get_sessions_by_pin(
+            &self,
+            pincode: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<String, FindCentersError> {
+            Ok(self
+                .get_calendar_base(
+                    "findByPin",
+                    &[("pincode", pincode), ("date", date)],
+                    vaccine,
+                )
+                .await?
+                .text()
+                .await?)
         }
     }
 
@@ -174,6 +398,56 @@ mod service {
                 .text()
                 .await?)
         }
+
+        #[tracing::instrument(skip(self))]
+        async fn get_all_centers_by_pin_json(
+            &self,
+            pincode: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<CenterResponse, Self::Error> {
+            Ok(self
+                .get_all_centers_by_pin_base(pincode, date, vaccine)
+                .await?
+                .json()
+                .await?)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn get_all_centers_by_pin(
+            &self,
+            pincode: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<String, Self::Error> {
+            Ok(self
+                .get_all_centers_by_pin_base(pincode, date, vaccine)
+                .await?
+                .text()
+                .await?)
+        }
+
+        async fn get_sessions_by_district_json(
+            &self,
+            district_id: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<SessionResponse, Self::Error> {
+            let sessions = self
+                .get_sessions_by_district(district_id, date, vaccine)
+                .await?;
+            Ok(serde_json::from_str(&sessions)?)
+        }
+
+        async fn get_sessions_by_pin_json(
+            &self,
+            pincode: &str,
+            date: &str,
+            vaccine: Option<&str>,
+        ) -> std::result::Result<SessionResponse, Self::Error> {
+            let sessions = self.get_sessions_by_pin(pincode, date, vaccine).await?;
+            Ok(serde_json::from_str(&sessions)?)
+        }
     }
 
     #[derive(Debug, Deserialize, Serialize)]
@@ -181,7 +455,7 @@ mod service {
         pub centers: Vec<Center>,
     }
 
-    #[derive(Debug, Deserialize, Serialize, Default)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
     pub struct Center {
         pub center_id: u32,
         pub name: String,
@@ -195,22 +469,232 @@ mod service {
         pub long: f32,
         pub fee_type: String,
         pub sessions: Vec<Session>,
+        #[serde(default)]
+        pub vaccine_fees: Vec<VaccineFee>,
     }
 
-    #[derive(Debug, Deserialize, Serialize, Default)]
+    impl Center {
+        /// Fee charged at this center for the given `vaccine`, free centers are always `Some(0)`.
+        /// Returns `None` when a paid center doesn't publish a (parseable) fee for the vaccine.
+        pub fn fee_for(&self, vaccine: &str) -> Option<u32> {
+            if self.fee_type.eq_ignore_ascii_case("Free") {
+                return Some(0);
+            }
+            self.vaccine_fees
+                .iter()
+                .find(|vaccine_fee| vaccine_fee.vaccine.eq_ignore_ascii_case(vaccine))
+                .and_then(|vaccine_fee| vaccine_fee.fee.trim().parse::<u32>().ok())
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
+    pub struct VaccineFee {
+        pub vaccine: String,
+        pub fee: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
     pub struct Session {
         pub session_id: String,
         pub available_capacity: f32,
         pub min_age_limit: u16,
         pub date: String,
+        pub vaccine: String,
         pub slots: Vec<String>,
         pub available_capacity_dose1: f32,
         pub available_capacity_dose2: f32,
     }
 
+    /// Sessions of a single day, as returned by the `findByDistrict` and `findByPin` APIs
+    /// of CoWIN.
+    #[derive(Debug, Deserialize, Serialize, Default)]
+    pub struct SessionResponse {
+        pub sessions: Vec<DaySession>,
+    }
+
+    /// Session along with the details of its center.
+    #[derive(Debug, Deserialize, Serialize, Default)]
+    pub struct DaySession {
+        pub center_id: u32,
+        pub name: String,
+        #[serde(default)]
+        pub address: String,
+        pub state_name: String,
+        pub district_name: String,
+        pub block_name: String,
+        pub pincode: u32,
+        pub from: String,
+        pub to: String,
+        pub lat: f32,
+        pub long: f32,
+        pub fee_type: String,
+        /// Fee of the `vaccine`, `"0"` at the free centers.
+        #[serde(default)]
+        pub fee: String,
+        pub session_id: String,
+        pub date: String,
+        pub available_capacity: f32,
+        #[serde(default)]
+        pub available_capacity_dose1: f32,
+        #[serde(default)]
+        pub available_capacity_dose2: f32,
+        pub min_age_limit: u16,
+        pub vaccine: String,
+        #[serde(default)]
+        pub slots: Vec<String>,
+    }
+
+    impl SessionResponse {
+        /// Sessions of the `centers` on the `date`, as the single day APIs would return them.
+        pub fn on_date(centers: CenterResponse, date: &str) -> Self {
+            let sessions = centers
+                .centers
+                .into_iter()
+                .flat_map(|center| {
+                    let Center {
+                        center_id,
+                        name,
+                        state_name,
+                        district_name,
+                        block_name,
+                        pincode,
+                        from,
+                        to,
+                        lat,
+                        long,
+                        fee_type,
+                        sessions,
+                        vaccine_fees,
+                    } = center;
+                    let date = date.to_string();
+                    sessions
+                        .into_iter()
+                        .filter(move |session| session.date == date)
+                        .map(move |session| {
+                            let fee = if fee_type.eq_ignore_ascii_case("Free") {
+                                "0".to_string()
+                            } else {
+                                vaccine_fees
+                                    .iter()
+                                    .find(|vaccine_fee| {
+                                        vaccine_fee.vaccine.eq_ignore_ascii_case(&session.vaccine)
+                                    })
+                                    .map(|vaccine_fee| vaccine_fee.fee.clone())
+                                    .unwrap_or_default()
+                            };
+                            DaySession {
+                                center_id,
+                                name: name.clone(),
+                                address: String::new(),
+                                state_name: state_name.clone(),
+                                district_name: district_name.clone(),
+                                block_name: block_name.clone(),
+                                pincode,
+                                from: from.clone(),
+                                to: to.clone(),
+                                lat,
+                                long,
+                                fee_type: fee_type.clone(),
+                                fee,
+                                session_id: session.session_id,
+                                date: session.date,
+                                available_capacity: session.available_capacity,
+                                available_capacity_dose1: session.available_capacity_dose1,
+                                available_capacity_dose2: session.available_capacity_dose2,
+                                min_age_limit: session.min_age_limit,
+                                vaccine: session.vaccine,
+                                slots: session.slots,
+                            }
+                        })
+                })
+                .collect();
+            Self { sessions }
+        }
+    }
+
+    /// Sessions grouped back into their centers, in the order the centers first appear.
+    impl From<SessionResponse> for CenterResponse {
+        fn from(response: SessionResponse) -> Self {
+            let mut centers = Vec::<Center>::new();
+            for day_session in response.sessions {
+                let DaySession {
+                    center_id,
+                    name,
+                    address: _,
+                    state_name,
+                    district_name,
+                    block_name,
+                    pincode,
+                    from,
+                    to,
+                    lat,
+                    long,
+                    fee_type,
+                    fee,
+                    session_id,
+                    date,
+                    available_capacity,
+                    available_capacity_dose1,
+                    available_capacity_dose2,
+                    min_age_limit,
+                    vaccine,
+                    slots,
+                } = day_session;
+                let index = match centers
+                    .iter()
+                    .position(|center| center.center_id == center_id)
+                {
+                    Some(index) => index,
+                    None => {
+                        centers.push(Center {
+                            center_id,
+                            name,
+                            state_name,
+                            district_name,
+                            block_name,
+                            pincode,
+                            from,
+                            to,
+                            lat,
+                            long,
+                            fee_type,
+                            sessions: vec![],
+                            vaccine_fees: vec![],
+                        });
+                        centers.len() - 1
+                    }
+                };
+                let center = &mut centers[index];
+                if !fee.is_empty()
+                    && !center
+                        .vaccine_fees
+                        .iter()
+                        .any(|vaccine_fee| vaccine_fee.vaccine == vaccine)
+                {
+                    center.vaccine_fees.push(VaccineFee {
+                        vaccine: vaccine.clone(),
+                        fee,
+                    });
+                }
+                center.sessions.push(Session {
+                    session_id,
+                    available_capacity,
+                    min_age_limit,
+                    date,
+                    vaccine,
+                    slots,
+                    available_capacity_dose1,
+                    available_capacity_dose2,
+                });
+            }
+            Self { centers }
+        }
+    }
+
     #[derive(Debug)]
     struct CentersConfig {
-        pub base_url: String,
+        /// Base URLs of CoWIN in the order they are tried.
+        pub base_urls: Vec<String>,
         pub user_agent_header: String,
         pub referer_header: String,
         pub origin_header: String,
@@ -218,12 +702,20 @@ mod service {
 
     impl CentersConfig {
         fn init() -> Self {
-            let base_url = env::var("BASE_URL").unwrap();
+            // Comma separated `BASE_URLS`, or the single `BASE_URL`
+            let base_urls = env::var("BASE_URLS")
+                .or_else(|_| env::var("BASE_URL"))
+                .unwrap()
+                .split(',')
+                .map(str::trim)
+                .filter(|base_url| !base_url.is_empty())
+                .map(str::to_string)
+                .collect();
             let user_agent_header = env::var("USER_AGENT_HEADER").unwrap();
             let referer_header = env::var("REFERER_HEADER").unwrap();
             let origin_header = env::var("ORIGIN_HEADER").unwrap();
             Self {
-                base_url,
+                base_urls,
                 user_agent_header,
                 referer_header,
                 origin_header,
@@ -0,0 +1,498 @@
+use std::{
+    collections::HashMap,
+    env,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::centers::{CenterResponse, FindCenters, SessionResponse};
+
+const CACHE_TTL: Duration = Duration::from_secs(60);
+const CACHE_STALE_TTL: Duration = Duration::from_secs(240);
+
+/// How long the calendars fetched from CoWIN are served from the cache.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Calendars younger than this are served without going to CoWIN.
+    pub ttl: Duration,
+    /// For this long after the `ttl`, calendars are still served while they are fetched
+    /// again in the background.
+    pub stale_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: CACHE_TTL,
+            stale_ttl: CACHE_STALE_TTL,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Defaults overridden by the `CACHE_TTL_SECS` and `CACHE_STALE_TTL_SECS` env vars.
+    pub fn from_env() -> Self {
+        fn var<T: FromStr>(key: &str) -> Option<T> {
+            env::var(key).ok().and_then(|val| val.parse().ok())
+        }
+        let default = Self::default();
+        Self {
+            ttl: var("CACHE_TTL_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.ttl),
+            stale_ttl: var("CACHE_STALE_TTL_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.stale_ttl),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Calendar {
+    District,
+    Pin,
+}
+
+/// Request of a calendar, the calendars are cached by the whole request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CalendarKey {
+    pub calendar: Calendar,
+    /// District id or pincode, as per the `calendar`.
+    pub id: String,
+    pub date: String,
+    pub vaccine: Option<String>,
+}
+
+impl CalendarKey {
+    pub fn district(district_id: &str, date: &str, vaccine: Option<&str>) -> Self {
+        Self::new(Calendar::District, district_id, date, vaccine)
+    }
+
+    pub fn pin(pincode: &str, date: &str, vaccine: Option<&str>) -> Self {
+        Self::new(Calendar::Pin, pincode, date, vaccine)
+    }
+
+    fn new(calendar: Calendar, id: &str, date: &str, vaccine: Option<&str>) -> Self {
+        Self {
+            calendar,
+            id: id.to_string(),
+            date: date.to_string(),
+            vaccine: vaccine.map(str::to_string),
+        }
+    }
+}
+
+/// Calendar as returned by CoWIN, along with the `etag` of its body.
+#[derive(Debug, Clone)]
+pub struct CachedCalendar {
+    pub body: Arc<str>,
+    pub etag: String,
+    fetched_at: Instant,
+}
+
+impl CachedCalendar {
+    fn new(body: String) -> Self {
+        let etag = format!("\"{}\"", hex::encode(Sha256::digest(body.as_bytes())));
+        Self {
+            body: body.into(),
+            etag,
+            fetched_at: Instant::now(),
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        self.fetched_at.elapsed()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CachedFindCentersError<E: std::error::Error + 'static> {
+    /// Error of the request shared by all the callers waiting on it.
+    #[error(transparent)]
+    FetchFail(Arc<E>),
+    #[error("JSON deserialization failed")]
+    JsonDeserializeFail(#[from] serde_json::Error),
+}
+
+type Fetch<E> = Shared<BoxFuture<'static, Result<CachedCalendar, Arc<E>>>>;
+
+/// Caches the calendars fetched through `F`. Concurrent requests of the same calendar
+/// share a single request to CoWIN, and calendars past their TTL are served stale while
+/// they are fetched again. Clones share the cache.
+pub struct CachedFindCenters<F: FindCenters> {
+    find_centers: Arc<F>,
+    config: CacheConfig,
+    calendars: Arc<Mutex<HashMap<CalendarKey, CachedCalendar>>>,
+    in_flight: Arc<Mutex<HashMap<CalendarKey, Fetch<F::Error>>>>,
+}
+
+impl<F: FindCenters> Clone for CachedFindCenters<F> {
+    fn clone(&self) -> Self {
+        Self {
+            find_centers: self.find_centers.clone(),
+            config: self.config.clone(),
+            calendars: self.calendars.clone(),
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+impl<F> CachedFindCenters<F>
+where
+    F: FindCenters + Send + Sync + 'static,
+{
+    pub fn new(find_centers: F) -> Self {
+        Self {
+            find_centers: Arc::new(find_centers),
+            config: CacheConfig::default(),
+            calendars: Default::default(),
+            in_flight: Default::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: CacheConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Value of the `Cache-Control` header for the `calendar`, as fresh as it is in here.
+    pub fn cache_control(&self, calendar: &CachedCalendar) -> String {
+        let CacheConfig { ttl, stale_ttl } = self.config;
+        format!(
+            "public, max-age={}, stale-while-revalidate={}",
+            ttl.checked_sub(calendar.age())
+                .unwrap_or_default()
+                .as_secs(),
+            stale_ttl.as_secs()
+        )
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn get_calendar(
+        &self,
+        key: CalendarKey,
+    ) -> Result<CachedCalendar, CachedFindCentersError<F::Error>> {
+        let CacheConfig { ttl, stale_ttl } = self.config;
+        let cached = self.calendars.lock().unwrap().get(&key).cloned();
+        match cached {
+            Some(calendar) if calendar.age() < ttl => return Ok(calendar),
+            Some(calendar) if calendar.age() < ttl + stale_ttl => {
+                tracing::debug!(message = "Revalidating stale calendar", age = ?calendar.age());
+                let fetch = self.fetch(key);
+                tokio::spawn(async move {
+                    if let Err(err) = fetch.await {
+                        tracing::warn!(message = "Unable to revalidate calendar", error = ?err);
+                    }
+                });
+                return Ok(calendar);
+            }
+            _ => {}
+        }
+        self.fetch(key)
+            .await
+            .map_err(CachedFindCentersError::FetchFail)
+    }
+
+    /// Request of the calendar in flight, a new one when there is none. The calendar is
+    /// cached once fetched, whoever is waiting on it.
+    fn fetch(&self, key: CalendarKey) -> Fetch<F::Error> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(fetch) = in_flight.get(&key) {
+            return fetch.clone();
+        }
+        let find_centers = self.find_centers.clone();
+        let calendars = self.calendars.clone();
+        let fetches = self.in_flight.clone();
+        let expires_in = self.config.ttl + self.config.stale_ttl;
+        let fetch_key = key.clone();
+        let fetch = async move {
+            let res = fetch_calendar(&*find_centers, &fetch_key).await;
+            if let Ok(calendar) = &res {
+                let mut calendars = calendars.lock().unwrap();
+                calendars.retain(|_key, calendar| calendar.age() < expires_in);
+                calendars.insert(fetch_key.clone(), calendar.clone());
+            }
+            fetches.lock().unwrap().remove(&fetch_key);
+            res
+        }
+        .boxed()
+        .shared();
+        in_flight.insert(key, fetch.clone());
+        fetch
+    }
+}
+
+async fn fetch_calendar<F: FindCenters>(
+    find_centers: &F,
+    key: &CalendarKey,
+) -> Result<CachedCalendar, Arc<F::Error>> {
+    let CalendarKey {
+        calendar,
+        id,
+        date,
+        vaccine,
+    } = key;
+    let body = match calendar {
+        Calendar::District => {
+            find_centers
+                .get_all_centers_by_district(id, date, vaccine.as_deref())
+                .await
+        }
+        Calendar::Pin => {
+            find_centers
+                .get_all_centers_by_pin(id, date, vaccine.as_deref())
+                .await
+        }
+    }
+    .map_err(Arc::new)?;
+    Ok(CachedCalendar::new(body))
+}
+
+#[async_trait]
+impl<F> FindCenters for CachedFindCenters<F>
+where
+    F: FindCenters + Send + Sync + 'static,
+{
+    type Error = CachedFindCentersError<F::Error>;
+
+    async fn get_all_centers_by_district(
+        &self,
+        district_id: &str,
+        date: &str,
+        vaccine: Option<&str>,
+    ) -> std::result::Result<String, Self::Error> {
+        let calendar = self
+            .get_calendar(CalendarKey::district(district_id, date, vaccine))
+            .await?;
+        Ok(calendar.body.to_string())
+    }
+
+    async fn get_all_centers_by_district_json(
+        &self,
+        district_id: &str,
+        date: &str,
+        vaccine: Option<&str>,
+    ) -> std::result::Result<CenterResponse, Self::Error> {
+        let calendar = self
+            .get_calendar(CalendarKey::district(district_id, date, vaccine))
+            .await?;
+        Ok(serde_json::from_str(&calendar.body)?)
+    }
+
+    async fn get_all_centers_by_pin(
+        &self,
+        pincode: &str,
+        date: &str,
+        vaccine: Option<&str>,
+    ) -> std::result::Result<String, Self::Error> {
+        let calendar = self
+            .get_calendar(CalendarKey::pin(pincode, date, vaccine))
+            .await?;
+        Ok(calendar.body.to_string())
+    }
+
+    async fn get_all_centers_by_pin_json(
+        &self,
+        pincode: &str,
+        date: &str,
+        vaccine: Option<&str>,
+    ) -> std::result::Result<CenterResponse, Self::Error> {
+        let calendar = self
+            .get_calendar(CalendarKey::pin(pincode, date, vaccine))
+            .await?;
+        Ok(serde_json::from_str(&calendar.body)?)
+    }
+
+    /// Sessions of a single day are not cached, they are as cheap to fetch as they get.
+    async fn get_sessions_by_district_json(
+        &self,
+        district_id: &str,
+        date: &str,
+        vaccine: Option<&str>,
+    ) -> std::result::Result<SessionResponse, Self::Error> {
+        self.find_centers
+            .get_sessions_by_district_json(district_id, date, vaccine)
+            .await
+            .map_err(|err| CachedFindCentersError::FetchFail(Arc::new(err)))
+    }
+
+    async fn get_sessions_by_pin_json(
+        &self,
+        pincode: &str,
+        date: &str,
+        vaccine: Option<&str>,
+    ) -> std::result::Result<SessionResponse, Self::Error> {
+        self.find_centers
+            .get_sessions_by_pin_json(pincode, date, vaccine)
+            .await
+            .map_err(|err| CachedFindCentersError::FetchFail(Arc::new(err)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use super::{CacheConfig, CachedFindCenters, CalendarKey};
+    use crate::covin::centers::{CenterResponse, FindCenters};
+
+    /// Calendars with the number of the request in the body, each taking a while to
+    /// arrive. Fails every request when `fail` is set.
+    #[derive(Default)]
+    struct CountingFindCenters {
+        requests: Arc<AtomicUsize>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl FindCenters for CountingFindCenters {
+        type Error = io::Error;
+
+        async fn get_all_centers_by_district(
+            &self,
+            district_id: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<String, Self::Error> {
+            let request = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if self.fail {
+                return Err(io::Error::new(io::ErrorKind::Other, "mock failure"));
+            }
+            Ok(format!("{}#{}", district_id, request))
+        }
+
+        async fn get_all_centers_by_district_json(
+            &self,
+            _district_id: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<CenterResponse, Self::Error> {
+            unimplemented!()
+        }
+
+        async fn get_all_centers_by_pin(
+            &self,
+            pincode: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<String, Self::Error> {
+            let request = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(format!("pin {}#{}", pincode, request))
+        }
+
+        async fn get_all_centers_by_pin_json(
+            &self,
+            _pincode: &str,
+            _date: &str,
+            _vaccine: Option<&str>,
+        ) -> std::result::Result<CenterResponse, Self::Error> {
+            unimplemented!()
+        }
+    }
+
+    fn cached_find_centers(
+        ttl_ms: u64,
+        stale_ttl_ms: u64,
+    ) -> (CachedFindCenters<CountingFindCenters>, Arc<AtomicUsize>) {
+        let find_centers = CountingFindCenters::default();
+        let requests = find_centers.requests.clone();
+        let cached = CachedFindCenters::new(find_centers).with_config(CacheConfig {
+            ttl: Duration::from_millis(ttl_ms),
+            stale_ttl: Duration::from_millis(stale_ttl_ms),
+        });
+        (cached, requests)
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_requests() {
+        let (cached, requests) = cached_find_centers(60_000, 0);
+        let (first, second, other) = tokio::join!(
+            cached.get_all_centers_by_district("1", "14-05-2021", None),
+            cached.get_all_centers_by_district("1", "14-05-2021", None),
+            cached.get_all_centers_by_district("1", "14-05-2021", Some("COVAXIN")),
+        );
+        assert_eq!(first.unwrap(), "1#1");
+        assert_eq!(second.unwrap(), "1#1");
+        assert!(other.is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Served from the cache from now on, pincodes are cached apart from the districts
+        let calendar = cached
+            .get_calendar(CalendarKey::district("1", "14-05-2021", None))
+            .await
+            .unwrap();
+        assert_eq!(&*calendar.body, "1#1");
+        assert!(cached
+            .cache_control(&calendar)
+            .starts_with("public, max-age=59"));
+        let pin = cached
+            .get_all_centers_by_pin("1", "14-05-2021", None)
+            .await
+            .unwrap();
+        assert_eq!(pin, "pin 1#3");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_stale_while_revalidate() {
+        let (cached, requests) = cached_find_centers(50, 60_000);
+        let get = || cached.get_all_centers_by_district("1", "14-05-2021", None);
+        assert_eq!(get().await.unwrap(), "1#1");
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        // Stale calendar is served right away, while the next one is on its way
+        assert_eq!(get().await.unwrap(), "1#1");
+        assert_eq!(get().await.unwrap(), "1#1");
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(get().await.unwrap(), "1#2");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Without a stale window, an expired calendar is fetched before it is served
+        let (cached, _requests) = cached_find_centers(50, 0);
+        let get = || cached.get_all_centers_by_district("1", "14-05-2021", None);
+        assert_eq!(get().await.unwrap(), "1#1");
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(get().await.unwrap(), "1#2");
+    }
+
+    #[tokio::test]
+    async fn test_errors_not_cached() {
+        let find_centers = CountingFindCenters {
+            fail: true,
+            ..Default::default()
+        };
+        let requests = find_centers.requests.clone();
+        let cached = CachedFindCenters::new(find_centers);
+        let (first, second) = tokio::join!(
+            cached.get_all_centers_by_district("1", "14-05-2021", None),
+            cached.get_all_centers_by_district("1", "14-05-2021", None),
+        );
+        assert_eq!(first.unwrap_err().to_string(), "mock failure");
+        assert!(second.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(cached
+            .get_all_centers_by_district("1", "14-05-2021", None)
+            .await
+            .is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
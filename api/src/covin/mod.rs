@@ -1,2 +1,6 @@
 pub mod centers;
+pub mod centers_cache;
 pub mod districts;
+pub mod sessions;
+pub mod states;
+pub mod upstream;
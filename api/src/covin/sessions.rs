@@ -0,0 +1,75 @@
+use crate::common::problem;
+use serde::Deserialize;
+use warp::Filter;
+
+use super::centers::{FindCenters, FIND_CENTERS};
+
+pub fn routes() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let by_district = warp::path::end()
+        .and(warp::query::<SessionQueryParams>())
+        .and_then(
+            |SessionQueryParams {
+                 district_id,
+                 date,
+                 vaccine,
+             }| async move {
+                let sessions = FIND_CENTERS
+                    .get_sessions_by_district_json(&district_id, &date, vaccine.as_deref())
+                    .await
+                    .map_err(problem::build)?;
+                tracing::info!(
+                    target: "covin::proxy",
+                    message = "vaccination sessions",
+                    %date,
+                    %district_id,
+                    vaccine = vaccine.as_deref().unwrap_or("*"),
+                    sessions = sessions.sessions.len()
+                );
+                Ok::<_, warp::reject::Rejection>(warp::reply::json(&sessions))
+            },
+        );
+
+    let by_pin = warp::path("pin")
+        .and(warp::path::end())
+        .and(warp::query::<SessionByPinQueryParams>())
+        .and_then(
+            |SessionByPinQueryParams {
+                 pincode,
+                 date,
+                 vaccine,
+             }| async move {
+                let sessions = FIND_CENTERS
+                    .get_sessions_by_pin_json(&pincode, &date, vaccine.as_deref())
+                    .await
+                    .map_err(problem::build)?;
+                tracing::info!(
+                    target: "covin::proxy",
+                    message = "vaccination sessions by pincode",
+                    %date,
+                    %pincode,
+                    vaccine = vaccine.as_deref().unwrap_or("*"),
+                    sessions = sessions.sessions.len()
+                );
+                Ok::<_, warp::reject::Rejection>(warp::reply::json(&sessions))
+            },
+        );
+
+    warp::path("sessions")
+        .and(warp::get())
+        .and(by_district.or(by_pin))
+        .with(warp::trace::named("sessions"))
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionQueryParams {
+    pub district_id: String,
+    pub date: String,
+    pub vaccine: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionByPinQueryParams {
+    pub pincode: String,
+    pub date: String,
+    pub vaccine: Option<String>,
+}
@@ -0,0 +1,755 @@
+use std::{env, sync::Arc, time::Duration};
+
+use crate::common::problem;
+use once_cell::sync::OnceCell;
+pub use service::{
+    CachedLocations, District, DistrictResponse, FindLocations, LocationIndex, LocationSnapshot,
+    LocationSnapshots, LocationsError, S3LocationSnapshots, State, StateResponse,
+};
+use warp::Filter;
+
+use super::centers::{CovinFindCenters, FindCentersError};
+
+/// Alert writes wait this long for the index on a cold instance, unless set by
+/// `LOCATIONS_WAIT_MS`.
+const LOCATIONS_WAIT: Duration = Duration::from_millis(1000);
+
+/// States and districts cached across the requests served by this instance. Loaded as
+/// the instance starts, from the snapshot in `LOCATIONS_SNAPSHOT_BUCKET` when there is
+/// one, and refreshed in the background once stale.
+static LOCATIONS: OnceCell<CachedLocations<CovinFindCenters>> = OnceCell::new();
+
+fn locations() -> &'static CachedLocations<CovinFindCenters> {
+    LOCATIONS.get_or_init(|| {
+        let ttl = env::var("LOCATIONS_TTL_SECS")
+            .ok()
+            .and_then(|ttl| ttl.parse().ok())
+            .map(Duration::from_secs);
+        let mut locations = CachedLocations::new(CovinFindCenters::new());
+        if let Some(ttl) = ttl {
+            locations = locations.with_ttl(ttl);
+        }
+        if let Ok(bucket) = env::var("LOCATIONS_SNAPSHOT_BUCKET") {
+            locations = locations.with_snapshots(S3LocationSnapshots::new(&bucket));
+        }
+        locations
+    })
+}
+
+/// Starts loading the states and districts, without waiting on them.
+pub fn warm() {
+    locations().refresh();
+}
+
+pub fn routes() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let states = warp::path::end().and_then(|| async {
+        let index = locations().index().await.map_err(problem::build)?;
+        Ok::<_, warp::Rejection>(warp::reply::json(&StateResponse {
+            states: index.states().to_vec(),
+        }))
+    });
+
+    let districts = warp::path::param::<u32>()
+        .and(warp::path("districts"))
+        .and(warp::path::end())
+        .and_then(|state_id: u32| async move {
+            let index = locations().index().await.map_err(problem::build)?;
+            let districts = index
+                .districts(state_id)
+                .ok_or_else(warp::reject::not_found)?;
+            Ok::<_, warp::Rejection>(warp::reply::json(&DistrictResponse {
+                districts: districts.to_vec(),
+            }))
+        });
+
+    warp::path("states")
+        .and(warp::get())
+        .and(states.or(districts))
+        .with(warp::trace::named("states"))
+}
+
+/// Index of the states and districts to check districts against, never loaded from CoWIN
+/// within the request. A stale index is served while a fresh one loads in the background.
+/// Without any index yet, waits up to `LOCATIONS_WAIT_MS` for the load in flight and then
+/// rejects with 503, rather than letting unknown districts through.
+pub fn with_location_index(
+) -> impl Filter<Extract = (Arc<LocationIndex>,), Error = warp::Rejection> + Clone {
+    let wait = env::var("LOCATIONS_WAIT_MS")
+        .ok()
+        .and_then(|wait| wait.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(LOCATIONS_WAIT);
+    warp::any().and_then(move || async move {
+        let locations = locations();
+        locations.refresh();
+        match locations.wait_for_index(wait).await {
+            Some(index) => Ok(index),
+            None => {
+                let retry_in = locations.retry_in().unwrap_or(wait);
+                Err(problem::build(
+                    LocationsError::<FindCentersError>::Unavailable(retry_in),
+                ))
+            }
+        }
+    })
+}
+
+mod service {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex, RwLock},
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use futures::{stream, StreamExt, TryStreamExt};
+    use rusoto_core::RusotoError;
+    use rusoto_s3::{GetObjectError, GetObjectRequest, PutObjectRequest, S3Client, S3};
+    use serde::{Deserialize, Serialize};
+    use thiserror::Error;
+
+    use crate::covin::centers::{CovinFindCenters, FindCentersError};
+
+    /// CoWIN asks for its states and districts to be cached for a day.
+    const LOCATIONS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    /// Districts of this many states are fetched at a time.
+    const LOAD_CONCURRENCY: usize = 4;
+    /// CoWIN is left alone this long after failing to load, doubling with every failure.
+    const LOAD_BACKOFF_MIN: Duration = Duration::from_secs(30);
+    const LOAD_BACKOFF_MAX: Duration = Duration::from_secs(15 * 60);
+    /// Requests waiting for the index check on it this often.
+    const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+    const LOCATIONS_SNAPSHOT_KEY: &str = "locations.json";
+
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct State {
+        pub state_id: u32,
+        pub state_name: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct StateResponse {
+        pub states: Vec<State>,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct District {
+        pub district_id: u32,
+        pub district_name: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct DistrictResponse {
+        pub districts: Vec<District>,
+    }
+
+    #[async_trait]
+    pub trait FindLocations {
+        type Error: std::error::Error + Sync + Send + 'static;
+
+        async fn get_states(&self) -> std::result::Result<StateResponse, Self::Error>;
+
+        async fn get_districts(
+            &self,
+            state_id: u32,
+        ) -> std::result::Result<DistrictResponse, Self::Error>;
+    }
+
+    #[async_trait]
+    impl FindLocations for CovinFindCenters {
+        type Error = FindCentersError;
+
+        #[tracing::instrument(skip(self))]
+        async fn get_states(&self) -> std::result::Result<StateResponse, Self::Error> {
+            Ok(self.get_location_base("states").await?.json().await?)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn get_districts(
+            &self,
+            state_id: u32,
+        ) -> std::result::Result<DistrictResponse, Self::Error> {
+            Ok(self
+                .get_location_base(&format!("districts/{}", state_id))
+                .await?
+                .json()
+                .await?)
+        }
+    }
+
+    /// States of CoWIN along with their districts.
+    #[derive(Debug)]
+    pub struct LocationIndex {
+        states: Vec<State>,
+        /// Districts of each state, by `state_id`.
+        districts: HashMap<u32, Vec<District>>,
+        /// State of each district, by `district_id`.
+        district_states: HashMap<u32, u32>,
+        loaded_at: DateTime<Utc>,
+    }
+
+    impl LocationIndex {
+        pub fn new(states: Vec<State>, districts: Vec<(u32, Vec<District>)>) -> Self {
+            Self::loaded_at(states, districts, Utc::now())
+        }
+
+        fn loaded_at(
+            states: Vec<State>,
+            districts: Vec<(u32, Vec<District>)>,
+            loaded_at: DateTime<Utc>,
+        ) -> Self {
+            let district_states = districts
+                .iter()
+                .flat_map(|(state_id, districts)| {
+                    districts
+                        .iter()
+                        .map(move |district| (district.district_id, *state_id))
+                })
+                .collect();
+            Self {
+                states,
+                districts: districts.into_iter().collect(),
+                district_states,
+                loaded_at,
+            }
+        }
+
+        pub fn states(&self) -> &[State] {
+            &self.states
+        }
+
+        /// Districts of the state, `None` when there is no such state.
+        pub fn districts(&self, state_id: u32) -> Option<&[District]> {
+            self.districts.get(&state_id).map(Vec::as_slice)
+        }
+
+        pub fn contains_district(&self, district_id: u32) -> bool {
+            self.district_states.contains_key(&district_id)
+        }
+
+        /// Time since the index was loaded from CoWIN, by any instance.
+        pub fn age(&self) -> Duration {
+            (Utc::now() - self.loaded_at).to_std().unwrap_or_default()
+        }
+    }
+
+    /// Index as stored in a snapshot, keeping the time it was loaded from CoWIN.
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    pub struct LocationSnapshot {
+        pub states: Vec<State>,
+        pub districts: Vec<(u32, Vec<District>)>,
+        pub loaded_at: DateTime<Utc>,
+    }
+
+    impl From<&LocationIndex> for LocationSnapshot {
+        fn from(index: &LocationIndex) -> Self {
+            let mut districts = index
+                .districts
+                .iter()
+                .map(|(state_id, districts)| (*state_id, districts.clone()))
+                .collect::<Vec<_>>();
+            districts.sort_by_key(|(state_id, _)| *state_id);
+            Self {
+                states: index.states.clone(),
+                districts,
+                loaded_at: index.loaded_at,
+            }
+        }
+    }
+
+    impl From<LocationSnapshot> for LocationIndex {
+        fn from(snapshot: LocationSnapshot) -> Self {
+            LocationIndex::loaded_at(snapshot.states, snapshot.districts, snapshot.loaded_at)
+        }
+    }
+
+    /// Snapshots of the index shared by the instances, so that a cold instance has the
+    /// index without loading it from CoWIN.
+    #[async_trait]
+    pub trait LocationSnapshots: Send + Sync {
+        /// Snapshot stored last, `None` when there is none yet.
+        async fn load(&self) -> anyhow::Result<Option<LocationSnapshot>>;
+
+        async fn store(&self, snapshot: &LocationSnapshot) -> anyhow::Result<()>;
+    }
+
+    pub struct S3LocationSnapshots {
+        s3_client: S3Client,
+        bucket: String,
+    }
+
+    impl S3LocationSnapshots {
+        pub fn new(bucket: &str) -> Self {
+            Self {
+                s3_client: S3Client::new(rusoto_core::Region::ApSouth1),
+                bucket: bucket.to_string(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl LocationSnapshots for S3LocationSnapshots {
+        #[tracing::instrument(level = "debug", skip(self))]
+        async fn load(&self) -> anyhow::Result<Option<LocationSnapshot>> {
+            let resp = match self
+                .s3_client
+                .get_object(GetObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: LOCATIONS_SNAPSHOT_KEY.to_string(),
+                    ..Default::default()
+                })
+                .await
+            {
+                Ok(resp) => resp,
+                Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            let body = match resp.body {
+                Some(body) => body.map_ok(|b| b.to_vec()).try_concat().await?,
+                None => return Ok(None),
+            };
+            Ok(Some(serde_json::from_slice(&body)?))
+        }
+
+        #[tracing::instrument(level = "debug", skip(self, snapshot))]
+        async fn store(&self, snapshot: &LocationSnapshot) -> anyhow::Result<()> {
+            self.s3_client
+                .put_object(PutObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: LOCATIONS_SNAPSHOT_KEY.to_string(),
+                    body: Some(serde_json::to_vec(snapshot)?.into()),
+                    content_type: Some("application/json".to_string()),
+                    ..Default::default()
+                })
+                .await?;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Error)]
+    pub enum LocationsError<E>
+    where
+        E: std::error::Error + 'static,
+    {
+        #[error("unable to load the states and districts")]
+        LoadFail(#[source] E),
+        #[error("states and districts not loaded, retry in {0:?}")]
+        Unavailable(Duration),
+    }
+
+    /// Index of the states and districts, loaded once and again after every `ttl`.
+    pub struct CachedLocations<F> {
+        find_locations: F,
+        snapshots: Option<Box<dyn LocationSnapshots>>,
+        ttl: Duration,
+        index: RwLock<Option<Arc<LocationIndex>>>,
+        /// Held while loading, so that the requests arriving meanwhile wait on the same load.
+        loading: tokio::sync::Mutex<()>,
+        /// Loads failed in a row, and the time before which CoWIN is not asked again.
+        backoff: Mutex<(u32, Option<Instant>)>,
+    }
+
+    impl<F> CachedLocations<F>
+    where
+        F: FindLocations + Sync,
+    {
+        pub fn new(find_locations: F) -> Self {
+            Self {
+                find_locations,
+                snapshots: None,
+                ttl: LOCATIONS_TTL,
+                index: RwLock::new(None),
+                loading: tokio::sync::Mutex::new(()),
+                backoff: Mutex::new((0, None)),
+            }
+        }
+
+        pub fn with_ttl(self, ttl: Duration) -> Self {
+            Self { ttl, ..self }
+        }
+
+        pub fn with_snapshots(self, snapshots: impl LocationSnapshots + 'static) -> Self {
+            Self {
+                snapshots: Some(Box::new(snapshots)),
+                ..self
+            }
+        }
+
+        /// Index loaded last, however old it is.
+        pub fn cached(&self) -> Option<Arc<LocationIndex>> {
+            self.index.read().unwrap().clone()
+        }
+
+        fn fresh(&self) -> Option<Arc<LocationIndex>> {
+            self.cached().filter(|index| index.age() < self.ttl)
+        }
+
+        /// Time left before CoWIN is asked again after failing to load, `None` when it
+        /// may be asked now.
+        pub fn retry_in(&self) -> Option<Duration> {
+            let (_, retry_at) = *self.backoff.lock().unwrap();
+            retry_at.and_then(|retry_at| retry_at.checked_duration_since(Instant::now()))
+        }
+
+        /// Index of the states and districts, loaded again once older than the `ttl`.
+        /// The old index is served for as long as CoWIN fails to load a new one, and
+        /// CoWIN is left alone for a while after every failure.
+        pub async fn index(&self) -> Result<Arc<LocationIndex>, LocationsError<F::Error>> {
+            if let Some(index) = self.fresh() {
+                return Ok(index);
+            }
+            let _loading = self.loading.lock().await;
+            // Loaded while waiting on the lock
+            if let Some(index) = self.fresh() {
+                return Ok(index);
+            }
+            if let Some(retry_in) = self.retry_in() {
+                return self.cached().ok_or(LocationsError::Unavailable(retry_in));
+            }
+            // A cold instance starts from the snapshot stored by the others, if fresh enough
+            if self.cached().is_none() {
+                if let Some(index) = self.load_snapshot().await {
+                    let index = self.set_index(index);
+                    if index.age() < self.ttl {
+                        return Ok(index);
+                    }
+                }
+            }
+            match self.load().await {
+                Ok(index) => {
+                    *self.backoff.lock().unwrap() = (0, None);
+                    self.store_snapshot(&index).await;
+                    Ok(self.set_index(index))
+                }
+                Err(err) => {
+                    let retry_in = self.back_off();
+                    match self.cached() {
+                        Some(index) => {
+                            tracing::warn!(message = "Unable to reload the districts", error = ?err, ?retry_in);
+                            Ok(index)
+                        }
+                        None => Err(LocationsError::LoadFail(err)),
+                    }
+                }
+            }
+        }
+
+        /// Loads the index in the background, unless it is fresh, loading already or
+        /// backing off.
+        pub fn refresh(&'static self)
+        where
+            F: Send,
+        {
+            if self.fresh().is_some() || self.retry_in().is_some() {
+                return;
+            }
+            if self.loading.try_lock().is_err() {
+                return;
+            }
+            tokio::spawn(async move {
+                if let Err(err) = self.index().await {
+                    tracing::warn!(message = "Unable to load the districts", error = ?err);
+                }
+            });
+        }
+
+        /// Index loaded last, waiting up to `timeout` for one when none is loaded yet.
+        /// Never loads the index itself, and gives up early while backing off.
+        pub async fn wait_for_index(&self, timeout: Duration) -> Option<Arc<LocationIndex>> {
+            let waiting_since = Instant::now();
+            loop {
+                if let Some(index) = self.cached() {
+                    return Some(index);
+                }
+                if waiting_since.elapsed() >= timeout || self.retry_in().is_some() {
+                    return None;
+                }
+                tokio::time::sleep(WAIT_INTERVAL).await;
+            }
+        }
+
+        fn set_index(&self, index: LocationIndex) -> Arc<LocationIndex> {
+            let index = Arc::new(index);
+            *self.index.write().unwrap() = Some(index.clone());
+            index
+        }
+
+        /// Leaves CoWIN alone for twice as long as after the previous failure.
+        fn back_off(&self) -> Duration {
+            let mut backoff = self.backoff.lock().unwrap();
+            let (failures, _) = *backoff;
+            let retry_in = (LOAD_BACKOFF_MIN * 2_u32.pow(failures.min(10))).min(LOAD_BACKOFF_MAX);
+            *backoff = (failures + 1, Some(Instant::now() + retry_in));
+            retry_in
+        }
+
+        async fn load_snapshot(&self) -> Option<LocationIndex> {
+            match self.snapshots.as_ref()?.load().await {
+                Ok(snapshot) => snapshot.map(LocationIndex::from),
+                Err(err) => {
+                    tracing::warn!(message = "Unable to load the districts snapshot", error = ?err);
+                    None
+                }
+            }
+        }
+
+        async fn store_snapshot(&self, index: &LocationIndex) {
+            if let Some(snapshots) = &self.snapshots {
+                if let Err(err) = snapshots.store(&LocationSnapshot::from(index)).await {
+                    tracing::warn!(message = "Unable to store the districts snapshot", error = ?err);
+                }
+            }
+        }
+
+        async fn load(&self) -> Result<LocationIndex, F::Error> {
+            let StateResponse { states } = self.find_locations.get_states().await?;
+            let state_ids = states
+                .iter()
+                .map(|state| state.state_id)
+                .collect::<Vec<_>>();
+            let districts = stream::iter(state_ids)
+                .map(|state_id| self.load_districts(state_id))
+                .buffered(LOAD_CONCURRENCY)
+                .try_collect::<Vec<_>>()
+                .await?;
+            Ok(LocationIndex::new(states, districts))
+        }
+
+        async fn load_districts(&self, state_id: u32) -> Result<(u32, Vec<District>), F::Error> {
+            let DistrictResponse { districts } =
+                self.find_locations.get_districts(state_id).await?;
+            Ok((state_id, districts))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use super::{
+        CachedLocations, District, DistrictResponse, FindLocations, LocationSnapshot,
+        LocationSnapshots, LocationsError, State, StateResponse,
+    };
+
+    /// Two states with a couple of districts each, failing once `fail` is set.
+    #[derive(Default, Clone)]
+    struct MockLocations {
+        calls: Arc<AtomicUsize>,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl MockLocations {
+        fn check(&self) -> io::Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(io::Error::new(io::ErrorKind::Other, "mock fetch failure"));
+            }
+            Ok(())
+        }
+    }
+
+    /// Snapshots kept in memory, shared by its clones.
+    #[derive(Default, Clone)]
+    struct MockSnapshots {
+        snapshot: Arc<Mutex<Option<LocationSnapshot>>>,
+    }
+
+    #[async_trait]
+    impl LocationSnapshots for MockSnapshots {
+        async fn load(&self) -> anyhow::Result<Option<LocationSnapshot>> {
+            Ok(self.snapshot.lock().unwrap().clone())
+        }
+
+        async fn store(&self, snapshot: &LocationSnapshot) -> anyhow::Result<()> {
+            *self.snapshot.lock().unwrap() = Some(snapshot.clone());
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl FindLocations for MockLocations {
+        type Error = io::Error;
+
+        async fn get_states(&self) -> std::result::Result<StateResponse, Self::Error> {
+            self.check()?;
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(StateResponse {
+                states: vec![
+                    State {
+                        state_id: 17,
+                        state_name: "Kerala".to_string(),
+                    },
+                    State {
+                        state_id: 31,
+                        state_name: "Tamil Nadu".to_string(),
+                    },
+                ],
+            })
+        }
+
+        async fn get_districts(
+            &self,
+            state_id: u32,
+        ) -> std::result::Result<DistrictResponse, Self::Error> {
+            self.check()?;
+            let district = |district_id: u32, district_name: &str| District {
+                district_id,
+                district_name: district_name.to_string(),
+            };
+            let districts = match state_id {
+                17 => vec![
+                    district(307, "Ernakulam"),
+                    district(296, "Thiruvananthapuram"),
+                ],
+                _ => vec![district(571, "Chennai"), district(539, "Coimbatore")],
+            };
+            Ok(DistrictResponse { districts })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_location_index() {
+        let mock = MockLocations::default();
+        let locations = CachedLocations::new(mock.clone());
+        assert!(locations.cached().is_none());
+
+        // Requests arriving together wait on a single load
+        let (index, other) = futures::join!(locations.index(), locations.index());
+        let index = index.unwrap();
+        assert!(other.is_ok());
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 3);
+
+        assert_eq!(
+            index
+                .states()
+                .iter()
+                .map(|state| state.state_name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Kerala", "Tamil Nadu"]
+        );
+        assert_eq!(
+            index
+                .districts(31)
+                .unwrap()
+                .iter()
+                .map(|district| district.district_id)
+                .collect::<Vec<_>>(),
+            vec![571, 539]
+        );
+        assert!(index.districts(1).is_none());
+        assert!(index.contains_district(307));
+        assert!(!index.contains_district(1));
+    }
+
+    #[tokio::test]
+    async fn test_location_index_stale_on_failure() {
+        let mock = MockLocations::default();
+        let locations = CachedLocations::new(mock.clone()).with_ttl(Duration::from_secs(0));
+        assert!(locations.index().await.is_ok());
+
+        // The index loaded last is served while CoWIN fails
+        mock.fail.store(true, Ordering::SeqCst);
+        let index = locations.index().await.unwrap();
+        assert!(index.contains_district(571));
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 4);
+
+        // Without an index to fall back on, the failure is passed on
+        let locations = CachedLocations::new(mock);
+        assert!(locations.index().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_location_index_backoff() {
+        let mock = MockLocations::default();
+        let locations = CachedLocations::new(mock.clone()).with_ttl(Duration::from_secs(0));
+        assert!(locations.index().await.is_ok());
+        assert!(locations.retry_in().is_none());
+
+        // CoWIN is left alone for a while once it fails
+        mock.fail.store(true, Ordering::SeqCst);
+        assert!(locations.index().await.is_ok());
+        assert!(locations.index().await.is_ok());
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 4);
+        assert!(locations.retry_in().unwrap() > Duration::from_secs(20));
+
+        let locations = CachedLocations::new(mock.clone());
+        assert!(matches!(
+            locations.index().await,
+            Err(LocationsError::LoadFail(_))
+        ));
+        assert!(matches!(
+            locations.index().await,
+            Err(LocationsError::Unavailable(_))
+        ));
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_location_index_snapshot() {
+        let snapshots = MockSnapshots::default();
+        let mock = MockLocations::default();
+        let locations = CachedLocations::new(mock.clone()).with_snapshots(snapshots.clone());
+        let index = locations.index().await.unwrap();
+        assert_eq!(
+            *snapshots.snapshot.lock().unwrap(),
+            Some(LocationSnapshot::from(index.as_ref()))
+        );
+
+        // Another instance starts from the snapshot, without asking CoWIN
+        let other = MockLocations::default();
+        let locations = CachedLocations::new(other.clone()).with_snapshots(snapshots.clone());
+        let index = locations.index().await.unwrap();
+        assert!(index.contains_district(539));
+        assert_eq!(index.districts(17).unwrap().len(), 2);
+        assert_eq!(other.calls.load(Ordering::SeqCst), 0);
+
+        // A stale snapshot is served while CoWIN fails to load a fresh index
+        other.fail.store(true, Ordering::SeqCst);
+        let locations = CachedLocations::new(other.clone())
+            .with_ttl(Duration::from_secs(0))
+            .with_snapshots(snapshots);
+        assert!(locations.index().await.unwrap().contains_district(307));
+        assert_eq!(other.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_location_index_wait() {
+        let mock = MockLocations::default();
+        let locations: &'static _ = Box::leak(Box::new(CachedLocations::new(mock.clone())));
+        assert!(locations
+            .wait_for_index(Duration::from_millis(0))
+            .await
+            .is_none());
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 0);
+
+        // Loaded in the background, once
+        locations.refresh();
+        locations.refresh();
+        let index = locations.wait_for_index(Duration::from_secs(5)).await;
+        assert!(index.unwrap().contains_district(571));
+        locations.refresh();
+        assert_eq!(mock.calls.load(Ordering::SeqCst), 3);
+
+        // Waiting gives up as soon as loading fails
+        let mock = MockLocations::default();
+        mock.fail.store(true, Ordering::SeqCst);
+        let locations: &'static _ = Box::leak(Box::new(CachedLocations::new(mock.clone())));
+        locations.refresh();
+        assert!(locations
+            .wait_for_index(Duration::from_secs(5))
+            .await
+            .is_none());
+        assert!(locations.retry_in().is_some());
+    }
+}
@@ -0,0 +1,523 @@
+use std::{
+    collections::hash_map::RandomState,
+    env,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use serde::Serialize;
+
+use super::centers::FindCentersError;
+
+const UPSTREAM_MAX_RETRIES: u32 = 2;
+const UPSTREAM_BASE_BACKOFF: Duration = Duration::from_millis(200);
+const UPSTREAM_MAX_BACKOFF: Duration = Duration::from_secs(5);
+const UPSTREAM_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const UPSTREAM_BREAKER_THRESHOLD: u32 = 5;
+const UPSTREAM_BREAKER_OPEN_FOR: Duration = Duration::from_secs(30);
+
+/// Limits of the requests to CoWIN, on top of the rate limits of the alert engine.
+#[derive(Debug, Clone)]
+pub struct UpstreamConfig {
+    /// Attempts made after the first one fails, for the failures worth a retry.
+    pub max_retries: u32,
+    /// Backoff of the first retry, doubled with every retry up to the `max_backoff`.
+    pub base_backoff: Duration,
+    /// Longest wait before a retry, a `Retry-After` longer than this isn't waited for.
+    pub max_backoff: Duration,
+    pub request_timeout: Duration,
+    /// Consecutive failed attempts opening the circuit breaker.
+    pub breaker_threshold: u32,
+    /// How long an open circuit breaker fails the requests without trying CoWIN.
+    pub breaker_open_for: Duration,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: UPSTREAM_MAX_RETRIES,
+            base_backoff: UPSTREAM_BASE_BACKOFF,
+            max_backoff: UPSTREAM_MAX_BACKOFF,
+            request_timeout: UPSTREAM_REQUEST_TIMEOUT,
+            breaker_threshold: UPSTREAM_BREAKER_THRESHOLD,
+            breaker_open_for: UPSTREAM_BREAKER_OPEN_FOR,
+        }
+    }
+}
+
+impl UpstreamConfig {
+    /// Defaults overridden by the `UPSTREAM_MAX_RETRIES`, `UPSTREAM_BACKOFF_MS`,
+    /// `UPSTREAM_MAX_BACKOFF_MS`, `UPSTREAM_TIMEOUT_MS`, `UPSTREAM_BREAKER_THRESHOLD` and
+    /// `UPSTREAM_BREAKER_OPEN_SECS` env vars.
+    pub fn from_env() -> Self {
+        fn var<T: FromStr>(key: &str) -> Option<T> {
+            env::var(key).ok().and_then(|val| val.parse().ok())
+        }
+        let default = Self::default();
+        Self {
+            max_retries: var("UPSTREAM_MAX_RETRIES").unwrap_or(default.max_retries),
+            base_backoff: var("UPSTREAM_BACKOFF_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.base_backoff),
+            max_backoff: var("UPSTREAM_MAX_BACKOFF_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.max_backoff),
+            request_timeout: var("UPSTREAM_TIMEOUT_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.request_timeout),
+            breaker_threshold: var("UPSTREAM_BREAKER_THRESHOLD")
+                .unwrap_or(default.breaker_threshold),
+            breaker_open_for: var("UPSTREAM_BREAKER_OPEN_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.breaker_open_for),
+        }
+    }
+
+    /// Wait before the retry following the failed `attempt`, counted from zero. Half of it
+    /// is random, so that the clients failed together don't retry together.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_backoff
+            .checked_mul(2_u32.saturating_pow(attempt))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        let jitter = RandomState::new().build_hasher().finish() % 1_000;
+        backoff / 2 + backoff / 2 * jitter as u32 / 1_000
+    }
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Stops trying CoWIN for a while once it failed the `threshold` number of attempts in a
+/// row. Once the while is over the requests go through again, a single failure opens the
+/// breaker again until an attempt succeeds.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    open_for: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, open_for: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            open_for,
+            state: Default::default(),
+        }
+    }
+
+    /// Time left for the breaker to let requests through, when it is open.
+    pub fn open_for(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        state
+            .opened_at
+            .and_then(|opened_at| self.open_for.checked_sub(opened_at.elapsed()))
+            .filter(|open_for| *open_for > Duration::from_secs(0))
+    }
+
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::default();
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.threshold {
+            if state.opened_at.is_none() {
+                tracing::warn!(
+                    message = "Circuit breaker opened",
+                    consecutive_failures = state.consecutive_failures
+                );
+            }
+            state.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// Whether a response with the `status` is worth a retry. CoWIN answers with a 403 when
+/// it is throttling the requests, along with the 429.
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::FORBIDDEN
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// `Retry-After` of the response, in seconds. Dates aren't used by CoWIN.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    resp.headers()
+        .get(RETRY_AFTER)
+        .and_then(|retry_after| retry_after.to_str().ok())
+        .and_then(|retry_after| retry_after.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// Whether the `err` of an upstream may not happen with the next one. Requests CoWIN
+/// rejects as bad are rejected by every upstream.
+fn fails_over(err: &FindCentersError) -> bool {
+    match err {
+        FindCentersError::CircuitOpen(_) => true,
+        FindCentersError::RequestFail(err) => err.status().map(is_retryable).unwrap_or(true),
+        FindCentersError::JsonDeserializeFail(_) => false,
+    }
+}
+
+/// Base URL of CoWIN, along with its health.
+#[derive(Debug)]
+struct Upstream {
+    base_url: String,
+    breaker: CircuitBreaker,
+    served: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+/// Responses served by an upstream, and requests it failed, since the start.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpstreamStats {
+    pub base_url: String,
+    pub served: usize,
+    pub failed: usize,
+    /// Whether the circuit breaker of the upstream lets the requests through.
+    pub healthy: bool,
+}
+
+/// Base URLs of CoWIN in the order of preference, each with its own circuit breaker.
+/// Requests go to the first healthy upstream, and fall over to the next one when it fails.
+#[derive(Debug)]
+pub struct Upstreams(Vec<Upstream>);
+
+impl Upstreams {
+    pub fn new(base_urls: &[String], config: &UpstreamConfig) -> Self {
+        Self(
+            base_urls
+                .iter()
+                .map(|base_url| Upstream {
+                    base_url: base_url.trim_end_matches('/').to_string(),
+                    breaker: CircuitBreaker::new(config.breaker_threshold, config.breaker_open_for),
+                    served: Default::default(),
+                    failed: Default::default(),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn stats(&self) -> Vec<UpstreamStats> {
+        self.0
+            .iter()
+            .map(|upstream| UpstreamStats {
+                base_url: upstream.base_url.clone(),
+                served: upstream.served.load(Ordering::Relaxed),
+                failed: upstream.failed.load(Ordering::Relaxed),
+                healthy: upstream.breaker.open_for().is_none(),
+            })
+            .collect()
+    }
+
+    /// Sends the request made by `send` for the base URL of an upstream, with the retries
+    /// as per the `config`, to each of the upstreams in turn until one of them serves it.
+    /// Fails with `CircuitOpen` when every upstream is open.
+    pub async fn send<F, Fut>(
+        &self,
+        config: &UpstreamConfig,
+        send: F,
+    ) -> Result<Response, FindCentersError>
+    where
+        F: Fn(&str) -> Fut,
+        Fut: Future<Output = reqwest::Result<Response>>,
+    {
+        let mut open_for: Option<Duration> = None;
+        let mut failure = None;
+        for upstream in self.0.iter() {
+            let base_url = upstream.base_url.as_str();
+            match send_with_retries(config, &upstream.breaker, || send(base_url)).await {
+                Ok(resp) => {
+                    upstream.served.fetch_add(1, Ordering::Relaxed);
+                    tracing::info!(
+                        target: "covin::upstream",
+                        message = "Served by upstream",
+                        upstream = %base_url,
+                        status = resp.status().as_u16()
+                    );
+                    return Ok(resp);
+                }
+                Err(FindCentersError::CircuitOpen(upstream_open_for)) => {
+                    open_for = Some(open_for.map_or(upstream_open_for, |open_for| {
+                        open_for.min(upstream_open_for)
+                    }));
+                }
+                Err(err) => {
+                    upstream.failed.fetch_add(1, Ordering::Relaxed);
+                    if !fails_over(&err) {
+                        return Err(err);
+                    }
+                    tracing::warn!(
+                        target: "covin::upstream",
+                        message = "Upstream failed, falling over to the next one",
+                        upstream = %base_url,
+                        error = ?err
+                    );
+                    failure = Some(err);
+                }
+            }
+        }
+        Err(failure.unwrap_or_else(|| {
+            FindCentersError::CircuitOpen(open_for.unwrap_or_else(|| Duration::from_secs(0)))
+        }))
+    }
+}
+
+/// Sends the request made by `send` until it succeeds, retrying the timeouts, connection
+/// errors and the responses worth a retry as per the `config`. Requests aren't sent while
+/// the `breaker` is open.
+pub async fn send_with_retries<F, Fut>(
+    config: &UpstreamConfig,
+    breaker: &CircuitBreaker,
+    send: F,
+) -> Result<Response, FindCentersError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = reqwest::Result<Response>>,
+{
+    let mut attempt = 0;
+    loop {
+        if let Some(open_for) = breaker.open_for() {
+            return Err(FindCentersError::CircuitOpen(open_for));
+        }
+        let (err, retry_in) = match send().await {
+            Ok(resp) if resp.status().is_success() || resp.status().is_redirection() => {
+                breaker.record_success();
+                return Ok(resp);
+            }
+            Ok(resp) if is_retryable(resp.status()) => {
+                breaker.record_failure();
+                let retry_in = retry_after(&resp).unwrap_or_else(|| config.backoff(attempt));
+                (resp.error_for_status().unwrap_err(), retry_in)
+            }
+            // Rest of the client errors are down to the request, not to CoWIN
+            Ok(resp) => return resp.error_for_status().map_err(Into::into),
+            Err(err) if err.is_timeout() || err.is_connect() || err.is_request() => {
+                breaker.record_failure();
+                (err, config.backoff(attempt))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if attempt >= config.max_retries || retry_in > config.max_backoff {
+            return Err(err.into());
+        }
+        tracing::debug!(message = "Retrying CoWIN request", attempt, ?retry_in, error = ?err);
+        tokio::time::sleep(retry_in).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use reqwest::Response;
+    use warp::http;
+
+    use super::{send_with_retries, CircuitBreaker, UpstreamConfig, UpstreamStats, Upstreams};
+    use crate::covin::centers::FindCentersError;
+
+    fn config() -> UpstreamConfig {
+        UpstreamConfig {
+            max_retries: 2,
+            base_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(100),
+            request_timeout: Duration::from_secs(1),
+            breaker_threshold: 3,
+            breaker_open_for: Duration::from_secs(60),
+        }
+    }
+
+    fn response(status: u16, retry_after: Option<&str>) -> Response {
+        let mut builder = http::Response::builder().status(status);
+        if let Some(retry_after) = retry_after {
+            builder = builder.header("Retry-After", retry_after);
+        }
+        Response::from(builder.body("{}").unwrap())
+    }
+
+    #[test]
+    fn test_backoff() {
+        let config = UpstreamConfig {
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1_000),
+            ..config()
+        };
+        for (attempt, full) in [
+            (0, 100),
+            (1, 200),
+            (2, 400),
+            (3, 800),
+            (4, 1_000),
+            (40, 1_000),
+        ]
+        .iter()
+        .copied()
+        {
+            let backoff = config.backoff(attempt);
+            assert!(backoff >= Duration::from_millis(full / 2), "{:?}", backoff);
+            assert!(backoff <= Duration::from_millis(full), "{:?}", backoff);
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        breaker.record_failure();
+        assert_eq!(breaker.open_for(), None);
+        breaker.record_success();
+        breaker.record_failure();
+        assert_eq!(breaker.open_for(), None);
+        breaker.record_failure();
+        assert!(breaker.open_for().is_some());
+
+        // Half open once the while is over, a failure opens it right away
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(breaker.open_for(), None);
+        breaker.record_failure();
+        assert!(breaker.open_for().is_some());
+        breaker.record_success();
+        assert_eq!(breaker.open_for(), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retries() {
+        let config = config();
+        let breaker = CircuitBreaker::new(10, config.breaker_open_for);
+        let attempts = &AtomicUsize::new(0);
+        let resp = send_with_retries(&config, &breaker, move || async move {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(response(503, None)),
+                1 => Ok(response(429, Some("0"))),
+                _ => Ok(response(200, None)),
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Out of retries, or asked to come back later than the longest backoff
+        let attempts = &AtomicUsize::new(0);
+        let res = send_with_retries(&config, &breaker, move || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Ok(response(403, None))
+        })
+        .await;
+        assert!(matches!(res, Err(FindCentersError::RequestFail(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        let attempts = &AtomicUsize::new(0);
+        let res = send_with_retries(&config, &breaker, move || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Ok(response(429, Some("120")))
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // Bad requests aren't retried
+        let attempts = &AtomicUsize::new(0);
+        let res = send_with_retries(&config, &breaker, move || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Ok(response(400, None))
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_open_breaker() {
+        let config = config();
+        let breaker = CircuitBreaker::new(config.breaker_threshold, config.breaker_open_for);
+        let attempts = &AtomicUsize::new(0);
+        let send = move || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Ok(response(500, None))
+        };
+        let res = send_with_retries(&config, &breaker, send).await;
+        assert!(matches!(res, Err(FindCentersError::RequestFail(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Requests fail without going to CoWIN while the breaker is open
+        let res = send_with_retries(&config, &breaker, send).await;
+        assert!(matches!(res, Err(FindCentersError::CircuitOpen(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_failover() {
+        let config = UpstreamConfig {
+            max_retries: 0,
+            breaker_threshold: 2,
+            ..config()
+        };
+        let upstreams = Upstreams::new(
+            &[
+                "https://cdn.example.com/api/".to_string(),
+                "https://api.example.com/api".to_string(),
+            ],
+            &config,
+        );
+        let cdn_status = &AtomicUsize::new(403);
+        let send = move |base_url: &str| {
+            let status = match base_url {
+                "https://cdn.example.com/api" => cdn_status.load(Ordering::SeqCst) as u16,
+                _ => 200,
+            };
+            async move { Ok(response(status, None)) }
+        };
+        let stats = |cdn_served, cdn_failed, cdn_healthy, api_served| {
+            vec![
+                UpstreamStats {
+                    base_url: "https://cdn.example.com/api".to_string(),
+                    served: cdn_served,
+                    failed: cdn_failed,
+                    healthy: cdn_healthy,
+                },
+                UpstreamStats {
+                    base_url: "https://api.example.com/api".to_string(),
+                    served: api_served,
+                    failed: 0,
+                    healthy: true,
+                },
+            ]
+        };
+
+        // Blocked by the first upstream, served by the next one
+        assert!(upstreams.send(&config, send).await.is_ok());
+        assert_eq!(upstreams.stats(), stats(0, 1, true, 1));
+        assert!(upstreams.send(&config, send).await.is_ok());
+        assert_eq!(upstreams.stats(), stats(0, 2, false, 2));
+
+        // Unhealthy upstream is skipped until its breaker lets requests through again
+        cdn_status.store(200, Ordering::SeqCst);
+        assert!(upstreams.send(&config, send).await.is_ok());
+        assert_eq!(upstreams.stats(), stats(0, 2, false, 3));
+
+        // Bad requests don't fall over
+        let res = upstreams
+            .send(&config, |_base_url: &str| async { Ok(response(400, None)) })
+            .await;
+        assert!(matches!(res, Err(FindCentersError::RequestFail(_))));
+        assert_eq!(upstreams.stats()[1].failed, 1);
+    }
+}
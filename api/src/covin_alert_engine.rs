@@ -1,16 +1,44 @@
+use std::time::Duration;
+
 use anyhow::Error;
 use covin_backend::{
     alert_engine::{
-        email_client::SesEmailClient, exclusion_map::S3ExclusionMap,
-        template_engine::TeraTemplateEngine, AlertEngine,
+        chat_client::TelegramChatClient,
+        clock::SystemClock,
+        dynamo_exclusion_map::DynamoExclusionMap,
+        email_client::SesEmailClient,
+        exclusion_map::{ExclusionMap, ExclusionMapBackend, InMemoryExclusionMap, S3ExclusionMap},
+        fetch::FetchConfig,
+        file_exclusion_map::FileExclusionMap,
+        notifier::{ChatChannel, EmailChannel, Notifier, SmsChannel, WebhookChannel},
+        run_report::RunReport,
+        shard::Shard,
+        sms_client::SnsSmsClient,
+        template_engine::TeraTemplateEngine,
+        webhook_client::ReqwestWebhookClient,
+        AlertEngine,
+    },
+    api::alerts::{AlertService, DeliveryMode},
+    covin::{
+        centers::CovinFindCenters,
+        centers_cache::{CacheConfig, CachedFindCenters},
     },
-    api::alerts::AlertService,
-    covin::centers::CovinFindCenters,
 };
 use lamedh_runtime::{handler_fn, run, Context, Error as LambdaError};
-use serde_json::{json, Value};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::Value;
 use tracing_subscriber::fmt::format::FmtSpan;
 
+/// Calendars cached across the invocations of a warm Lambda. Alerts go out on fresh
+/// calendars only, so stale calendars aren't served to the engine.
+static FIND_CENTERS: Lazy<CachedFindCenters<CovinFindCenters>> = Lazy::new(|| {
+    CachedFindCenters::new(CovinFindCenters::new()).with_config(CacheConfig {
+        stale_ttl: Duration::from_secs(0),
+        ..CacheConfig::from_env()
+    })
+});
+
 #[tokio::main]
 async fn main() -> Result<(), LambdaError> {
     let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_owned());
@@ -35,12 +63,75 @@ async fn main() -> Result<(), LambdaError> {
     Ok(())
 }
 
+/// Payload of the event invoking the alert engine, e.g. `{ "shard": { "index": 0, "count": 4 } }`
+/// to run the first of four shards. Without a shard, every district is processed.
+/// The daily digests are sent by separately scheduled events with `{ "mode": "digest" }`,
+/// for each shard of the realtime runs since the sessions of the digests are held in the
+/// exclusion map of the shard.
+/// Alerts left in the legacy table are alerted from it until migrated, which invoking the
+/// engine with `{ "migrateLegacyAlerts": true }` does for all of them, sending no alert.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AlertEngineEvent {
+    #[serde(default)]
+    shard: Option<Shard>,
+    #[serde(default)]
+    mode: DeliveryMode,
+    #[serde(default)]
+    migrate_legacy_alerts: bool,
+}
+
 #[tracing::instrument(level = "debug", err)]
-async fn func(_event: Value, _: Context) -> Result<Value, Error> {
-    let find_centers = CovinFindCenters::new();
-    let exclusion_map = S3ExclusionMap::init().await;
-    let ses_client = SesEmailClient::new();
-    let tera = TeraTemplateEngine::try_init()?;
+async fn func(event: Value, _: Context) -> Result<Value, Error> {
+    let event = if event.is_null() {
+        AlertEngineEvent::default()
+    } else {
+        serde_json::from_value::<AlertEngineEvent>(event)?
+    };
+    if event.migrate_legacy_alerts {
+        let migrated = AlertService::new().migrate_legacy_alerts().await?;
+        tracing::info!(message = "Migrated legacy alerts", migrated);
+        return Ok(serde_json::json!({ "migrated": migrated }));
+    }
+    let shard = event.shard.unwrap_or_default().validate()?;
+    let mode = event.mode;
+    let report = match ExclusionMapBackend::from_env()? {
+        ExclusionMapBackend::S3 => {
+            let exclusion_map = S3ExclusionMap::init_for_shard(&shard).await;
+            run_engine(exclusion_map, shard, mode).await?
+        }
+        ExclusionMapBackend::DynamoDb => {
+            // Sessions alerted while the map was kept in S3 are imported into the table
+            let exclusion_map = DynamoExclusionMap::new().with_s3_fallback().await;
+            run_engine(exclusion_map, shard, mode).await?
+        }
+        ExclusionMapBackend::File(dir) => {
+            let exclusion_map = FileExclusionMap::init_for_shard(dir, &shard).await?;
+            run_engine(exclusion_map, shard, mode).await?
+        }
+        ExclusionMapBackend::InMemory => {
+            run_engine(InMemoryExclusionMap::new(), shard, mode).await?
+        }
+    };
+    Ok(serde_json::to_value(report)?)
+}
+
+/// Runs the alert engine for the alerts of the `shard` in the delivery `mode`, keeping the
+/// alerts sent in the `exclusion_map`.
+async fn run_engine<Em: ExclusionMap>(
+    exclusion_map: Em,
+    shard: Shard,
+    mode: DeliveryMode,
+) -> Result<RunReport, Error> {
+    let find_centers = FIND_CENTERS.clone();
+    let notifier = Notifier::new()
+        .with_channel(EmailChannel::new(
+            SesEmailClient::new(),
+            TeraTemplateEngine::try_init()?,
+        ))
+        .with_channel(SmsChannel::new(SnsSmsClient::new()))
+        .with_channel(WebhookChannel::new(ReqwestWebhookClient::new()))
+        .with_channel(ChatChannel::new(TelegramChatClient::new()));
     let get_all_alert_configs = || async {
         let alert_service = AlertService::new();
         alert_service.get_all_alert_configs().await
@@ -49,9 +140,11 @@ async fn func(_event: Value, _: Context) -> Result<Value, Error> {
         get_all_alert_configs,
         find_centers,
         exclusion_map,
-        ses_client,
-        tera,
-    );
-    alert_engine.run().await?;
-    Ok(json!({ "message": "Completed!", "status": "ok" }))
+        notifier,
+        SystemClock,
+    )
+    .with_fetch_config(FetchConfig::from_env())
+    .with_shard(shard)
+    .with_delivery(mode);
+    alert_engine.run().await
 }
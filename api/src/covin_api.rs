@@ -1,7 +1,7 @@
 use std::net::SocketAddrV4;
 
 use anyhow::Result;
-use covin_backend::{api::alerts, common::problem};
+use covin_backend::{api::alerts, common::problem, covin::states};
 use tracing_subscriber::fmt::format::FmtSpan;
 use warp::{
     self,
@@ -26,8 +26,11 @@ async fn main() -> Result<()> {
         tracing_builder.init();
     }
 
+    // Districts of the alerts are checked against the states and districts loaded meanwhile
+    states::warm();
+
     let cors = warp::cors()
-        .allow_methods(&[Method::GET, Method::POST, Method::DELETE])
+        .allow_methods(&[Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_header(header::CONTENT_TYPE)
         .allow_header(header::AUTHORIZATION)
         .allow_any_origin()
@@ -3,7 +3,7 @@ use std::net::SocketAddrV4;
 use anyhow::Result;
 use covin_backend::{
     common::problem,
-    covin::{centers, districts},
+    covin::{centers, districts, sessions, states},
 };
 use tracing_subscriber::fmt::format::FmtSpan;
 use warp::{
@@ -29,6 +29,9 @@ async fn main() -> Result<()> {
         tracing_builder.init();
     }
 
+    // States and districts are served from the index loaded meanwhile
+    states::warm();
+
     let cors = warp::cors()
         .allow_methods(&[Method::GET, Method::POST, Method::DELETE])
         .allow_header(header::CONTENT_TYPE)
@@ -37,7 +40,12 @@ async fn main() -> Result<()> {
         .build();
 
     let routes = warp::path("proxy")
-        .and(centers::routes().or(districts::routes()))
+        .and(
+            centers::routes()
+                .or(sessions::routes())
+                .or(states::routes())
+                .or(districts::routes()),
+        )
         .recover(problem::unpack)
         .with(warp::log("covin::proxy"))
         .with(cors)
@@ -1,5 +1,11 @@
 import React from "react";
-import { Alert, CenterDim, District } from "../../services/api";
+import {
+  Alert,
+  CenterDim,
+  District,
+  VaccineType,
+  VaccineTypes,
+} from "../../services/api";
 import { useFormik } from "formik";
 import * as yup from "yup";
 import { useAuth } from "../auth/ProvideAuth";
@@ -33,6 +39,8 @@ export default function AlertsEdit({
           anyCenters: !alert.centers,
           centers: alert.centers ? alert.centers : "",
           age: alert.age ? alert.age : "",
+          vaccine: alert.vaccine ? alert.vaccine : VaccineType.ANY,
+          smsOptIn: !!(alert.channels && alert.channels.sms),
         }
       : {
           districtId: "",
@@ -43,6 +51,8 @@ export default function AlertsEdit({
           mobileNo: phone_number ? phone_number.substr(3, 10) : "",
           age: "",
           dose: "",
+          vaccine: VaccineType.ANY,
+          smsOptIn: false,
         },
     validationSchema: yup.object({
       districtId: yup
@@ -76,12 +86,27 @@ export default function AlertsEdit({
         .string()
         .oneOf(["any", "first", "second"])
         .required("Select a dose availability filter"),
+      vaccine: yup
+        .string()
+        .oneOf(VaccineTypes)
+        .required("Select a vaccine filter"),
     }),
     onSubmit: (values) => {
+      // An update replaces the whole alert, so the fields not on the form are sent
+      // back as they were loaded
       let alertPayload: Alert = {
+        ...alert,
         districtId: Number(values.districtId),
         email: values.email,
         dose: values.dose,
+        vaccine: values.vaccine,
+        channels: {
+          ...(alert && alert.channels),
+          sms: !!values.mobileNo && values.smsOptIn,
+        },
+        centers: undefined,
+        age: undefined,
+        mobileNo: undefined,
       };
 
       if (values.centers && values.centers instanceof Array) {
@@ -300,6 +325,25 @@ export default function AlertsEdit({
                         {formik.errors.mobileNo}
                       </div>
                     ) : null}
+                    <p>
+                      <label
+                        htmlFor="smsOptIn"
+                        className="block text-sm font-medium text-gray-700"
+                      >
+                        <input
+                          type="checkbox"
+                          name="smsOptIn"
+                          id="smsOptIn"
+                          disabled={!formik.values.mobileNo}
+                          onChange={formik.handleChange}
+                          checked={formik.values.smsOptIn}
+                          className="rounded-md"
+                        ></input>
+                        <span className="text-gray-700 m-1 p-1">
+                          Also alert me by SMS
+                        </span>
+                      </label>
+                    </p>
                   </div>
 
                   <div className="col-span-3 sm:col-span-2">
@@ -358,6 +402,34 @@ export default function AlertsEdit({
                       </div>
                     ) : null}
                   </div>
+                  <div className="col-span-6 sm:col-span-3">
+                    <label
+                      htmlFor="vaccine"
+                      className="block text-sm font-medium text-gray-700"
+                    >
+                      Vaccine
+                    </label>
+                    <select
+                      id="vaccine"
+                      name="vaccine"
+                      onChange={formik.handleChange}
+                      value={formik.values.vaccine}
+                      className="mt-1 block w-full py-2 px-3 border border-gray-300 bg-white rounded-md shadow-sm focus:outline-none focus:ring-indigo-500 focus:border-indigo-500 sm:text-sm"
+                    >
+                      {VaccineTypes.map((vaccineType) => (
+                        <option key={vaccineType} value={vaccineType}>
+                          {vaccineType === VaccineType.ANY
+                            ? "Alert for any vaccine"
+                            : `Alert for ${vaccineType} only`}
+                        </option>
+                      ))}
+                    </select>
+                    {formik.touched.vaccine && formik.errors.vaccine ? (
+                      <div className="text-red-700 text-sm m-2 p-2">
+                        {formik.errors.vaccine}
+                      </div>
+                    ) : null}
+                  </div>
                 </div>
 
                 {/* <div className="px-4 py-5 bg-white space-y-6 sm:p-6">
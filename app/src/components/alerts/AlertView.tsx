@@ -90,6 +90,10 @@ export default function AlertView({
             <dt className="text-sm font-medium text-gray-500">Mobile</dt>
             <dd className="mt-1 text-sm text-gray-900 sm:mt-0 sm:col-span-2">
               {alert.mobileNo && alert.mobileNo.substr(3, 10)}
+              {alert.mobileNo &&
+                alert.channels &&
+                alert.channels.sms &&
+                " (SMS alerts enabled)"}
             </dd>
           </div>
           <div className="bg-gray-50 px-4 py-5 sm:grid sm:grid-cols-3 sm:gap-4 sm:px-6">
@@ -104,6 +108,15 @@ export default function AlertView({
               Alert for {alert.dose || "any"} dose
             </dd>
           </div>
+          <div className="bg-gray-50 px-4 py-5 sm:grid sm:grid-cols-3 sm:gap-4 sm:px-6">
+            <dt className="text-sm font-medium text-gray-500">Vaccine</dt>
+            <dd className="mt-1 text-sm text-gray-900 sm:mt-0 sm:col-span-2">
+              Alert for{" "}
+              {alert.vaccine && alert.vaccine !== "ANY"
+                ? alert.vaccine
+                : "any vaccine"}
+            </dd>
+          </div>
         </dl>
       </div>
     </div>
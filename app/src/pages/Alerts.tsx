@@ -54,11 +54,13 @@ export default function Alerts() {
 
   const deleteAlert = () => {
     if (
+      alert &&
+      alert.alertId &&
       window.confirm(
         "Are you sure you want to delete the alert?\nPress 'OK' to delete the alert, 'Cancel' otherwise!"
       )
     ) {
-      removeAlert().then(() => {
+      removeAlert(alert.alertId).then(() => {
         initialize();
         setEditing(false);
       });
@@ -180,48 +180,101 @@ export async function getDistricts(): Promise<District[]> {
   }
 }
 
+export interface ChannelPreferences {
+  email?: boolean;
+  sms?: boolean;
+  webhook?: boolean;
+  chat?: boolean;
+}
+
+export interface GeoFilter {
+  lat: number;
+  long: number;
+  radiusKm: number;
+  nearbyDistricts?: number[];
+}
+
+export interface RenotifyPolicy {
+  trigger: string;
+  minRise?: number;
+  minRisePercent?: number;
+  minIntervalMinutes?: number;
+  maxPerSession?: number;
+}
+
+export interface QuietHours {
+  startHour: number;
+  endHour: number;
+}
+
 export interface Alert {
+  alertId?: string;
+  name?: string;
   centers?: number[];
+  pincodes?: number[];
+  location?: GeoFilter;
   districtId: number;
   email: string;
   mobileNo?: string;
   age?: number;
   dose?: string;
+  vaccine?: string;
+  feeType?: string;
+  maxFee?: number;
+  daysAhead?: number;
+  daysOfWeek?: string;
+  channels?: ChannelPreferences;
+  renotify?: RenotifyPolicy;
+  quietHours?: QuietHours;
+  maxPerHour?: number;
+  maxPerDay?: number;
+  delivery?: string;
+  webhookUrl?: string;
+  webhookSecret?: string;
+  telegramChatId?: string;
 }
 
-export async function getAlert(): Promise<Alert | undefined> {
+export async function getAlerts(): Promise<Alert[]> {
   try {
     const resp = await authFetch(`${API_BASE_URL}/alerts/register`);
-    const json: Alert = await resp.json();
+    const json: Alert[] = await resp.json();
     return json;
   } catch (error) {
-    if (error.status && error.status === 404) {
-      return;
-    }
     console.error("An error occured", error);
     throw Error("An error occured");
   }
 }
 
+export async function getAlert(): Promise<Alert | undefined> {
+  const alerts = await getAlerts();
+  return alerts.length ? alerts[0] : undefined;
+}
+
 export async function createAlert(alert: Alert): Promise<void> {
+  const { alertId } = alert;
   try {
-    await authFetch(`${API_BASE_URL}/alerts/register`, {
-      method: "POST",
-      mode: "cors",
-      headers: {
-        "Content-Type": "application/json",
-      },
-      body: JSON.stringify(alert),
-    });
+    await authFetch(
+      alertId
+        ? `${API_BASE_URL}/alerts/register/${alertId}`
+        : `${API_BASE_URL}/alerts/register`,
+      {
+        method: alertId ? "PUT" : "POST",
+        mode: "cors",
+        headers: {
+          "Content-Type": "application/json",
+        },
+        body: JSON.stringify(alert),
+      }
+    );
   } catch (error) {
     console.error("An error occured", error);
     throw Error("An error occured");
   }
 }
 
-export async function deleteAlert(): Promise<void> {
+export async function deleteAlert(alertId: string): Promise<void> {
   try {
-    await authFetch(`${API_BASE_URL}/alerts/register`, {
+    await authFetch(`${API_BASE_URL}/alerts/register/${alertId}`, {
       method: "DELETE",
       mode: "cors",
     });